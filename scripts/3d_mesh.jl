@@ -3,15 +3,15 @@ using FileIO
 using MeshIO
 using GeometryBasics
 
+include("snapshot.jl")
+
 STATE = "states/energy-4000.bin"
-WIDTH = 1440
-HEIGHT = 900
 
 VALUE_SCALE = 350
 BASE_HEIGHT = 14
 
-raw_state = read(STATE)
-state = reshape(reinterpret(Float32, raw_state[9:end]), (WIDTH, HEIGHT))
+state = load_snapshot(STATE)[1]
+WIDTH, HEIGHT = size(state)
 
 points = GeometryBasics.Point3f[]
 connec = TriangleFace{Int64}[]
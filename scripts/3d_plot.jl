@@ -1,13 +1,12 @@
 using GLMakie
 
+include("snapshot.jl")
+
 STATE_PATH = "states"
-WIDTH = 1440
-HEIGHT = 900
 Z_SCALE = 250
 
 function load_state(name)
-	state = read(STATE_PATH * "/" * name)
-	return reshape(reinterpret(Float32, state[9:end]), (WIDTH, HEIGHT))[:, 5:end]
+	return load_snapshot(STATE_PATH * "/" * name)[1][:, 5:end]
 end
 
 
@@ -29,6 +28,7 @@ GLMakie.activate!()
 GLMakie.closeall()
 
 data = Observable(load_state(states[1]))
+WIDTH, HEIGHT = size(data[])
 
 fig = Figure(resolution = (1920, 1080))
 axis = Axis3(fig[1, 1], aspect = (WIDTH, HEIGHT, Z_SCALE), azimuth = 6.275pi, elevation = 0.16pi, xlabel = "x", ylabel = "y", zlabel = "z")
@@ -1,17 +1,16 @@
 using Images
 using Colors
 
-STATE_PATH = "states"
+include("snapshot.jl")
 
-WIDTH = 1920
-HEIGHT = 1080
+STATE_PATH = "states"
 
 function load_state(name)
-	state = read(STATE_PATH * "/" * name)
-	return reshape(reinterpret(Float32, state[9:end]), (WIDTH, HEIGHT))
+	return load_snapshot(STATE_PATH * "/" * name)[1]
 end
 
-states = readdir(STATE_PATH)
+states = filter(x -> isfile(STATE_PATH * "/" * x), readdir(STATE_PATH))
+WIDTH, HEIGHT = size(load_state(states[1]))
 out = zeros(RGB, WIDTH, HEIGHT)
 
 for state in states
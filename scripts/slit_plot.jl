@@ -5,12 +5,15 @@ using ImageIO
 
 #= CONFIG =#
 
+# Output of a line detector across the screen, recording `energy` to a .csv file
+detector = "screen.csv"
 smooth = 15
 image_width = 100
 image_color = RGB(0, 1, 0)
 # image_color = RGB(0.4549019607843137, 0.1607843137254902, 0.4588235294117647)
 
-data = reinterpret(Float32, read("out.f32"))[2:end-1]
+# The last row holds the time averaged intensity along the line, after the tick and time columns
+data = parse.(Float32, split(last(readlines(detector)), ",")[3:end])
 max_value = sqrt(maximum(data))
 
 for i in eachindex(data)
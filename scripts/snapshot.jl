@@ -0,0 +1,33 @@
+# Reads the snapshots written to `states`, see `Snapshot` in `src/simulation/snapshot.rs` for the layout.
+# Returns the cells indexed by [x, y] along with the header, which is `nothing` for files written before it was added.
+
+const SNAPSHOT_MAGIC = Vector{UInt8}("WAVESNAP")
+
+function load_snapshot(path)
+	bytes = read(path)
+	value(T, offset) = reinterpret(T, bytes[offset+1:offset+sizeof(T)])[1]
+
+	if bytes[1:8] != SNAPSHOT_MAGIC
+		size = (Int(value(UInt32, 0)), Int(value(UInt32, 4)))
+		return reshape(reinterpret(Float32, bytes[9:end]), size), nothing
+	end
+
+	value(UInt8, 60) == 0 || error("Unsupported data type in $(path)")
+	header_length = value(UInt32, 12)
+	config_length = value(UInt16, 62)
+	metadata = (
+		version = value(UInt32, 8),
+		tick = value(UInt64, 24),
+		time = value(Float64, 32),
+		dx = value(Float32, 40),
+		dt = value(Float32, 44),
+		v = value(Float32, 48),
+		frequency = value(Float32, 52),
+		amplitude = value(Float32, 56),
+		kind = value(UInt8, 61) == 0 ? :state : :energy,
+		config = String(bytes[65:64+config_length]),
+	)
+
+	size = (Int(value(UInt32, 16)), Int(value(UInt32, 20)))
+	return reshape(reinterpret(Float32, bytes[header_length+1:end]), size), metadata
+end
@@ -1,5 +1,6 @@
-use std::{fs, mem, path::Path, sync::Arc};
+use std::{mem, sync::Arc};
 
+use nalgebra::Vector2;
 use wgpu::{
     CommandEncoderDescriptor, CompositeAlphaMode, Device, PresentMode, Queue, Surface,
     SurfaceConfiguration, TextureFormat, TextureUsages, TextureViewDescriptor,
@@ -7,7 +8,7 @@ use wgpu::{
 use winit::window::Window;
 
 use crate::{
-    misc::util::{download_buffer, save_dated_file},
+    recording::Recording,
     renderer::Renderer,
     simulation::Simulation,
     ui::{egui::Egui, interface::Gui},
@@ -19,6 +20,7 @@ pub struct App<'a> {
     pub graphics: GraphicsContext<'a>,
     pub simulation: Simulation,
     pub renderer: Renderer,
+    pub recording: Recording,
 
     pub egui: Egui,
     pub gui: Gui,
@@ -27,47 +29,49 @@ pub struct App<'a> {
 pub struct GraphicsContext<'a> {
     pub window: Arc<Window>,
     pub surface: Surface<'a>,
-    pub device: Device,
-    pub queue: Queue,
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
 }
 
 impl App<'_> {
     pub fn render(&mut self) {
         let gc = &self.graphics;
+        let window = gc.window.inner_size();
+        let window = Vector2::new(window.width, window.height);
+        self.simulation.solver_mut().window = window;
+        if let Err(e) = self.simulation.update() {
+            eprintln!("Simulation paused: {:?}", e);
+        }
+
         let mut encoder = gc
             .device
             .create_command_encoder(&CommandEncoderDescriptor { label: None });
 
-        self.simulation
-            .update(gc, &mut encoder, gc.window.inner_size());
-
         let output = gc.surface.get_current_texture().unwrap();
         let view = output
             .texture
             .create_view(&TextureViewDescriptor::default());
 
-        self.renderer.render(self, &mut encoder, &view);
+        self.renderer.render(
+            &gc.device,
+            &gc.queue,
+            &self.simulation,
+            window,
+            &mut encoder,
+            &view,
+        );
         self.egui.render(gc, &mut encoder, &view, |ctx| {
-            self.gui
-                .ui(ctx, gc, &mut self.simulation, &mut self.renderer);
+            self.gui.ui(
+                ctx,
+                gc,
+                &mut self.simulation,
+                &mut self.renderer,
+                &self.recording,
+            );
         });
 
-        while let Some((snapshot, name)) = self.simulation.snapshot.pop() {
-            let buffer = snapshot.stage(&self.simulation, &mut encoder);
-
-            let mut data = Vec::with_capacity(8 + buffer.size() as usize);
-            let size = self.simulation.get_size();
-            data.extend_from_slice(&size.x.to_le_bytes());
-            data.extend_from_slice(&size.y.to_le_bytes());
-            data.extend_from_slice(&download_buffer(buffer, gc));
-
-            let path = if let Some(name) = name {
-                Path::new("states").join(name).to_path_buf()
-            } else {
-                save_dated_file("states", snapshot.name(), "bin").unwrap()
-            };
-
-            fs::write(path, data).unwrap();
+        if let Err(e) = self.simulation.save_snapshots() {
+            eprintln!("Failed to save snapshot: {:?}", e);
         }
 
         gc.queue.submit([encoder.finish()]);
@@ -75,8 +79,15 @@ impl App<'_> {
         output.present();
         gc.window.request_redraw();
 
-        if mem::take(&mut self.gui.queue_screenshot) {
-            if let Err(e) = self.renderer.screenshot(self) {
+        if let Err(e) = self.recording.update(&self.renderer, &self.simulation) {
+            eprintln!("Failed to record frame: {:?}", e);
+        }
+
+        if mem::take(&mut self.simulation.queue_screenshot) {
+            if let Err(e) = self
+                .renderer
+                .screenshot(&gc.device, &gc.queue, &self.simulation)
+            {
                 eprintln!("Failed to take screenshot: {:?}", e);
             }
         }
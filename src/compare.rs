@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use wgpu::{Device, Queue};
+
+use crate::{
+    config::Config,
+    simulation::{
+        solver::{CpuSolver, GpuSolver, Scene, Solver},
+        SimulationParameters,
+    },
+};
+
+/// Steps the same scene on the GPU and CPU solvers, printing how far their fields drift apart.
+/// Scripts are not run, so both solvers see the initial parameters from the config.
+pub fn compare(device: Arc<Device>, queue: Arc<Queue>, config: &Config, ticks: u64) -> Result<()> {
+    let scene = Scene::load(config)?;
+    let mut gpu = GpuSolver::new(device, queue, &scene)?;
+    let mut cpu = CpuSolver::new(&scene)?;
+
+    let mut params = SimulationParameters::new(config);
+    let interval = (ticks / 10).max(1);
+    for tick in 0..ticks {
+        params.tick = tick;
+        gpu.tick(&params);
+        cpu.tick(&params);
+
+        let next = tick + 1;
+        if next % interval == 0 || next == ticks {
+            let state = Difference::new(&gpu.read_state(next), &cpu.read_state(next));
+            let energy = Difference::new(&gpu.read_average_energy(), &cpu.read_average_energy());
+            println!("Tick {next}: state {state}, energy {energy}");
+        }
+    }
+
+    Ok(())
+}
+
+struct Difference {
+    max: f32,
+    rms: f32,
+    scale: f32,
+}
+
+impl Difference {
+    fn new(gpu: &[f32], cpu: &[f32]) -> Self {
+        let (mut max, mut sum, mut scale) = (0f32, 0f64, 0f32);
+        for (a, b) in gpu.iter().zip(cpu) {
+            let error = (a - b).abs();
+            max = max.max(error);
+            sum += (error as f64).powi(2);
+            scale = scale.max(b.abs());
+        }
+
+        Self {
+            max,
+            rms: (sum / cpu.len() as f64).sqrt() as f32,
+            scale,
+        }
+    }
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "max error {:.3e} (rms {:.3e}) of {:.3e}",
+            self.max, self.rms, self.scale
+        )
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{
+    config::CompositeArgs,
+    misc::color::{self, Grading},
+    simulation::snapshot::{Snapshot, SnapshotType},
+};
+
+/// Colors energy snapshots by the wavelength they were taken at, read from the `wavelength` column of a sweep's `index.csv`
+/// or otherwise found from the wave speed and frequency in their headers.
+/// Replaces `scripts/composite.jl`, which read the wavelength from names like `wl-500nm.bin`.
+pub fn run(args: &CompositeArgs) -> Result<()> {
+    let mut paths = Vec::new();
+    for input in &args.inputs {
+        if !input.is_dir() {
+            paths.push(input.clone());
+            continue;
+        }
+
+        let mut files = fs::read_dir(input)
+            .with_context(|| format!("Failed to read {}", input.display()))?
+            .map(|x| Ok(x?.path()))
+            .collect::<Result<Vec<PathBuf>>>()?;
+        files.retain(|x| x.is_file() && x.extension().is_some_and(|x| x == "bin" || x == "npz"));
+        files.sort();
+        paths.extend(files);
+    }
+
+    let mut indexes = HashMap::new();
+    let mut snapshots = Vec::new();
+    for path in paths {
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        if !indexes.contains_key(&directory) {
+            let wavelengths = sweep_wavelengths(&directory)?;
+            indexes.insert(directory.clone(), wavelengths);
+        }
+        let swept = path
+            .file_name()
+            .and_then(|x| indexes[&directory].get(Path::new(x)))
+            .copied();
+
+        let snapshot = Snapshot::load(&path)?;
+        let wavelength = match (&snapshot.metadata, swept) {
+            (Some(metadata), _) if metadata.kind != SnapshotType::Energy => {
+                eprintln!(
+                    "Skipping {}, which isn't an energy snapshot.",
+                    path.display()
+                );
+                continue;
+            }
+            (_, Some(wavelength)) => wavelength,
+            (Some(metadata), None) => metadata.v / metadata.frequency * 1e9,
+            (None, None) => {
+                eprintln!(
+                    "Skipping {}, which has no swept wavelength or wave speed and frequency to find it from.",
+                    path.display()
+                );
+                continue;
+            }
+        };
+
+        ensure!(
+            wavelength.is_finite() && wavelength > 0.0,
+            "{} has no usable wavelength.",
+            path.display()
+        );
+        snapshots.push((wavelength, path, snapshot));
+    }
+
+    let Some((_, first_path, first)) = snapshots.first() else {
+        bail!("No energy snapshots found.");
+    };
+    let size = first.size;
+    if let Some((_, path, snapshot)) = snapshots.iter().find(|x| x.2.size != size) {
+        bail!(
+            "{} is {}×{}, but {} is {}×{}.",
+            path.display(),
+            snapshot.size.x,
+            snapshot.size.y,
+            first_path.display(),
+            size.x,
+            size.y
+        );
+    }
+
+    snapshots.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (wavelength, path, _) in &snapshots {
+        println!("{:.1} nm: {}", wavelength, path.display());
+    }
+
+    let layers = (snapshots.iter())
+        .map(|(wavelength, _, snapshot)| (*wavelength, snapshot.data.as_slice()))
+        .collect::<Vec<_>>();
+    let grading = Grading {
+        exposure: args.exposure,
+        gamma: args.gamma,
+        white_balance: args.white_balance,
+    };
+
+    if let Some(parent) = args.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    color::composite(size.x, size.y, &layers, &grading).save(&args.output)?;
+    println!(
+        "Wrote {} from {} snapshots.",
+        args.output.display(),
+        layers.len()
+    );
+    Ok(())
+}
+
+/// Maps the energy snapshots listed in a sweep's `index.csv` to the wavelength, in nanometers, they were swept at.
+/// Empty if the directory has no index or the sweep didn't vary `wavelength`.
+fn sweep_wavelengths(directory: &Path) -> Result<HashMap<PathBuf, f32>> {
+    let path = directory.join("index.csv");
+    if !path.is_file() {
+        return Ok(HashMap::new());
+    }
+
+    let index =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut rows = index.lines();
+    let columns = rows
+        .next()
+        .unwrap_or_default()
+        .split(',')
+        .collect::<Vec<_>>();
+    let column = |name| columns.iter().position(|x| *x == name);
+    let (Some(wavelength), Some(energy)) =
+        (column("wavelength"), column(SnapshotType::Energy.name()))
+    else {
+        return Ok(HashMap::new());
+    };
+
+    let mut wavelengths = HashMap::new();
+    for (line, row) in rows.enumerate() {
+        let row = row.split(',').collect::<Vec<_>>();
+        let (Some(value), Some(name)) = (row.get(wavelength), row.get(energy)) else {
+            bail!("Row {} of {} is missing columns.", line + 2, path.display());
+        };
+        let value = value.parse::<f32>().with_context(|| {
+            format!(
+                "Row {} of {} has an invalid wavelength.",
+                line + 2,
+                path.display()
+            )
+        })?;
+        wavelengths.insert(PathBuf::from(name), value * 1e9);
+    }
+
+    Ok(wavelengths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::sweep_wavelengths;
+
+    #[test]
+    fn reads_sweep_wavelengths() {
+        let directory = env::temp_dir().join(format!("wave-sim-{}-composite", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        assert!(sweep_wavelengths(&directory).unwrap().is_empty());
+
+        let index = "run,wavelength,v,energy\n\
+            0,0.0000005,300000000,000_wavelength=5e-7_v=3e8.bin\n\
+            1,0.000001,300000000,001_wavelength=1e-6_v=3e8.bin\n";
+        fs::write(directory.join("index.csv"), index).unwrap();
+        let wavelengths = sweep_wavelengths(&directory).unwrap();
+        assert_eq!(wavelengths.len(), 2);
+        let wavelength = wavelengths[&PathBuf::from("001_wavelength=1e-6_v=3e8.bin")];
+        assert!((wavelength - 1000.0).abs() < 1e-3);
+
+        fs::write(
+            directory.join("index.csv"),
+            "run,v,energy\n0,3e8,000_v=3e8.bin\n",
+        )
+        .unwrap();
+        assert!(sweep_wavelengths(&directory).unwrap().is_empty());
+
+        fs::write(
+            directory.join("index.csv"),
+            "run,wavelength,energy\n0,red,a.bin\n",
+        )
+        .unwrap();
+        assert!(sweep_wavelengths(&directory).is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,12 +1,34 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs, mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use anyhow::Result;
-use clap::Parser;
-use serde::Deserialize;
+use anyhow::{bail, ensure, Context, Result};
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
 
-#[derive(Deserialize, Debug)]
-#[serde(default)]
+use crate::{
+    misc::{color::WhiteBalance, mesh::MeshOptions, units},
+    simulation::{
+        boundary::{Boundaries, Boundary},
+        detector::Detector,
+        flux::FluxConfig,
+        lock_in::LockInConfig,
+        snapshot::SnapshotFormat,
+        solver::{courant_limit, stencil_weights},
+        source::Source,
+        sweep::SweepConfig,
+    },
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
+    /// The path the config was loaded from.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
     /// The path that shader and map files are relative to.
     pub base_path: Option<PathBuf>,
 
@@ -16,6 +38,16 @@ pub struct Config {
     pub parameters: Parameters,
     /// Oscillator parameters.
     pub oscillator: Oscillator,
+    /// Point sources, added on top of the oscillator and map emitters.
+    #[serde(rename = "source")]
+    pub sources: Vec<Source>,
+    /// Points, lines and rectangles to record the field at.
+    #[serde(rename = "detector")]
+    pub detectors: Vec<Detector>,
+    /// Lines to measure the energy flowing across.
+    pub flux: FluxConfig,
+    /// Per-cell amplitude and phase at the oscillator frequency.
+    pub lock_in: Option<LockInConfig>,
 
     /// The path to the shader file.
     pub shader: Option<PathBuf>,
@@ -25,76 +57,683 @@ pub struct Config {
     /// The red channel represents walls, green represents emitters, and blue represents change in c (128 is no change).
     /// Should be a lossless format like PNG.
     pub map: Option<PathBuf>,
+    /// The path to a greyscale image defining the loss of each cell, where white applies all of `loss`.
+    /// Without one, the map's alpha channel is used instead, with transparent pixels applying all of `loss`.
+    pub loss_map: Option<PathBuf>,
+
+    /// Format of snapshots taken without a file extension.
+    pub snapshot_format: SnapshotFormat,
+    /// How snapshots written as `.stl` or `.ply` meshes are built.
+    pub mesh: MeshOptions,
 
     /// Audio configuration.
     pub audio: Option<AudioConfig>,
+    /// Checks for the field blowing up.
+    pub stability: Stability,
+    /// Capturing rendered frames as numbered images or an animation.
+    pub record: RecordConfig,
+    /// Rerunning the simulation over ranges of parameters, taking snapshots at the end of each run.
+    pub sweep: Option<SweepConfig>,
+    /// Configs this one is merged on top of, which `load` has already merged in unless a file is read on its own to find an error.
+    #[serde(skip_serializing)]
+    extends: Option<Value>,
+
+    // Keys from the flat layout used before `[parameters]` and `[oscillator]`, which `load` moves into them.
+    #[serde(rename = "dt", deserialize_with = "units::time")]
+    legacy_dt: Option<f32>,
+    #[serde(rename = "dx", deserialize_with = "units::length")]
+    legacy_dx: Option<f32>,
+    #[serde(rename = "v", deserialize_with = "units::speed")]
+    legacy_v: Option<f32>,
+    #[serde(rename = "reflective_boundary")]
+    legacy_reflective_boundary: Option<bool>,
+    #[serde(rename = "amplitude")]
+    legacy_amplitude: Option<f32>,
+    #[serde(rename = "frequency", deserialize_with = "units::frequency")]
+    legacy_frequency: Option<f32>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
 pub struct Parameters {
     /// Time step (ms).
+    #[serde(deserialize_with = "units::time")]
     pub dt: f32,
     /// Space step (mm).
+    #[serde(deserialize_with = "units::length")]
     pub dx: f32,
     /// Wave speed m/s.
+    #[serde(deserialize_with = "units::speed")]
     pub v: f32,
 
     /// Reflective Boundaries
     pub reflective_boundary: bool,
+    /// Per-edge boundary conditions, overriding `reflective_boundary` when set.
+    pub boundary: Option<Boundaries>,
+    /// Thickness in cells of the perfectly matched layer used on absorbing edges.
+    /// Zero uses a first-order absorbing boundary on the outermost cells instead.
+    pub pml_thickness: u32,
+    /// Order of accuracy of the Laplacian stencil, one of 2, 4 or 6.
+    /// Higher orders have less numerical dispersion but a lower CFL limit.
+    pub stencil_order: u32,
+    /// Damping rate (1/s) of cells with full loss, added as a `loss * du/dt` term to the wave equation.
+    #[serde(deserialize_with = "units::frequency")]
+    pub loss: f32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Oscillator {
     /// Initial oscillator amplitude.
     pub amplitude: f32,
     /// Initial oscillator frequency in Hz.
+    #[serde(deserialize_with = "units::frequency")]
     pub frequency: f32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct AudioConfig {
     pub input: PathBuf,
     pub output: PathBuf,
     pub pickup: (u32, u32),
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Stability {
+    /// Ticks between checks of the field, zero to disable them.
+    pub interval: u64,
+    /// Largest magnitude the field can reach before it is considered unstable.
+    pub max_amplitude: f32,
+    /// Return to the state from the last passing check when the field becomes unstable.
+    pub rollback: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordConfig {
+    /// Record from the start, instead of waiting for the GUI or a script to start it.
+    pub enabled: bool,
+    /// How often a frame is captured while recording.
+    pub interval: RecordInterval,
+    /// Write every frame as a numbered PNG.
+    pub images: bool,
+    /// Directory the numbered frames are written to, a dated one in `recordings` without one.
+    pub output: Option<PathBuf>,
+    /// A `.gif` file every frame is also added to.
+    pub animation: Option<PathBuf>,
+    /// Frame rate of the animation.
+    pub fps: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordInterval {
+    /// Every time this many ticks have passed, which is the same no matter how fast the simulation runs.
+    Ticks(u64),
+    /// Every this many rendered frames that have advanced the simulation.
+    Frames(u64),
+}
+
 #[derive(Parser)]
 #[clap(name = "wave-sim", version = "0.1.0", author = "Connor Slade")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Path to a configuration file. (params.toml)
-    config: PathBuf,
+    #[arg(required = true)]
+    pub config: Option<PathBuf>,
+    /// Run the config on both the GPU and CPU solvers for this many ticks and report how far they diverge.
+    #[arg(long, value_name = "TICKS", conflicts_with = "headless")]
+    pub compare: Option<u64>,
+    /// Resume from a checkpoint written by an earlier run of the same config.
+    #[arg(long, value_name = "PATH")]
+    pub checkpoint: Option<PathBuf>,
+    /// Override a config value, like `--set oscillator.frequency=5e14` or `--set size=[800,600]`.
+    /// Values are read as TOML, falling back to a string.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<Override>,
+    /// Print the config after applying its bases and overrides, then exit.
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Run without opening a window until the script pauses, the sweep finishes or the tick count is reached.
+    #[arg(long)]
+    pub headless: bool,
+    /// Number of ticks to run for in headless mode.
+    #[arg(long, requires = "headless")]
+    pub ticks: Option<u64>,
+    /// Use the CPU solver, which doesn't need a graphics adapter.
+    /// Screenshots and custom shaders are not supported.
+    #[arg(long, requires = "headless")]
+    pub cpu: bool,
+}
+
+/// A value set from the command line, at a dotted path of keys where numbers index into arrays.
+#[derive(Debug, Clone)]
+pub struct Override {
+    key: Vec<String>,
+    value: Value,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Colors energy snapshots taken at different wavelengths and adds them up into a true color image.
+    Composite(CompositeArgs),
+    /// Turns a snapshot into a height field mesh for 3D printing.
+    Mesh(MeshArgs),
+}
+
+#[derive(clap::Args)]
+pub struct CompositeArgs {
+    /// Energy snapshots, or directories to use every `.bin` and `.npz` snapshot from.
+    #[arg(default_value = "states")]
+    pub inputs: Vec<PathBuf>,
+    /// Path of the image to write.
+    #[arg(short, long, default_value = "composite.png")]
+    pub output: PathBuf,
+    /// Brightness, where a flat spectrum with an energy of `1 / exposure` in every snapshot is at full brightness.
+    #[arg(long, default_value_t = 1.0)]
+    pub exposure: f32,
+    /// Display gamma, using the sRGB transfer function if not given.
+    #[arg(long)]
+    pub gamma: Option<f32>,
+    /// `none`, `flat` to make a flat spectrum white, or red, green and blue gains like `1,0.9,1.2`.
+    #[arg(long, default_value = "none")]
+    pub white_balance: WhiteBalance,
+}
+
+#[derive(clap::Args)]
+pub struct MeshArgs {
+    /// A `.bin`, `.npy` or `.npz` snapshot, using the energy from `.npz` archives.
+    pub input: PathBuf,
+    /// Path of the mesh to write, as a binary `.stl` or `.ply` file.
+    #[arg(short, long, default_value = "mesh.stl")]
+    pub output: PathBuf,
+    /// Height of the surface per unit of the field, in cells.
+    #[arg(long, default_value_t = 350.0)]
+    pub scale: f32,
+    /// Thickness of the solid base under the surface in cells, leaving just the surface if zero.
+    #[arg(long, default_value_t = 14.0)]
+    pub base: f32,
+    /// Averages square blocks of this many cells into each vertex.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub decimation: u32,
+    /// Scale heights logarithmically over this many decades below the peak, which then reaches `scale`.
+    #[arg(long, value_name = "DECADES")]
+    pub log: Option<f32>,
 }
 
 impl Config {
     pub fn base_path(&self) -> PathBuf {
         self.base_path.clone().unwrap_or_default()
     }
+
+    /// Writes the config out as TOML, with every default filled in.
+    pub fn to_toml(&self) -> Result<String> {
+        let mut value = Value::try_from(self)?;
+        round_floats(&mut value);
+        Ok(toml::to_string(&value)?)
+    }
+
+    /// Moves keys written in the flat layout into `[parameters]` and `[oscillator]`, returning where each went.
+    /// The parsed `table` is used to find keys set in both places.
+    fn migrate(&mut self, table: &Table) -> Result<Vec<String>> {
+        let mut moved = Vec::new();
+        let (parameters, oscillator) = (&mut self.parameters, &mut self.oscillator);
+        migrate_key(
+            table,
+            &mut moved,
+            "parameters.dt",
+            self.legacy_dt.take(),
+            &mut parameters.dt,
+        )?;
+        migrate_key(
+            table,
+            &mut moved,
+            "parameters.dx",
+            self.legacy_dx.take(),
+            &mut parameters.dx,
+        )?;
+        migrate_key(
+            table,
+            &mut moved,
+            "parameters.v",
+            self.legacy_v.take(),
+            &mut parameters.v,
+        )?;
+        migrate_key(
+            table,
+            &mut moved,
+            "parameters.reflective_boundary",
+            self.legacy_reflective_boundary.take(),
+            &mut parameters.reflective_boundary,
+        )?;
+        migrate_key(
+            table,
+            &mut moved,
+            "oscillator.amplitude",
+            self.legacy_amplitude.take(),
+            &mut oscillator.amplitude,
+        )?;
+        migrate_key(
+            table,
+            &mut moved,
+            "oscillator.frequency",
+            self.legacy_frequency.take(),
+            &mut oscillator.frequency,
+        )?;
+        Ok(moved)
+    }
+
+    /// Checks that values are in range, so mistakes are caught before any GPU work starts.
+    pub fn validate(&self) -> Result<()> {
+        let (width, height) = self.size;
+        ensure!(
+            width > 0 && height > 0,
+            "Size must be at least one cell in each direction."
+        );
+
+        let params = &self.parameters;
+        for (name, value) in [("dt", params.dt), ("dx", params.dx), ("v", params.v)] {
+            ensure!(
+                value.is_finite() && value > 0.0,
+                "`parameters.{name}` must be positive, found {value}."
+            );
+        }
+        ensure!(
+            params.loss.is_finite() && params.loss >= 0.0,
+            "`parameters.loss` can't be negative, found {}.",
+            params.loss
+        );
+        ensure!(
+            2 * (params.pml_thickness + 1) < width.min(height),
+            "PML must be thinner than half the simulation size."
+        );
+        let Some(weights) = stencil_weights(params.stencil_order) else {
+            bail!("Stencil order must be 2, 4 or 6.");
+        };
+        ensure!(
+            params.boundaries().is_valid(),
+            "Periodic boundaries must be used on both opposite edges."
+        );
+
+        let courant = params.v * params.dt / params.dx;
+        let limit = courant_limit(weights);
+        if courant > limit {
+            eprintln!(
+                "Warning: The Courant number v·dt/dx is {courant:.3}, above the limit of {limit:.3} for a stencil order of {}, so the simulation will be unstable.",
+                params.stencil_order
+            );
+        }
+
+        for (name, path) in [("map", &self.map), ("loss_map", &self.loss_map)] {
+            let Some(path) = path else { continue };
+            let path = self.base_path().join(path);
+            let (x, y) = image::image_dimensions(&path)
+                .with_context(|| format!("Failed to read `{name}` from {}", path.display()))?;
+            ensure!(
+                x <= width && y <= height,
+                "`{name}` is {x}×{y}, which doesn't fit in the simulation size of {width}×{height}."
+            );
+        }
+
+        for (i, source) in self.sources.iter().enumerate() {
+            ensure!(
+                source.width.is_finite() && source.width > 0.0,
+                "`source[{i}].width` must be positive, found {}.",
+                source.width
+            );
+            (source.waveform.validate(source.frequency))
+                .with_context(|| format!("`source[{i}].waveform` is invalid."))?;
+        }
+
+        if let Some(audio) = &self.audio {
+            let (x, y) = audio.pickup;
+            ensure!(
+                x < width && y < height,
+                "`audio.pickup` at ({x}, {y}) is outside the {width}×{height} simulation."
+            );
+        }
+
+        Ok(())
+    }
 }
 
-pub fn parse() -> Result<Config> {
-    let args = Args::parse();
+/// Moves one key from the top level to its `section.key`, failing if it was set in both places.
+fn migrate_key<T>(
+    table: &Table,
+    moved: &mut Vec<String>,
+    target_key: &str,
+    legacy: Option<T>,
+    target: &mut T,
+) -> Result<()> {
+    let Some(value) = legacy else {
+        return Ok(());
+    };
 
-    let raw_config = fs::read_to_string(&args.config)?;
-    let mut config = toml::from_str::<Config>(&raw_config)?;
+    let (section, key) = target_key.split_once('.').unwrap();
+    ensure!(
+        table.get(section).and_then(|x| x.get(key)).is_none(),
+        "`{key}` is set both at the top level and in `[{section}]`."
+    );
+    *target = value;
+    moved.push(format!("`{target_key}`"));
+    Ok(())
+}
+
+impl Parameters {
+    pub fn boundaries(&self) -> Boundaries {
+        self.boundary
+            .unwrap_or(Boundaries::uniform(if self.reflective_boundary {
+                Boundary::Dirichlet
+            } else {
+                Boundary::Absorbing
+            }))
+    }
+}
 
+/// Keys holding paths, which are made absolute when the file they're in is extended so they stay relative to it.
+const PATH_KEYS: [&[&str]; 15] = [
+    &["shader"],
+    &["script"],
+    &["map"],
+    &["loss_map"],
+    &["audio", "input"],
+    &["audio", "output"],
+    &["record", "output"],
+    &["record", "animation"],
+    &["lock_in", "output"],
+    &["lock_in", "composite"],
+    &["flux", "output"],
+    &["flux", "reference"],
+    &["source", "reference"],
+    &["detector", "output"],
+    &["sweep", "output"],
+];
+
+/// Loads a config and any it `extends`, rejecting unknown keys and moving ones in the legacy flat layout into their tables with a warning.
+pub fn load(path: &Path, overrides: &[Override]) -> Result<Config> {
+    let raw_config =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let invalid = || format!("Invalid config {}", path.display());
+    let mut table = toml::from_str::<Table>(&raw_config).with_context(invalid)?;
+
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut files = Vec::new();
+    let merged = merge_bases(
+        &mut table,
+        &canonical,
+        &mut vec![canonical.clone()],
+        &mut files,
+    )?;
+    let bases = table.clone();
+    for value in overrides {
+        value.apply(&mut table)?;
+    }
+
+    let mut config = match merged || !overrides.is_empty() {
+        false => toml::from_str::<Config>(&raw_config).with_context(invalid)?,
+        // Merged tables have no positions, so each file is read on its own to point to the line an error is on.
+        true => match Config::deserialize(Value::Table(table.clone())) {
+            Ok(config) => config,
+            Err(error) => {
+                files.push((path.to_path_buf(), raw_config));
+                return Err(locate_error(&files, bases, overrides).unwrap_or_else(|| {
+                    anyhow::Error::new(error).context(format!(
+                        "Invalid config {} with its bases and overrides",
+                        path.display()
+                    ))
+                }));
+            }
+        },
+    };
+
+    let moved = config.migrate(&table).with_context(invalid)?;
+    if !moved.is_empty() {
+        eprintln!(
+            "Warning: {} uses the legacy flat layout, moving its keys to {}. Put them in their tables to silence this warning.",
+            path.display(),
+            moved.join(", ")
+        );
+    }
+
+    config.path = Some(path.to_path_buf());
     if config.base_path.is_none() {
-        config.base_path = Some(args.config.parent().unwrap().to_path_buf());
+        config.base_path = Some(path.parent().unwrap().to_path_buf());
     }
 
+    config.validate().with_context(invalid)?;
     Ok(config)
 }
 
+/// Merges the configs named by `extends` in the table of the config at `path` underneath it, in order.
+/// The `chain` of configs being loaded is used to find cycles, and every base read is added to `files` along with its contents.
+/// Returns whether there were any bases.
+fn merge_bases(
+    table: &mut Table,
+    path: &Path,
+    chain: &mut Vec<PathBuf>,
+    files: &mut Vec<(PathBuf, String)>,
+) -> Result<bool> {
+    let Some(extends) = table.remove("extends") else {
+        return Ok(false);
+    };
+    let bases = match extends {
+        Value::String(base) => vec![base],
+        Value::Array(bases) => bases
+            .into_iter()
+            .map(|x| match x {
+                Value::String(base) => Some(base),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .with_context(|| format!("`extends` in {} must only hold paths.", path.display()))?,
+        _ => bail!(
+            "`extends` in {} must be a path or a list of them.",
+            path.display()
+        ),
+    };
+
+    let mut merged = Table::new();
+    for base in bases {
+        let base = path.parent().unwrap().join(base);
+        let canonical = base.canonicalize().with_context(|| {
+            format!(
+                "Failed to read {}, extended by {}",
+                base.display(),
+                path.display()
+            )
+        })?;
+        if let Some(start) = chain.iter().position(|x| *x == canonical) {
+            let cycle = (chain[start..].iter().chain([&canonical]))
+                .map(|x| x.display().to_string())
+                .collect::<Vec<_>>();
+            bail!(
+                "Configs extend each other in a cycle: {}",
+                cycle.join(" → ")
+            );
+        }
+
+        let raw_config = fs::read_to_string(&canonical)?;
+        let mut table = toml::from_str::<Table>(&raw_config)
+            .with_context(|| format!("Invalid config {}", base.display()))?;
+        chain.push(canonical.clone());
+        merge_bases(&mut table, &canonical, chain, files)?;
+        chain.pop();
+        files.push((base.clone(), raw_config));
+
+        // Paths in a base are relative to its own directory and `base_path`, rather than the config extending it.
+        let mut directory = canonical.parent().unwrap().to_path_buf();
+        match table.remove("base_path") {
+            Some(Value::String(base_path)) => directory.push(base_path),
+            Some(_) => bail!("`base_path` in {} must be a path.", base.display()),
+            None => {}
+        }
+        for key in PATH_KEYS {
+            resolve_path(&mut table, key, &directory);
+        }
+        merge(&mut merged, table);
+    }
+
+    merge(&mut merged, mem::take(table));
+    *table = merged;
+    Ok(true)
+}
+
+/// Finds where the error in a merged config came from, first by reading each file on its own to point to its line,
+/// skipping missing fields as another file can fill them in, then by applying the overrides to the merged `table` one at a time.
+fn locate_error(
+    files: &[(PathBuf, String)],
+    mut table: Table,
+    overrides: &[Override],
+) -> Option<anyhow::Error> {
+    for (path, raw_config) in files {
+        match toml::from_str::<Config>(raw_config) {
+            Err(error) if !error.message().starts_with("missing field") => {
+                let context = format!("Invalid config {}", path.display());
+                return Some(anyhow::Error::new(error).context(context));
+            }
+            _ => {}
+        }
+    }
+
+    Config::deserialize(Value::Table(table.clone())).ok()?;
+    for value in overrides {
+        value.apply(&mut table).ok()?;
+        if let Err(error) = Config::deserialize(Value::Table(table.clone())) {
+            let context = format!("Invalid config after setting `{}`", value.key.join("."));
+            return Some(anyhow::Error::new(error).context(context));
+        }
+    }
+
+    None
+}
+
+impl Override {
+    /// Sets the value in a config's table, adding any tables on the way to it.
+    fn apply(&self, table: &mut Table) -> Result<()> {
+        let key = self.key.join(".");
+        let (first, rest) = self.key.split_first().unwrap();
+
+        let mut value = (table.entry(first)).or_insert_with(|| Value::Table(Table::new()));
+        for (i, part) in rest.iter().enumerate() {
+            let parent = self.key[..=i].join(".");
+            value = match value {
+                Value::Table(table) => {
+                    (table.entry(part)).or_insert_with(|| Value::Table(Table::new()))
+                }
+                Value::Array(items) => {
+                    let index = (part.parse::<usize>().ok()).with_context(|| {
+                        format!("Can't set `{key}`, as `{parent}` is an array of values indexed by number.")
+                    })?;
+                    (items.get_mut(index))
+                        .with_context(|| format!("`{key}` indexes past the end of `{parent}`."))?
+                }
+                _ => bail!("Can't set `{key}`, as `{parent}` isn't a table."),
+            };
+        }
+
+        *value = self.value.clone();
+        Ok(())
+    }
+}
+
+impl FromStr for Override {
+    type Err = anyhow::Error;
+
+    /// Parses `key.path=value`, taking values that aren't valid TOML, like bare paths, as strings.
+    fn from_str(s: &str) -> Result<Self> {
+        let (key, value) = (s.split_once('='))
+            .with_context(|| format!("Expected `key.path=value`, found `{s}`."))?;
+        let key = key.trim().split('.').map(str::to_owned).collect::<Vec<_>>();
+        ensure!(key.iter().all(|x| !x.is_empty()), "`{s}` has an empty key.");
+
+        let value = toml::from_str::<Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut x| x.remove("value"))
+            .unwrap_or_else(|| Value::String(value.to_owned()));
+        Ok(Self { key, value })
+    }
+}
+
+/// Rounds floats to the shortest decimal that gives the same `f32`, which is what every float in the config is stored as.
+fn round_floats(value: &mut Value) {
+    match value {
+        Value::Float(x) => *x = (*x as f32).to_string().parse().unwrap_or(*x),
+        Value::Array(items) => items.iter_mut().for_each(round_floats),
+        Value::Table(table) => table.iter_mut().for_each(|(_, x)| round_floats(x)),
+        _ => {}
+    }
+}
+
+/// Deep merges `over` into `base`, where tables are merged key by key and everything else, including arrays, is replaced.
+fn merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Joins the path at `key` onto `directory`, going into every table of arrays along the way.
+fn resolve_path(table: &mut Table, key: &[&str], directory: &Path) {
+    let Some((first, rest)) = key.split_first() else {
+        return;
+    };
+    match table.get_mut(*first) {
+        Some(Value::String(path)) if rest.is_empty() => {
+            *path = directory.join(&*path).to_string_lossy().into_owned();
+        }
+        Some(Value::Table(table)) => resolve_path(table, rest, directory),
+        Some(Value::Array(items)) => {
+            for item in items {
+                if let Value::Table(table) = item {
+                    resolve_path(table, rest, directory);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
+            path: None,
             base_path: None,
             size: (1920, 1080),
             parameters: Default::default(),
             oscillator: Default::default(),
+            sources: Vec::new(),
+            detectors: Vec::new(),
+            flux: FluxConfig::default(),
+            lock_in: None,
             shader: None,
             map: None,
+            loss_map: None,
             script: None,
+            snapshot_format: Default::default(),
+            mesh: Default::default(),
             audio: None,
+            stability: Default::default(),
+            record: Default::default(),
+            sweep: None,
+            extends: None,
+            legacy_dt: None,
+            legacy_dx: None,
+            legacy_v: None,
+            legacy_reflective_boundary: None,
+            legacy_amplitude: None,
+            legacy_frequency: None,
         }
     }
 }
@@ -106,6 +745,33 @@ impl Default for Parameters {
             dx: 5e-6,
             v: 299_792_458.0,
             reflective_boundary: false,
+            boundary: None,
+            pml_thickness: 0,
+            stencil_order: 2,
+            loss: 0.0,
+        }
+    }
+}
+
+impl Default for Stability {
+    fn default() -> Self {
+        Self {
+            interval: 64,
+            max_amplitude: 1e6,
+            rollback: false,
+        }
+    }
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: RecordInterval::Frames(1),
+            images: true,
+            output: None,
+            animation: None,
+            fps: 30.0,
         }
     }
 }
@@ -118,3 +784,187 @@ impl Default for Oscillator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use toml::{Table, Value};
+
+    use super::{load, merge_bases, Override};
+
+    /// Writes configs into a fresh directory, returning it.
+    fn write_configs(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = env::temp_dir().join(format!("wave-sim-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        for (file, contents) in files {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        directory.canonicalize().unwrap()
+    }
+
+    fn merged(path: PathBuf) -> anyhow::Result<Table> {
+        let mut table = toml::from_str::<Table>(&fs::read_to_string(&path)?)?;
+        merge_bases(&mut table, &path, &mut vec![path.clone()], &mut Vec::new())?;
+        Ok(table)
+    }
+
+    #[test]
+    fn merges_bases_in_order() {
+        let directory = write_configs(
+            "merge",
+            &[
+                (
+                    "shared/a.toml",
+                    "map = \"a.png\"\n[parameters]\ndt = 1\ndx = 1",
+                ),
+                ("b.toml", "[parameters]\ndx = 2\nv = 2"),
+                (
+                    "c.toml",
+                    "extends = [\"shared/a.toml\", \"b.toml\"]\n[parameters]\nv = 3",
+                ),
+            ],
+        );
+
+        let table = merged(directory.join("c.toml")).unwrap();
+        let parameters = table["parameters"].as_table().unwrap();
+        assert!(!table.contains_key("extends"));
+        assert_eq!(parameters["dt"], Value::Integer(1));
+        assert_eq!(parameters["dx"], Value::Integer(2));
+        assert_eq!(parameters["v"], Value::Integer(3));
+
+        // Paths stay relative to the base they're from
+        let map = directory.join("shared").join("a.png");
+        assert_eq!(table["map"].as_str(), map.to_str());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let directory = write_configs(
+            "cycle",
+            &[
+                ("a.toml", "extends = \"b.toml\""),
+                ("b.toml", "extends = \"c.toml\""),
+                ("c.toml", "extends = \"b.toml\""),
+                ("self.toml", "extends = \"./self.toml\""),
+            ],
+        );
+
+        let error = merged(directory.join("a.toml")).unwrap_err().to_string();
+        assert!(error.contains("cycle"), "{error}");
+        assert!(!error.contains("a.toml"), "{error}");
+        let error = merged(directory.join("self.toml")).unwrap_err().to_string();
+        assert!(error.contains("cycle"), "{error}");
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn allows_shared_bases() {
+        let directory = write_configs(
+            "diamond",
+            &[
+                ("base.toml", "size = [4, 4]"),
+                ("a.toml", "extends = \"base.toml\""),
+                ("b.toml", "extends = \"base.toml\""),
+                ("c.toml", "extends = [\"a.toml\", \"b.toml\"]"),
+            ],
+        );
+
+        let table = merged(directory.join("c.toml")).unwrap();
+        assert!(table.contains_key("size"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn apply(table: &str, overrides: &[&str]) -> anyhow::Result<Table> {
+        let mut table = toml::from_str::<Table>(table).unwrap();
+        for value in overrides {
+            value.parse::<Override>()?.apply(&mut table)?;
+        }
+        Ok(table)
+    }
+
+    #[test]
+    fn parses_overrides() {
+        let value = "parameters.dt=\"5 us\"".parse::<Override>().unwrap();
+        assert_eq!(value.key, ["parameters", "dt"]);
+        assert_eq!(value.value, Value::String("5 us".into()));
+
+        let value = " size = [4, 4]".parse::<Override>().unwrap();
+        assert_eq!(value.key, ["size"]);
+        assert_eq!(value.value.as_array().unwrap().len(), 2);
+        // Values that aren't TOML are taken as strings
+        let value = "map=maps/lens.png".parse::<Override>().unwrap();
+        assert_eq!(value.value, Value::String("maps/lens.png".into()));
+        let value = "stability.rollback=true".parse::<Override>().unwrap();
+        assert_eq!(value.value, Value::Boolean(true));
+
+        assert!("parameters.dt".parse::<Override>().is_err());
+        assert!("parameters..dt=1".parse::<Override>().is_err());
+        assert!("=1".parse::<Override>().is_err());
+    }
+
+    #[test]
+    fn applies_overrides() {
+        let table = apply(
+            "[parameters]\ndt = 1\n[[source]]\namplitude = 1\n[[source]]\namplitude = 2",
+            &[
+                "parameters.dx=2",
+                "oscillator.frequency=3",
+                "source.1.amplitude=4",
+            ],
+        )
+        .unwrap();
+        assert_eq!(table["parameters"]["dt"], Value::Integer(1));
+        assert_eq!(table["parameters"]["dx"], Value::Integer(2));
+        assert_eq!(table["oscillator"]["frequency"], Value::Integer(3));
+        assert_eq!(table["source"][0]["amplitude"], Value::Integer(1));
+        assert_eq!(table["source"][1]["amplitude"], Value::Integer(4));
+
+        let error = |overrides| apply("size = 4\nsource = [{}]", overrides).unwrap_err();
+        assert!(error(&["size.x=1"]).to_string().contains("isn't a table"));
+        assert!(error(&["source.x.amplitude=1"])
+            .to_string()
+            .contains("indexed by number"));
+        assert!(error(&["source.1.amplitude=1"])
+            .to_string()
+            .contains("past the end"));
+    }
+
+    #[test]
+    fn locates_errors_in_merged_configs() {
+        let directory = write_configs(
+            "locate",
+            &[
+                ("base.toml", "[oscillator]\nfrequency = 1\n"),
+                ("typo.toml", "size = [32, 32]\n[parameters]\ndtt = 1\n"),
+                (
+                    "main.toml",
+                    "extends = [\"base.toml\", \"typo.toml\"]\n[oscillator]\namplitude = 1",
+                ),
+                (
+                    "valid.toml",
+                    "extends = \"base.toml\"\nsize = [32, 32]\n[oscillator]\namplitude = 1",
+                ),
+            ],
+        );
+
+        let error = load(&directory.join("main.toml"), &[]).unwrap_err();
+        let message = format!("{error:?}");
+        assert!(message.contains("typo.toml"), "{message}");
+        assert!(message.contains("line 3"), "{message}");
+
+        let path = directory.join("valid.toml");
+        assert!(load(&path, &[]).is_ok());
+        let overrides = [
+            "parameters.dx=2".parse().unwrap(),
+            "size=\"big\"".parse().unwrap(),
+        ];
+        let message = format!("{:?}", load(&path, &overrides).unwrap_err());
+        assert!(message.contains("`size`"), "{message}");
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
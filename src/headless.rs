@@ -0,0 +1,55 @@
+use std::mem;
+
+use anyhow::{bail, Result};
+
+use crate::simulation::{flux::Flux, solver::Solver, Simulation};
+
+/// Runs a simulation without a window until it is paused, its sweep finishes or `ticks` have been computed.
+/// Queued snapshots are started after every tick and screenshots are taken with `screenshot`.
+/// `record` is called after every tick to capture frames, and once more with `finish` set at the end.
+/// Fails if the field becomes unstable.
+pub fn run<S: Solver>(
+    mut simulation: Simulation<S>,
+    ticks: Option<u64>,
+    mut screenshot: impl FnMut(&Simulation<S>) -> Result<()>,
+    mut record: impl FnMut(&Simulation<S>, bool) -> Result<()>,
+) -> Result<()> {
+    if ticks.is_none() && !simulation.has_script() && simulation.sweep().is_none() {
+        bail!("A tick count is required to run headless without a script or sweep.");
+    }
+
+    let params = &mut simulation.parameters;
+    params.ticks_per_dispatch = 1;
+    params.running = true;
+
+    let mut elapsed = 0;
+    while simulation.parameters.running && ticks.is_none_or(|ticks| elapsed < ticks) {
+        simulation.update()?;
+        if let Some(instability) = simulation.instability {
+            simulation.flush()?;
+            record(&simulation, true)?;
+            bail!("{instability}");
+        }
+
+        simulation.save_snapshots()?;
+
+        if mem::take(&mut simulation.queue_screenshot) {
+            screenshot(&simulation)?;
+        }
+        record(&simulation, false)?;
+
+        elapsed += 1;
+    }
+
+    simulation.flush()?;
+    record(&simulation, true)?;
+    // The last check may only finish once everything is flushed.
+    if let Some(instability) = simulation.instability {
+        bail!("{instability}");
+    }
+    println!("Finished after {elapsed} ticks.");
+    if let Some(report) = simulation.flux().and_then(Flux::report) {
+        println!("{report}");
+    }
+    Ok(())
+}
@@ -1,7 +1,9 @@
-use std::sync::Arc;
+use std::{mem, sync::Arc};
 
 use anyhow::{Context, Result};
 use app::{App, GraphicsContext};
+use clap::Parser;
+use config::{Args, Command};
 use image::ImageFormat;
 use ui::egui::Egui;
 use wgpu::{Instance, RequestAdapterOptions};
@@ -14,19 +16,61 @@ use winit::{
 };
 
 mod app;
+mod compare;
+mod composite;
 mod config;
+mod headless;
+mod mesh;
 mod misc;
+mod recording;
 mod renderer;
 mod simulation;
 mod ui;
+use recording::Recording;
 use renderer::Renderer;
-use simulation::{Simulation, SimulationFlags};
+use simulation::{
+    solver::{CpuSolver, GpuSolver},
+    Simulation, SimulationFlags,
+};
 
 const ICON: &[u8] = include_bytes!("assets/icon.png");
 
 #[pollster::main]
 async fn main() -> Result<()> {
-    let args = config::parse()?;
+    let args = Args::parse();
+    match &args.command {
+        Some(Command::Composite(args)) => return composite::run(args),
+        Some(Command::Mesh(args)) => return mesh::run(args),
+        None => {}
+    }
+    let config = config::load(args.config.as_ref().unwrap(), &args.overrides)?;
+    if args.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    if args.cpu {
+        let mut simulation = Simulation::new(&config, CpuSolver::new)?;
+        if let Some(path) = &args.checkpoint {
+            simulation.load_checkpoint(path)?;
+        }
+
+        let mut warned = false;
+        return headless::run(
+            simulation,
+            args.ticks,
+            |_| {
+                eprintln!("Screenshots are not supported by the CPU solver.");
+                Ok(())
+            },
+            |simulation, _| {
+                if simulation.record.enabled && !mem::replace(&mut warned, true) {
+                    eprintln!("Recording is not supported by the CPU solver.");
+                }
+                Ok(())
+            },
+        );
+    }
 
     let instance = Instance::default();
     let adapter = instance
@@ -34,9 +78,39 @@ async fn main() -> Result<()> {
         .await
         .context("No adapter found")?;
     let (device, queue) = adapter.request_device(&Default::default(), None).await?;
+    let (device, queue) = (Arc::new(device), Arc::new(queue));
 
-    let simulation = Simulation::new(&device, &args)?;
-    let renderer = Renderer::new(&device, args.size);
+    if let Some(ticks) = args.compare {
+        return compare::compare(device, queue, &config, ticks);
+    }
+
+    let mut simulation = Simulation::new(&config, |scene| {
+        GpuSolver::new(device.clone(), queue.clone(), scene)
+    })?;
+    if let Some(path) = &args.checkpoint {
+        simulation.load_checkpoint(path)?;
+    }
+
+    let renderer = Renderer::new(&device, config.size);
+    let mut recording = Recording::new(
+        device.clone(),
+        queue.clone(),
+        &config.record,
+        &config.base_path(),
+        simulation.get_size(),
+    )?;
+
+    if args.headless {
+        return headless::run(
+            simulation,
+            args.ticks,
+            |simulation| renderer.screenshot(&device, &queue, simulation),
+            |simulation, finish| match finish {
+                true => recording.finish(),
+                false => recording.update(&renderer, simulation),
+            },
+        );
+    }
 
     let event_loop = EventLoop::new()?;
 
@@ -47,7 +121,7 @@ async fn main() -> Result<()> {
             .with_window_icon(Some(
                 Icon::from_rgba(icon.to_rgba8().to_vec(), icon.width(), icon.height()).unwrap(),
             ))
-            .with_inner_size(PhysicalSize::new(args.size.0, args.size.1))
+            .with_inner_size(PhysicalSize::new(config.size.0, config.size.1))
             .build(&event_loop)?,
     );
 
@@ -56,6 +130,7 @@ async fn main() -> Result<()> {
     let mut app = App {
         simulation,
         renderer,
+        recording,
         egui: Egui::new(&device, &window),
         gui: Default::default(),
         graphics: GraphicsContext {
@@ -80,7 +155,15 @@ async fn main() -> Result<()> {
             }
 
             match event {
-                WindowEvent::CloseRequested => event_loop.exit(),
+                WindowEvent::CloseRequested => {
+                    if let Err(e) = app.simulation.flush() {
+                        eprintln!("Failed to save snapshot: {:?}", e);
+                    }
+                    if let Err(e) = app.recording.finish() {
+                        eprintln!("Failed to save recording: {:?}", e);
+                    }
+                    event_loop.exit();
+                }
                 WindowEvent::RedrawRequested => app.render(),
                 WindowEvent::Resized(_size) => app.configure_surface(),
                 WindowEvent::KeyboardInput {
@@ -96,8 +179,8 @@ async fn main() -> Result<()> {
                     }
 
                     if event.physical_key == PhysicalKey::Code(KeyCode::KeyR) {
-                        app.simulation.reset_states(&app.graphics.queue);
-                        app.simulation.reset_average_energy(&app.graphics.queue);
+                        app.simulation.reset_states();
+                        app.simulation.reset_average_energy();
                     }
                 }
                 _ => {}
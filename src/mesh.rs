@@ -0,0 +1,42 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    config::MeshArgs,
+    misc::mesh::{Mesh, MeshOptions},
+    simulation::snapshot::{Snapshot, SnapshotFormat},
+};
+
+/// Writes a snapshot as a height field mesh, like `scripts/3d_mesh.jl` did for energy snapshots.
+pub fn run(args: &MeshArgs) -> Result<()> {
+    let format = SnapshotFormat::from_path(&args.output).filter(SnapshotFormat::is_mesh);
+    let Some(format) = format else {
+        bail!("Meshes have to be written to a .stl or .ply file.");
+    };
+
+    let snapshot = Snapshot::load(&args.input)?;
+    let options = MeshOptions {
+        scale: args.scale,
+        base: args.base,
+        decimation: args.decimation,
+        log: args.log,
+    };
+    let mesh = Mesh::height_field(snapshot.size, &snapshot.data, &options);
+    let data = match format {
+        SnapshotFormat::Stl => mesh.encode_stl(),
+        _ => mesh.encode_ply(),
+    };
+
+    if let Some(parent) = args.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&args.output, data)
+        .with_context(|| format!("Failed to write {}", args.output.display()))?;
+    println!(
+        "Wrote {} with {} triangles.",
+        args.output.display(),
+        mesh.triangles()
+    );
+    Ok(())
+}
@@ -1,32 +1,32 @@
-use std::{fs::File, io::Read};
+use std::{
+    fs::File,
+    io::{BufWriter, Read},
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Ok, Result};
 use hound::{SampleFormat, WavReader, WavWriter};
+use nalgebra::Vector2;
 use rubato::{
     Resampler, SincFixedIn, SincInterpolationParameters, SincInterpolationType, WindowFunction,
 };
-use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
-    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, Maintain, MapMode,
-};
-
-use crate::GraphicsContext;
-
-const OUTPUT_BUFFER_SIZE: usize = 512;
-const SAMPLE_RATE: u32 = 16_000;
 
-pub struct Audio {
-    pub audio_in_buffer: Buffer,
-    pub audio_in_len: usize,
+pub const OUTPUT_BUFFER_SIZE: usize = 512;
+pub const SAMPLE_RATE: u32 = 16_000;
 
-    pub audio_out_buffer: Buffer,
-    audio_writer: WavWriter<File>,
+pub struct AudioInput {
+    pub samples: Vec<f32>,
+    pub pickup: Vector2<u32>,
+}
 
-    staging_buffer: Buffer,
+pub struct AudioOutput {
+    audio_writer: WavWriter<BufWriter<File>>,
+    path: PathBuf,
+    samples: Vec<f32>,
 }
 
-impl Audio {
-    pub fn new(device: &Device, wav_in: impl Read, wav_out: File) -> Result<Self> {
+impl AudioInput {
+    pub fn new(wav_in: impl Read, pickup: Vector2<u32>) -> Result<Self> {
         let mut audio_in_reader = WavReader::new(wav_in)?;
         let audio_in_spec = audio_in_reader.spec();
         let mut audio_in = match audio_in_spec.sample_format {
@@ -60,75 +60,53 @@ impl Audio {
             audio_in = resampler.process(&[&audio_in], None)?.remove(0);
         }
 
-        let audio_writer = WavWriter::new(
-            wav_out,
+        Ok(Self {
+            samples: audio_in,
+            pickup,
+        })
+    }
+}
+
+impl AudioOutput {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        Ok(Self {
+            audio_writer: Self::create(&path)?,
+            path,
+            samples: Vec::new(),
+        })
+    }
+
+    fn create(path: &Path) -> Result<WavWriter<BufWriter<File>>> {
+        Ok(WavWriter::create(
+            path,
             hound::WavSpec {
                 channels: 1,
                 sample_rate: SAMPLE_RATE,
                 bits_per_sample: 32,
                 sample_format: hound::SampleFormat::Float,
             },
-        )
-        .unwrap();
-
-        let audio_in_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&audio_in),
-            usage: BufferUsages::STORAGE,
-        });
-
-        let buf_size = OUTPUT_BUFFER_SIZE as u64 * 4;
-        let audio_out_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: buf_size,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-
-        let staging_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: buf_size,
-            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
-
-        Ok(Self {
-            audio_in_buffer,
-            audio_in_len: audio_in.len(),
-
-            audio_out_buffer,
-            audio_writer,
-
-            staging_buffer,
-        })
+        )?)
     }
 
-    pub fn tick(&mut self, tick: u64, gc: &GraphicsContext, encoder: &mut CommandEncoder) {
-        if tick > 0 && tick as usize % OUTPUT_BUFFER_SIZE == OUTPUT_BUFFER_SIZE - 1 {
-            encoder.copy_buffer_to_buffer(
-                &self.audio_out_buffer,
-                0,
-                &self.staging_buffer,
-                0,
-                OUTPUT_BUFFER_SIZE as u64 * 4,
-            );
-
-            let slice = self.staging_buffer.slice(..);
-            let (tx, rx) = crossbeam_channel::bounded(1);
-            slice.map_async(MapMode::Read, move |_| tx.send(()).unwrap());
+    pub fn write(&mut self, samples: &[f32]) {
+        self.samples.extend_from_slice(samples);
+        for sample in samples {
+            self.audio_writer
+                .write_sample((1.0 - (-sample.abs()).exp()).copysign(*sample))
+                .unwrap();
+        }
+    }
 
-            gc.device.poll(Maintain::Wait);
+    /// Every sample written so far, before being compressed.
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
 
-            rx.recv().unwrap();
-            let mapped = slice.get_mapped_range();
-            let data = bytemuck::cast_slice::<_, f32>(&mapped);
-            for sample in data {
-                self.audio_writer
-                    .write_sample((1.0 - (-sample.abs()).exp()).copysign(*sample))
-                    .unwrap();
-            }
-            drop(mapped);
-            self.staging_buffer.unmap();
-        }
+    /// Rewrites the output file with only the given samples.
+    pub fn restart(&mut self, samples: &[f32]) -> Result<()> {
+        self.audio_writer = Self::create(&self.path)?;
+        self.samples.clear();
+        self.write(samples);
+        Ok(())
     }
 }
@@ -0,0 +1,157 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use image::{Rgb, RgbImage};
+use nalgebra::{Matrix3, Vector3};
+
+/// First wavelength (nm) in `CIE_1931`.
+const CIE_START: f32 = 380.0;
+/// Spacing (nm) between the rows of `CIE_1931`.
+const CIE_STEP: f32 = 10.0;
+
+/// CIE 1931 2° standard observer color matching functions (x̄, ȳ, z̄) from 380 to 780 nm.
+#[rustfmt::skip]
+#[allow(clippy::excessive_precision)]
+const CIE_1931: [[f32; 3]; 41] = [
+    [0.001368, 0.000039, 0.006450], [0.004243, 0.000120, 0.020050],
+    [0.014310, 0.000396, 0.067850], [0.043510, 0.001210, 0.207400],
+    [0.134380, 0.004000, 0.645600], [0.283900, 0.011600, 1.385600],
+    [0.348280, 0.023000, 1.747060], [0.336200, 0.038000, 1.772110],
+    [0.290800, 0.060000, 1.669200], [0.195360, 0.090980, 1.287640],
+    [0.095640, 0.139020, 0.812950], [0.032010, 0.208020, 0.465180],
+    [0.004900, 0.323000, 0.272000], [0.009300, 0.503000, 0.158200],
+    [0.063270, 0.710000, 0.078250], [0.165500, 0.862000, 0.042160],
+    [0.290400, 0.954000, 0.020300], [0.433450, 0.994950, 0.008750],
+    [0.594500, 0.995000, 0.003900], [0.762100, 0.952000, 0.002100],
+    [0.916300, 0.870000, 0.001650], [1.026300, 0.757000, 0.001100],
+    [1.062200, 0.631000, 0.000800], [1.002600, 0.503000, 0.000340],
+    [0.854450, 0.381000, 0.000190], [0.642400, 0.265000, 0.000050],
+    [0.447900, 0.175000, 0.000020], [0.283500, 0.107000, 0.000000],
+    [0.164900, 0.061000, 0.000000], [0.087400, 0.032000, 0.000000],
+    [0.046770, 0.017000, 0.000000], [0.022700, 0.008210, 0.000000],
+    [0.011359, 0.004102, 0.000000], [0.005790, 0.002091, 0.000000],
+    [0.002899, 0.001047, 0.000000], [0.001440, 0.000520, 0.000000],
+    [0.000690, 0.000249, 0.000000], [0.000332, 0.000120, 0.000000],
+    [0.000166, 0.000060, 0.000000], [0.000083, 0.000030, 0.000000],
+    [0.000042, 0.000015, 0.000000],
+];
+
+/// Linear sRGB from CIE XYZ, with a D65 white point.
+#[rustfmt::skip]
+const XYZ_TO_SRGB: Matrix3<f32> = Matrix3::new(
+    3.2406, -1.5372, -0.4986,
+    -0.9689, 1.8758, 0.0415,
+    0.0557, -0.2040, 1.0570,
+);
+
+/// The color matching functions at a wavelength in nm, linearly interpolated and zero outside the visible range.
+pub fn wavelength_xyz(wavelength: f32) -> Vector3<f32> {
+    let position = (wavelength - CIE_START) / CIE_STEP;
+    if !(0.0..=(CIE_1931.len() - 1) as f32).contains(&position) {
+        return Vector3::zeros();
+    }
+
+    let (index, t) = (position as usize, position.fract());
+    let next = CIE_1931[(index + 1).min(CIE_1931.len() - 1)];
+    Vector3::from(CIE_1931[index]).lerp(&Vector3::from(next), t)
+}
+
+/// Applies the sRGB transfer function to a linear value.
+pub fn srgb_gamma(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// How the added up color of a composite is turned into pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Grading {
+    /// Brightness, where a flat spectrum with an energy of `1 / exposure` in every map comes out at full brightness.
+    pub exposure: f32,
+    /// Display gamma, using the sRGB transfer function without one.
+    pub gamma: Option<f32>,
+    pub white_balance: WhiteBalance,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WhiteBalance {
+    /// Leaves the colors as they are, so a flat spectrum is slightly pink.
+    None,
+    /// Scales each channel so a flat spectrum over the wavelengths used comes out white.
+    Flat,
+    /// Multiplies the red, green and blue channels.
+    Gains([f32; 3]),
+}
+
+/// Colors each energy map by its wavelength (nm) and adds them up into one image.
+pub fn composite(width: u32, height: u32, layers: &[(f32, &[f32])], grading: &Grading) -> RgbImage {
+    let colors = (layers.iter())
+        .map(|(wavelength, _)| wavelength_xyz(*wavelength))
+        .collect::<Vec<_>>();
+    let total = colors.iter().sum::<Vector3<f32>>();
+    let scale = grading.exposure / total.y.max(f32::EPSILON);
+
+    let gains = match grading.white_balance {
+        WhiteBalance::None => Vector3::repeat(1.0),
+        WhiteBalance::Flat => (XYZ_TO_SRGB * total / total.y.max(f32::EPSILON)).map(|x| {
+            if x > 0.0 {
+                1.0 / x
+            } else {
+                0.0
+            }
+        }),
+        WhiteBalance::Gains(gains) => Vector3::from(gains),
+    };
+    let transfer = |x: f32| match grading.gamma {
+        Some(gamma) => x.powf(1.0 / gamma),
+        None => srgb_gamma(x),
+    };
+
+    RgbImage::from_fn(width, height, |x, y| {
+        let i = (y * width + x) as usize;
+        let xyz = (layers.iter().zip(&colors))
+            .map(|((_, energy), color)| color * energy[i])
+            .sum::<Vector3<f32>>();
+        let rgb = (XYZ_TO_SRGB * xyz * scale).component_mul(&gains);
+        Rgb(rgb
+            .map(|x| (transfer(x.clamp(0.0, 1.0)) * 255.0).round() as u8)
+            .into())
+    })
+}
+
+impl Default for Grading {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0,
+            gamma: None,
+            white_balance: WhiteBalance::None,
+        }
+    }
+}
+
+impl FromStr for WhiteBalance {
+    type Err = anyhow::Error;
+
+    /// Parses `none`, `flat` or red, green and blue gains like `1.0,0.9,1.2`.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "none" => WhiteBalance::None,
+            "flat" => WhiteBalance::Flat,
+            _ => {
+                let gains = (s.split(','))
+                    .map(|x| x.trim().parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()
+                    .and_then(|x| <[f32; 3]>::try_from(x).ok())
+                    .with_context(|| {
+                        format!(
+                            "Expected `none`, `flat` or three gains like `1,0.9,1.2`, found `{s}`."
+                        )
+                    })?;
+                WhiteBalance::Gains(gains)
+            }
+        })
+    }
+}
@@ -0,0 +1,168 @@
+use nalgebra::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// How a field is turned into a height field mesh, configured with a `[mesh]` table.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct MeshOptions {
+    /// Height of the surface per unit of the field, in cells.
+    pub scale: f32,
+    /// Thickness of the solid base under the surface in cells, leaving just the surface if zero.
+    pub base: f32,
+    /// Averages square blocks of this many cells into each vertex.
+    pub decimation: u32,
+    /// Scales heights logarithmically over this many decades below the peak, which then reaches `scale`.
+    pub log: Option<f32>,
+}
+
+/// A triangle mesh, with faces wound counterclockwise when seen from outside.
+pub struct Mesh {
+    vertices: Vec<Vector3<f32>>,
+    faces: Vec<[u32; 3]>,
+}
+
+impl Mesh {
+    /// Builds a surface over the field with one cell to a unit, seen from above like the image, on a solid base if it has one.
+    pub fn height_field(size: Vector2<u32>, data: &[f32], options: &MeshOptions) -> Self {
+        let step = options.decimation.max(1) as usize;
+        let (width, height) = (size.x as usize, size.y as usize);
+        let (columns, rows) = (width.div_ceil(step), height.div_ceil(step));
+
+        let mut values = Vec::with_capacity(columns * rows);
+        for y in 0..rows {
+            for x in 0..columns {
+                let xs = x * step..((x + 1) * step).min(width);
+                let ys = y * step..((y + 1) * step).min(height);
+                let count = xs.len() * ys.len();
+                let sum = (ys.flat_map(|y| xs.clone().map(move |x| y * width + x)))
+                    .map(|i| data[i])
+                    .sum::<f32>();
+                values.push(sum / count as f32);
+            }
+        }
+
+        if let Some(decades) = options.log {
+            let peak = values.iter().fold(0.0_f32, |peak, x| peak.max(x.abs()));
+            for value in &mut values {
+                *value = match peak > 0.0 {
+                    true => (1.0 + (value.abs() / peak).log10() / decades).max(0.0),
+                    false => 0.0,
+                };
+            }
+        }
+
+        // Image rows go down, so they are flipped to keep the mesh the same way up when seen from above.
+        let position =
+            |x: usize, y: usize| Vector2::new(x * step, (rows - 1 - y) * step).cast::<f32>();
+        let index = |x: usize, y: usize| (y * columns + x) as u32;
+
+        let mut vertices = Vec::with_capacity(columns * rows);
+        for y in 0..rows {
+            for x in 0..columns {
+                let z = options.base + values[y * columns + x] * options.scale;
+                vertices.push(position(x, y).push(z));
+            }
+        }
+
+        let mut faces = Vec::with_capacity((columns - 1) * (rows - 1) * 2);
+        for y in 0..rows - 1 {
+            for x in 0..columns - 1 {
+                let (a, b) = (index(x, y), index(x + 1, y));
+                let (c, d) = (index(x, y + 1), index(x + 1, y + 1));
+                faces.extend([[a, c, b], [b, c, d]]);
+            }
+        }
+
+        if options.base > 0.0 && columns > 1 && rows > 1 {
+            // Edge vertices going counterclockwise from above, starting at the bottom left corner of the image.
+            let perimeter = ((0..columns - 1).map(|x| (x, rows - 1)))
+                .chain((1..rows).rev().map(|y| (columns - 1, y)))
+                .chain((1..columns).rev().map(|x| (x, 0)))
+                .chain((0..rows - 1).map(|y| (0, y)))
+                .collect::<Vec<_>>();
+
+            let bottom = vertices.len() as u32;
+            for &(x, y) in &perimeter {
+                vertices.push(position(x, y).push(0.0));
+            }
+            let center = vertices.len() as u32;
+            let extent = position(columns - 1, 0);
+            vertices.push((extent / 2.0).push(0.0));
+
+            for i in 0..perimeter.len() {
+                let j = (i + 1) % perimeter.len();
+                let (top_i, top_j) = (
+                    index(perimeter[i].0, perimeter[i].1),
+                    index(perimeter[j].0, perimeter[j].1),
+                );
+                let (bottom_i, bottom_j) = (bottom + i as u32, bottom + j as u32);
+                faces.extend([
+                    [top_i, bottom_i, bottom_j],
+                    [top_i, bottom_j, top_j],
+                    [center, bottom_j, bottom_i],
+                ]);
+            }
+        }
+
+        Self { vertices, faces }
+    }
+
+    pub fn triangles(&self) -> usize {
+        self.faces.len()
+    }
+
+    /// Encodes the mesh as a binary STL file.
+    pub fn encode_stl(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(84 + self.faces.len() * 50);
+        out.extend_from_slice(b"wave-sim height field");
+        out.resize(80, 0);
+        out.extend_from_slice(&(self.faces.len() as u32).to_le_bytes());
+
+        for face in &self.faces {
+            let [a, b, c] = face.map(|x| self.vertices[x as usize]);
+            let normal = (b - a).cross(&(c - a)).try_normalize(0.0);
+            let normal = normal.unwrap_or_else(Vector3::zeros);
+            for vector in [normal, a, b, c] {
+                out.extend(vector.iter().flat_map(|x| x.to_le_bytes()));
+            }
+            out.extend_from_slice(&0_u16.to_le_bytes());
+        }
+
+        out
+    }
+
+    /// Encodes the mesh as a binary PLY file, which shares vertices between faces.
+    pub fn encode_ply(&self) -> Vec<u8> {
+        let header = format!(
+            "ply\nformat binary_little_endian 1.0\ncomment wave-sim height field\n\
+             element vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+             element face {}\nproperty list uchar int vertex_indices\nend_header\n",
+            self.vertices.len(),
+            self.faces.len()
+        );
+
+        let mut out =
+            Vec::with_capacity(header.len() + self.vertices.len() * 12 + self.faces.len() * 13);
+        out.extend_from_slice(header.as_bytes());
+        for vertex in &self.vertices {
+            out.extend(vertex.iter().flat_map(|x| x.to_le_bytes()));
+        }
+        for face in &self.faces {
+            out.push(3);
+            out.extend(face.iter().flat_map(|x| (*x as i32).to_le_bytes()));
+        }
+
+        out
+    }
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            scale: 350.0,
+            base: 14.0,
+            decimation: 1,
+            log: None,
+        }
+    }
+}
@@ -1,5 +1,11 @@
 pub mod ring_buffer;
 pub use ring_buffer::RingBuffer;
 pub mod audio;
+pub mod color;
+pub mod mesh;
+pub mod npy;
 pub mod preprocess;
+pub mod readback;
+pub mod units;
 pub mod util;
+pub mod writer;
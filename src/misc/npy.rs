@@ -0,0 +1,233 @@
+use std::io::{Read, Seek, Write};
+
+use anyhow::{bail, ensure, Context, Result};
+use bytemuck::Pod;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// Types that can be stored in a `.npy` array, along with their NumPy dtype.
+pub trait Element: Pod {
+    const DESCR: &'static str;
+}
+
+impl Element for u8 {
+    const DESCR: &'static str = "|u1";
+}
+
+impl Element for u64 {
+    const DESCR: &'static str = "<u8";
+}
+
+impl Element for f32 {
+    const DESCR: &'static str = "<f4";
+}
+
+impl Element for f64 {
+    const DESCR: &'static str = "<f8";
+}
+
+/// Encodes a C ordered array in the `.npy` version 1.0 format.
+pub fn encode<T: Element>(shape: &[usize], data: &[T]) -> Vec<u8> {
+    debug_assert_eq!(shape.iter().product::<usize>(), data.len());
+
+    let shape = match shape {
+        [x] => format!("({x},)"),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}",
+        T::DESCR
+    );
+
+    // The data has to start on a multiple of 64 bytes, after the magic, version and header length.
+    let length = 10 + header.len() + 1;
+    header.push_str(&" ".repeat(length.next_multiple_of(64) - length));
+    header.push('\n');
+
+    let data = bytemuck::cast_slice::<T, u8>(data);
+    let mut out = Vec::with_capacity(10 + header.len() + data.len());
+    out.extend_from_slice(b"\x93NUMPY\x01\x00");
+    out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    out.extend_from_slice(header.as_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+/// Decodes a C ordered `.npy` array of `T`, returning its shape and data.
+pub fn decode<T: Element>(bytes: &[u8]) -> Result<(Vec<usize>, Vec<T>)> {
+    ensure!(bytes.starts_with(b"\x93NUMPY"), "Not a .npy file.");
+    ensure!(bytes.len() >= 10, "File is too short.");
+    let (header_len, start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 if bytes.len() >= 12 => (
+            u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+            12,
+        ),
+        x => bail!("Unsupported version {x}."),
+    };
+    ensure!(bytes.len() >= start + header_len, "File is too short.");
+    let header = std::str::from_utf8(&bytes[start..start + header_len])?;
+
+    let value = |key: &str| {
+        let start = header.find(&format!("'{key}':"))? + key.len() + 3;
+        Some(header[start..].trim_start())
+    };
+    let descr = (value("descr"))
+        .and_then(|x| x.strip_prefix('\'')?.split('\'').next())
+        .context("Header is missing the dtype.")?;
+    ensure!(
+        descr == T::DESCR,
+        "Expected a dtype of {}, found {descr}.",
+        T::DESCR
+    );
+    ensure!(
+        value("fortran_order").is_some_and(|x| x.starts_with("False")),
+        "Fortran ordered arrays are not supported."
+    );
+    let shape = (value("shape"))
+        .and_then(|x| x.strip_prefix('(')?.split(')').next())
+        .context("Header is missing the shape.")?
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| Ok(x.parse()?))
+        .collect::<Result<Vec<usize>>>()?;
+
+    let data = &bytes[start + header_len..];
+    let count = shape.iter().product::<usize>();
+    ensure!(
+        data.len() == count * size_of::<T>(),
+        "Expected {count} values for a shape of {shape:?}, found {} bytes.",
+        data.len()
+    );
+    let data = (data.chunks_exact(size_of::<T>()))
+        .map(bytemuck::pod_read_unaligned)
+        .collect();
+    Ok((shape, data))
+}
+
+/// Reads arrays from a `.npz` archive, like `numpy.load`.
+pub struct NpzReader<R: Read + Seek> {
+    zip: ZipArchive<R>,
+}
+
+impl<R: Read + Seek> NpzReader<R> {
+    pub fn new(reader: R) -> Result<Self> {
+        Ok(Self {
+            zip: ZipArchive::new(reader)?,
+        })
+    }
+
+    /// Reads the array saved under `name`, returning its shape and data.
+    pub fn get<T: Element>(&mut self, name: &str) -> Result<(Vec<usize>, Vec<T>)> {
+        let mut file = (self.zip.by_name(&format!("{name}.npy")))
+            .with_context(|| format!("Missing array `{name}`."))?;
+        let mut bytes = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut bytes)?;
+        decode(&bytes).with_context(|| format!("Invalid array `{name}`"))
+    }
+}
+
+/// Writes an uncompressed `.npz` archive, like `numpy.savez`.
+pub struct NpzWriter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+}
+
+impl<W: Write + Seek> NpzWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            zip: ZipWriter::new(writer),
+        }
+    }
+
+    /// Adds an array that will be loaded under `name`.
+    pub fn add<T: Element>(&mut self, name: &str, shape: &[usize], data: &[T]) -> Result<()> {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(size_of_val(data) >= u32::MAX as usize);
+        self.zip.start_file(format!("{name}.npy"), options)?;
+        self.zip.write_all(&encode(shape, data))?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<W> {
+        Ok(self.zip.finish()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{decode, encode, NpzReader, NpzWriter};
+
+    #[test]
+    fn round_trips_arrays() {
+        let data = [1.0f32, -2.5, 3.0, f32::MIN_POSITIVE, 5.0, 6.0];
+        let bytes = encode(&[2, 3], &data);
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(bytes[9 + header_len], b'\n');
+        assert_eq!(decode::<f32>(&bytes).unwrap(), (vec![2, 3], data.to_vec()));
+
+        let data = [1u64, u64::MAX, 3];
+        assert_eq!(
+            decode::<u64>(&encode(&[3], &data)).unwrap(),
+            (vec![3], data.to_vec())
+        );
+        assert_eq!(
+            decode::<f64>(&encode(&[], &[0.5])).unwrap(),
+            (vec![], vec![0.5])
+        );
+    }
+
+    #[test]
+    fn decodes_version_two_headers() {
+        let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (2,), }\n";
+        let mut bytes = b"\x93NUMPY\x02\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        for value in [1.0f32, 2.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        assert_eq!(decode::<f32>(&bytes).unwrap(), (vec![2], vec![1.0, 2.0]));
+    }
+
+    #[test]
+    fn rejects_mismatched_arrays() {
+        let bytes = encode(&[2, 2], &[1.0f32; 4]);
+        assert!(decode::<f64>(&bytes).is_err());
+        assert!(decode::<f32>(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode::<f32>(&bytes[1..]).is_err());
+
+        let mut fortran = bytes;
+        let start = (fortran.windows(5)).position(|x| x == b"False").unwrap();
+        fortran[start..start + 5].copy_from_slice(b"True ");
+        assert!(decode::<f32>(&fortran).is_err());
+    }
+
+    #[test]
+    fn round_trips_archives() {
+        let mut npz = NpzWriter::new(Cursor::new(Vec::new()));
+        npz.add("field", &[2, 2], &[1.0f32, 2.0, 3.0, 4.0]).unwrap();
+        npz.add("tick", &[], &[7u64]).unwrap();
+        let bytes = npz.finish().unwrap().into_inner();
+
+        let mut npz = NpzReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(npz.get::<u64>("tick").unwrap(), (vec![], vec![7]));
+        assert_eq!(
+            npz.get::<f32>("field").unwrap(),
+            (vec![2, 2], vec![1.0, 2.0, 3.0, 4.0])
+        );
+        assert!(npz.get::<f32>("energy").is_err());
+        assert!(npz.get::<f64>("field").is_err());
+    }
+}
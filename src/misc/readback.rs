@@ -0,0 +1,105 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::{Context, Result};
+use bytemuck::Pod;
+use wgpu::{
+    Buffer, BufferAddress, BufferAsyncError, BufferDescriptor, BufferUsages,
+    CommandEncoderDescriptor, Device, Maintain, MapMode, Queue,
+};
+
+/// Copies buffers back to the CPU without stalling, through a pool of staging buffers.
+/// Finished copies are returned by `poll` in the order they were requested.
+pub struct Readback<T> {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+
+    pool: Vec<Buffer>,
+    pending: VecDeque<Pending<T>>,
+}
+
+struct Pending<T> {
+    tag: T,
+    buffer: Buffer,
+    /// Set once mapping the buffer has finished or failed.
+    mapped: Arc<OnceLock<Result<(), BufferAsyncError>>>,
+}
+
+impl<T> Readback<T> {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        Self {
+            device,
+            queue,
+
+            pool: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Submits a copy of `size` bytes from `source`, after any work already submitted.
+    pub fn read(&mut self, source: &Buffer, offset: BufferAddress, size: BufferAddress, tag: T) {
+        let buffer = match self.pool.iter().position(|x| x.size() == size) {
+            Some(index) => self.pool.swap_remove(index),
+            None => self.device.create_buffer(&BufferDescriptor {
+                label: None,
+                size,
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(source, offset, &buffer, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let mapped = Arc::new(OnceLock::new());
+        let result = mapped.clone();
+        (buffer.slice(..)).map_async(MapMode::Read, move |x| {
+            let _ = result.set(x);
+        });
+
+        self.pending.push_back(Pending {
+            tag,
+            buffer,
+            mapped,
+        });
+    }
+
+    /// Returns the copies that have finished, waiting for all of them if `wait` is set.
+    /// A copy that failed to map is returned as an error once the ones before it have been returned.
+    pub fn poll<D: Pod>(&mut self, wait: bool) -> Result<Vec<(T, Vec<D>)>> {
+        if self.pending.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.device
+            .poll(if wait { Maintain::Wait } else { Maintain::Poll });
+
+        let mut out = Vec::new();
+        while let Some(pending) = self.pending.front() {
+            match pending.mapped.get() {
+                None => break,
+                Some(Err(_)) if !out.is_empty() => break,
+                Some(Err(e)) => {
+                    let e = e.clone();
+                    self.pending.pop_front();
+                    return Err(e).context("Failed to read back a buffer");
+                }
+                Some(Ok(())) => {}
+            }
+
+            let Pending { tag, buffer, .. } = self.pending.pop_front().unwrap();
+            let data = bytemuck::cast_slice(&buffer.slice(..).get_mapped_range()).to_vec();
+            buffer.unmap();
+
+            self.pool.push(buffer);
+            out.push((tag, data));
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,327 @@
+use std::fmt;
+
+use anyhow::{bail, Context, Result};
+use serde::{
+    de::{self, DeserializeSeed, SeqAccess, Visitor},
+    Deserializer,
+};
+
+/// SI prefixes, with `u` and `µ` both accepted for micro.
+const PREFIXES: [(&str, f64); 14] = [
+    ("a", 1e-18),
+    ("f", 1e-15),
+    ("p", 1e-12),
+    ("n", 1e-9),
+    ("u", 1e-6),
+    ("µ", 1e-6),
+    ("μ", 1e-6),
+    ("m", 1e-3),
+    ("c", 1e-2),
+    ("k", 1e3),
+    ("M", 1e6),
+    ("G", 1e9),
+    ("T", 1e12),
+    ("P", 1e15),
+];
+
+/// Base units and their powers of meters and seconds.
+const UNITS: [(&str, (i8, i8)); 3] = [("m", (1, 0)), ("s", (0, 1)), ("Hz", (0, -1))];
+
+/// What a quantity measures, which its unit has to agree with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Length,
+    Time,
+    Speed,
+    /// Frequencies and rates, written in `Hz` or `1/s`.
+    Frequency,
+}
+
+impl Dimension {
+    const ALL: [Dimension; 4] = [
+        Dimension::Length,
+        Dimension::Time,
+        Dimension::Speed,
+        Dimension::Frequency,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dimension::Length => "length",
+            Dimension::Time => "time",
+            Dimension::Speed => "speed",
+            Dimension::Frequency => "frequency",
+        }
+    }
+
+    /// The SI unit values of this dimension are stored in.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Dimension::Length => "m",
+            Dimension::Time => "s",
+            Dimension::Speed => "m/s",
+            Dimension::Frequency => "Hz",
+        }
+    }
+
+    fn example(&self) -> &'static str {
+        match self {
+            Dimension::Length => "5 um",
+            Dimension::Time => "11 ms",
+            Dimension::Speed => "340.29 m/s",
+            Dimension::Frequency => "430 THz",
+        }
+    }
+
+    /// Powers of meters and seconds.
+    fn exponents(&self) -> (i8, i8) {
+        match self {
+            Dimension::Length => (1, 0),
+            Dimension::Time => (0, 1),
+            Dimension::Speed => (1, -1),
+            Dimension::Frequency => (0, -1),
+        }
+    }
+}
+
+/// Parses a value with a unit, like `5 um`, `430 THz` or `340.29 m/s`, into SI units of the given dimension.
+pub fn parse(text: &str, dimension: Dimension) -> Result<f64> {
+    let text = text.trim();
+    let split = (text.char_indices())
+        .find(|(_, c)| !(c.is_ascii_digit() || "+-._eE".contains(*c)))
+        .map_or(text.len(), |(i, _)| i);
+    let (number, unit) = (&text[..split], text[split..].trim());
+
+    let value = (number.replace('_', "").parse::<f64>())
+        .with_context(|| format!("`{text}` doesn't start with a number."))?;
+    if unit.is_empty() {
+        bail!(
+            "`{text}` has no unit, expected a {} like `{}`.",
+            dimension.name(),
+            dimension.example()
+        );
+    }
+
+    let (numerator, denominator) = unit.split_once('/').unwrap_or((unit, "1"));
+    let (Some((scale, top)), Some((divisor, bottom))) =
+        (parse_unit(numerator.trim()), parse_unit(denominator.trim()))
+    else {
+        bail!("Unknown unit `{unit}` in `{text}`.");
+    };
+
+    let exponents = (top.0 - bottom.0, top.1 - bottom.1);
+    if exponents != dimension.exponents() {
+        let found = Dimension::ALL.iter().find(|x| x.exponents() == exponents);
+        bail!(
+            "Expected a {} like `{}`, but `{unit}` is {}.",
+            dimension.name(),
+            dimension.example(),
+            found.map_or("not one".into(), |x| format!("a {}", x.name()))
+        );
+    }
+
+    Ok(value * scale / divisor)
+}
+
+/// Parses a single unit with an optional prefix, like `um` or `THz`, into its scale and powers of meters and seconds.
+fn parse_unit(unit: &str) -> Option<(f64, (i8, i8))> {
+    if unit == "1" {
+        return Some((1.0, (0, 0)));
+    }
+
+    UNITS.iter().find_map(|(symbol, exponents)| {
+        let prefix = unit.strip_suffix(symbol)?;
+        let scale = match prefix {
+            "" => 1.0,
+            _ => PREFIXES.iter().find(|x| x.0 == prefix)?.1,
+        };
+        Some((scale, *exponents))
+    })
+}
+
+/// Config fields that hold quantities, so the same `deserialize_with` functions work for single values, optional ones and lists.
+pub trait Quantities<'de>: Sized {
+    fn deserialize_as<D: Deserializer<'de>>(
+        deserializer: D,
+        dimension: Dimension,
+    ) -> Result<Self, D::Error>;
+}
+
+impl<'de> Quantities<'de> for f32 {
+    fn deserialize_as<D: Deserializer<'de>>(
+        deserializer: D,
+        dimension: Dimension,
+    ) -> Result<Self, D::Error> {
+        QuantitySeed(dimension).deserialize(deserializer)
+    }
+}
+
+impl<'de> Quantities<'de> for Option<f32> {
+    fn deserialize_as<D: Deserializer<'de>>(
+        deserializer: D,
+        dimension: Dimension,
+    ) -> Result<Self, D::Error> {
+        // Missing values are handled with `#[serde(default)]`, as TOML has no null.
+        QuantitySeed(dimension).deserialize(deserializer).map(Some)
+    }
+}
+
+impl<'de> Quantities<'de> for Vec<f32> {
+    fn deserialize_as<D: Deserializer<'de>>(
+        deserializer: D,
+        dimension: Dimension,
+    ) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(QuantityList(dimension))
+    }
+}
+
+pub fn length<'de, D: Deserializer<'de>, T: Quantities<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    T::deserialize_as(deserializer, Dimension::Length)
+}
+
+pub fn time<'de, D: Deserializer<'de>, T: Quantities<'de>>(deserializer: D) -> Result<T, D::Error> {
+    T::deserialize_as(deserializer, Dimension::Time)
+}
+
+pub fn speed<'de, D: Deserializer<'de>, T: Quantities<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    T::deserialize_as(deserializer, Dimension::Speed)
+}
+
+pub fn frequency<'de, D: Deserializer<'de>, T: Quantities<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    T::deserialize_as(deserializer, Dimension::Frequency)
+}
+
+/// Reads a number in SI units or a string with a unit.
+#[derive(Clone, Copy)]
+struct QuantitySeed(Dimension);
+
+impl<'de> DeserializeSeed<'de> for QuantitySeed {
+    type Value = f32;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<f32, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for QuantitySeed {
+    type Value = f32;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "a number in {} or a {} like `{}`",
+            self.0.symbol(),
+            self.0.name(),
+            self.0.example()
+        )
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<f32, E> {
+        Ok(value as f32)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<f32, E> {
+        Ok(value as f32)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<f32, E> {
+        Ok(value as f32)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<f32, E> {
+        parse(value, self.0).map(|x| x as f32).map_err(E::custom)
+    }
+}
+
+struct QuantityList(Dimension);
+
+impl<'de> Visitor<'de> for QuantityList {
+    type Value = Vec<f32>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a list of {} values", self.0.name())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<f32>, A::Error> {
+        let mut out = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element_seed(QuantitySeed(self.0))? {
+            out.push(value);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::{parse, Dimension};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() <= b.abs() * 1e-12
+    }
+
+    #[test]
+    fn parses_prefixed_units() {
+        assert!(close(parse("5 um", Dimension::Length).unwrap(), 5e-6));
+        assert!(close(parse("5µm", Dimension::Length).unwrap(), 5e-6));
+        assert!(close(parse(" 81 mm ", Dimension::Length).unwrap(), 0.081));
+        assert!(close(parse("62.5 us", Dimension::Time).unwrap(), 62.5e-6));
+        assert!(close(
+            parse("430 THz", Dimension::Frequency).unwrap(),
+            4.3e14
+        ));
+        assert!(close(parse("1.5e3 m", Dimension::Length).unwrap(), 1500.0));
+        assert!(close(parse("1_000 Hz", Dimension::Frequency).unwrap(), 1e3));
+    }
+
+    #[test]
+    fn parses_ratios() {
+        assert!(close(
+            parse("340.29 m/s", Dimension::Speed).unwrap(),
+            340.29
+        ));
+        assert!(close(parse("3 km/ms", Dimension::Speed).unwrap(), 3e6));
+        assert!(close(parse("0.5 1/s", Dimension::Frequency).unwrap(), 0.5));
+        assert!(parse("2 m/m", Dimension::Speed).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_quantities() {
+        assert!(parse("5", Dimension::Length).is_err());
+        assert!(parse("um", Dimension::Length).is_err());
+        assert!(parse("5 xm", Dimension::Length).is_err());
+        assert!(parse("5 ft", Dimension::Length).is_err());
+
+        let error = parse("5 ms", Dimension::Length).unwrap_err().to_string();
+        assert!(error.contains("is a time"), "{error}");
+        let error = parse("5 m/s/s", Dimension::Speed).unwrap_err().to_string();
+        assert!(error.contains("Unknown unit"), "{error}");
+    }
+
+    #[test]
+    fn deserializes_numbers_and_strings() {
+        #[derive(Deserialize)]
+        struct Fields {
+            #[serde(deserialize_with = "super::length")]
+            plain: f32,
+            #[serde(deserialize_with = "super::length")]
+            unit: f32,
+            #[serde(deserialize_with = "super::frequency")]
+            list: Vec<f32>,
+        }
+
+        let fields: Fields =
+            toml::from_str("plain = 2\nunit = \"3 cm\"\nlist = [1.5, \"2 kHz\"]").unwrap();
+        assert_eq!(fields.plain, 2.0);
+        assert_eq!(fields.unit, 0.03);
+        assert_eq!(fields.list, [1.5, 2000.0]);
+        assert!(toml::from_str::<Fields>("plain = \"2 s\"\nunit = 1\nlist = []").is_err());
+    }
+}
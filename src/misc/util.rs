@@ -1,13 +1,13 @@
 use std::{
     fs,
+    io::ErrorKind,
     path::{Path, PathBuf},
     time::SystemTime,
 };
 
 use anyhow::Result;
-use wgpu::{Buffer, MaintainBase, MapMode};
-
-use crate::GraphicsContext;
+use bytemuck::Pod;
+use wgpu::{Buffer, Device, MaintainBase, MapMode};
 
 pub fn save_dated_file(base: impl AsRef<Path>, name: &str, ext: &str) -> Result<PathBuf> {
     let base = base.as_ref();
@@ -16,19 +16,9 @@ pub fn save_dated_file(base: impl AsRef<Path>, name: &str, ext: &str) -> Result<
         fs::create_dir_all(base)?;
     }
 
-    let timestamp = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
+    let timestamp = timestamp();
     for i in 0.. {
-        let name = if i == 0 {
-            format!("{name}-{timestamp}.{ext}")
-        } else {
-            format!("{name}-{timestamp}-{i}.{ext}")
-        };
-
-        let path = base.join(name);
+        let path = base.join(format!("{}.{ext}", dated_name(name, timestamp, i)));
         if !path.exists() {
             return Ok(path);
         }
@@ -37,16 +27,49 @@ pub fn save_dated_file(base: impl AsRef<Path>, name: &str, ext: &str) -> Result<
     unreachable!()
 }
 
-pub fn download_buffer(buffer: &Buffer, gc: &GraphicsContext) -> Vec<u8> {
+/// Creates a new directory named like the files from `save_dated_file`.
+pub fn save_dated_dir(base: impl AsRef<Path>, name: &str) -> Result<PathBuf> {
+    let base = base.as_ref();
+    fs::create_dir_all(base)?;
+
+    let timestamp = timestamp();
+    for i in 0.. {
+        let path = base.join(dated_name(name, timestamp, i));
+        match fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    unreachable!()
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn dated_name(name: &str, timestamp: u64, i: u32) -> String {
+    if i == 0 {
+        format!("{name}-{timestamp}")
+    } else {
+        format!("{name}-{timestamp}-{i}")
+    }
+}
+
+pub fn download_buffer<T: Pod>(buffer: &Buffer, device: &Device) -> Vec<T> {
     let slice = buffer.slice(..);
 
     let (tx, rx) = crossbeam_channel::bounded(1);
     slice.map_async(MapMode::Read, move |_| tx.send(()).unwrap());
 
-    gc.device.poll(MaintainBase::Wait);
+    device.poll(MaintainBase::Wait);
     rx.recv().unwrap();
 
-    let data = slice.get_mapped_range().to_vec();
+    let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
     buffer.unmap();
 
     data
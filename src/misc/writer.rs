@@ -0,0 +1,65 @@
+use std::thread::{self, JoinHandle};
+
+use anyhow::Result;
+use crossbeam_channel::{Receiver, Sender};
+
+type Job = Box<dyn FnOnce() -> Result<()> + Send>;
+
+/// Runs file writes on a background thread, in the order they were pushed.
+/// Dropping it waits for the remaining writes.
+pub struct Writer {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Result<()>>,
+    queued: usize,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        let (jobs, job_rx) = crossbeam_channel::unbounded::<Job>();
+        let (result_tx, results) = crossbeam_channel::unbounded();
+
+        let handle = thread::spawn(move || {
+            for job in job_rx {
+                let _ = result_tx.send(job());
+            }
+        });
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            queued: 0,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn push(&mut self, job: impl FnOnce() -> Result<()> + Send + 'static) {
+        self.jobs.as_ref().unwrap().send(Box::new(job)).unwrap();
+        self.queued += 1;
+    }
+
+    /// Checks the jobs that have finished, returning the first error.
+    pub fn poll(&mut self) -> Result<()> {
+        let results = self.results.try_iter().collect::<Vec<_>>();
+        self.queued -= results.len();
+        results.into_iter().collect()
+    }
+
+    /// Waits for every job to finish, returning the first error.
+    pub fn flush(&mut self) -> Result<()> {
+        let results = (0..self.queued)
+            .map(|_| self.results.recv().unwrap())
+            .collect::<Vec<_>>();
+        self.queued = 0;
+        results.into_iter().collect()
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        drop(self.jobs.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{ensure, Result};
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame, RgbaImage,
+};
+use nalgebra::Vector2;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Device, Extent3d,
+    ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, Origin3d, Queue, Texture, TextureAspect,
+    TextureDescriptor, TextureDimension, TextureUsages, TextureViewDescriptor,
+    COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+use crate::{
+    app::TEXTURE_FORMAT,
+    config::{RecordConfig, RecordInterval},
+    misc::{readback::Readback, util::save_dated_dir, writer::Writer},
+    renderer::Renderer,
+    simulation::Simulation,
+};
+
+type Animation = Arc<Mutex<Option<GifEncoder<BufWriter<File>>>>>;
+
+/// Captures the whole domain as frames while `Simulation::record` is enabled, writing them in the background.
+pub struct Recording {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    size: Vector2<u32>,
+    /// Bytes in each row of `copy`, padded to the alignment needed for copies out of textures.
+    row_bytes: u32,
+
+    texture: Texture,
+    copy: Buffer,
+    readback: Readback<u64>,
+    writer: Writer,
+
+    images: bool,
+    /// Directory frames are written to, picked when the first one is.
+    output: Option<PathBuf>,
+    animation: Option<(PathBuf, Animation)>,
+    fps: f32,
+
+    /// Number of frames captured so far, which numbers the next one.
+    frames: u64,
+    last_tick: Option<u64>,
+    /// Frames rendered since the last capture.
+    skipped: u64,
+}
+
+impl Recording {
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        config: &RecordConfig,
+        base_path: &Path,
+        size: Vector2<u32>,
+    ) -> Result<Self> {
+        ensure!(
+            !matches!(
+                config.interval,
+                RecordInterval::Ticks(0) | RecordInterval::Frames(0)
+            ),
+            "Recording interval must be at least one."
+        );
+        ensure!(config.fps > 0.0, "Recording frame rate must be positive.");
+        if let Some(path) = &config.animation {
+            ensure!(
+                path.extension().is_some_and(|x| x == "gif"),
+                "Animations must be written to a .gif file."
+            );
+        }
+
+        let extent = Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: None,
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TEXTURE_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let row_bytes = (size.x * 4).next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let copy = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: row_bytes as u64 * size.y as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            readback: Readback::new(device.clone(), queue.clone()),
+            device,
+            queue,
+            size,
+            row_bytes,
+
+            texture,
+            copy,
+            writer: Writer::new(),
+
+            images: config.images,
+            output: config.output.as_ref().map(|x| base_path.join(x)),
+            animation: (config.animation.as_ref())
+                .map(|x| (base_path.join(x), Arc::new(Mutex::new(None)))),
+            fps: config.fps,
+
+            frames: 0,
+            last_tick: None,
+            skipped: 0,
+        })
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Captures a frame if one is due, and writes the frames that have been read back.
+    /// Frames are only captured once the simulation has advanced, so nothing is recorded while paused.
+    pub fn update(&mut self, renderer: &Renderer, simulation: &Simulation) -> Result<()> {
+        let record = &simulation.record;
+        let tick = simulation.parameters.tick;
+        if record.enabled && self.last_tick != Some(tick) {
+            self.skipped += 1;
+            let due = match record.interval {
+                RecordInterval::Ticks(ticks) => {
+                    let ticks = ticks.max(1);
+                    self.last_tick
+                        .is_none_or(|last| tick / ticks != last / ticks)
+                }
+                RecordInterval::Frames(frames) => {
+                    self.last_tick.is_none() || self.skipped >= frames
+                }
+            };
+
+            if due {
+                self.capture(renderer, simulation)?;
+                self.last_tick = Some(tick);
+                self.skipped = 0;
+            }
+        }
+
+        self.write(false)?;
+        self.writer.poll()
+    }
+
+    /// Waits for every captured frame to be written and finishes the animation.
+    pub fn finish(&mut self) -> Result<()> {
+        self.write(true)?;
+        if let Some((_, animation)) = &self.animation {
+            let animation = animation.clone();
+            self.writer.push(move || {
+                // Dropping the encoder writes the end of the file.
+                animation.lock().unwrap().take();
+                Ok(())
+            });
+        }
+        self.writer.flush()
+    }
+
+    fn capture(&mut self, renderer: &Renderer, simulation: &Simulation) -> Result<()> {
+        if self.images && self.output.is_none() {
+            self.output = Some(save_dated_dir("recordings", "recording")?);
+        }
+        if let Some(output) = self.output.as_ref().filter(|_| self.images) {
+            fs::create_dir_all(output)?;
+        }
+
+        let mut encoder =
+            (self.device).create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let view = self.texture.create_view(&TextureViewDescriptor::default());
+        renderer.render_domain(&self.device, &self.queue, simulation, &mut encoder, &view);
+
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &self.copy,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            self.texture.size(),
+        );
+        self.queue.submit([encoder.finish()]);
+
+        (self.readback).read(&self.copy, 0, self.copy.size(), self.frames);
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Starts writing the frames that have been read back.
+    fn write(&mut self, wait: bool) -> Result<()> {
+        for (frame, data) in self.readback.poll::<u8>(wait)? {
+            let (size, row_bytes) = (self.size, self.row_bytes as usize);
+            let output = self.output.clone().filter(|_| self.images);
+            let animation = self.animation.clone();
+            let delay = Delay::from_saturating_duration(Duration::from_secs_f32(1.0 / self.fps));
+
+            self.writer.push(move || {
+                // Rows are padded and the texture is BGRA.
+                let pixels = (data.chunks_exact(row_bytes))
+                    .flat_map(|row| row[..size.x as usize * 4].chunks_exact(4))
+                    .flat_map(|x| [x[2], x[1], x[0], 255])
+                    .collect();
+                let image = RgbaImage::from_raw(size.x, size.y, pixels).unwrap();
+
+                if let Some(output) = output {
+                    image.save(output.join(format!("{frame:06}.png")))?;
+                }
+
+                if let Some((path, animation)) = animation {
+                    let mut animation = animation.lock().unwrap();
+                    if animation.is_none() {
+                        if let Some(parent) = path.parent() {
+                            fs::create_dir_all(parent)?;
+                        }
+                        let file = BufWriter::new(File::create(&path)?);
+                        let mut encoder = GifEncoder::new_with_speed(file, 10);
+                        encoder.set_repeat(Repeat::Infinite)?;
+                        *animation = Some(encoder);
+                    }
+                    let encoder = animation.as_mut().unwrap();
+                    encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+                }
+
+                Ok(())
+            });
+        }
+
+        Ok(())
+    }
+}
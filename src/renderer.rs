@@ -9,13 +9,13 @@ use wgpu::{
     ColorTargetState, ColorWrites, CommandEncoder, CommandEncoderDescriptor, Device, Extent3d,
     Face, FragmentState, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, IndexFormat, LoadOp,
     Maintain, MapMode, MultisampleState, Operations, Origin3d, PipelineLayoutDescriptor,
-    PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+    PrimitiveState, Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
     RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, StoreOp,
     TextureAspect, TextureDescriptor, TextureDimension, TextureUsages, TextureView,
     TextureViewDescriptor, VertexState, COPY_BYTES_PER_ROW_ALIGNMENT,
 };
 
-use crate::{app::TEXTURE_FORMAT, misc::util, App};
+use crate::{app::TEXTURE_FORMAT, misc::util, simulation::Simulation};
 
 pub struct Renderer {
     render_pipeline: RenderPipeline,
@@ -27,6 +27,8 @@ pub struct Renderer {
     pub zoom: f32,
     pub gain: f32,
     pub energy_gain: f32,
+    /// Index of the lock-in frequency shown by the amplitude and phase views.
+    pub lock_in_frequency: usize,
 }
 
 #[derive(ShaderType, Default)]
@@ -41,6 +43,7 @@ pub struct RenderContext {
 
     pan: Vector2<f32>,
     zoom: f32,
+    lock_in_offset: u32,
 }
 
 impl Renderer {
@@ -98,6 +101,16 @@ impl Renderer {
                     ty: state_layout_type,
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -143,12 +156,67 @@ impl Renderer {
             zoom: 1.0,
             gain: 1.0,
             energy_gain: 1.0,
+            lock_in_frequency: 0,
+        }
+    }
+
+    pub fn render(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        simulation: &Simulation,
+        window: Vector2<u32>,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+    ) {
+        let context = self.context(simulation, window);
+        self.draw(device, queue, simulation, &context, encoder, view);
+    }
+
+    /// Renders the whole domain at one pixel per cell, ignoring the pan and zoom of the window.
+    pub fn render_domain(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        simulation: &Simulation,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+    ) {
+        let context = RenderContext {
+            pan: Vector2::zeros(),
+            zoom: 1.0,
+            ..self.context(simulation, simulation.get_size())
+        };
+        self.draw(device, queue, simulation, &context, encoder, view);
+    }
+
+    fn context(&self, simulation: &Simulation, window: Vector2<u32>) -> RenderContext {
+        let size = simulation.get_size();
+        let frequencies = simulation.lock_in().map_or(1, |x| x.frequency_count());
+        RenderContext {
+            size,
+            window,
+            tick: simulation.parameters.tick as u32,
+            flags: simulation.parameters.flags.bits(),
+
+            gain: self.gain,
+            energy_gain: self.energy_gain,
+            pan: self.pan,
+            zoom: self.zoom.powi(2),
+            lock_in_offset: self.lock_in_frequency.min(frequencies - 1) as u32 * size.x * size.y,
         }
     }
 
-    pub fn render(&self, app: &App, encoder: &mut CommandEncoder, view: &TextureView) {
-        let gc = &app.graphics;
-        let bind_group = gc.device.create_bind_group(&BindGroupDescriptor {
+    fn draw(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        simulation: &Simulation,
+        context: &RenderContext,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
             layout: &self.bind_group_layout,
             entries: &[
                 BindGroupEntry {
@@ -157,36 +225,26 @@ impl Renderer {
                 },
                 BindGroupEntry {
                     binding: 1,
-                    resource: app.simulation.get_state().as_entire_binding(),
+                    resource: simulation.solver().get_state().as_entire_binding(),
                 },
                 BindGroupEntry {
                     binding: 2,
-                    resource: app
-                        .simulation
+                    resource: simulation
+                        .solver()
                         .get_average_energy_buffer()
                         .as_entire_binding(),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: simulation.solver().get_lock_in_buffer().as_entire_binding(),
+                },
             ],
             label: None,
         });
 
-        let window = app.graphics.window.inner_size();
-        let mut context = UniformBuffer::new(Vec::new());
-        context
-            .write(&RenderContext {
-                size: app.simulation.get_size(),
-                window: Vector2::new(window.width, window.height),
-                tick: app.simulation.parameters.tick as u32,
-                flags: app.simulation.parameters.flags.bits(),
-
-                gain: self.gain,
-                energy_gain: self.energy_gain,
-                pan: self.pan,
-                zoom: self.zoom.powi(2),
-            })
-            .unwrap();
-        gc.queue
-            .write_buffer(&self.context, 0, &context.into_inner());
+        let mut buffer = UniformBuffer::new(Vec::new());
+        buffer.write(context).unwrap();
+        queue.write_buffer(&self.context, 0, &buffer.into_inner());
 
         let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
             label: None,
@@ -208,11 +266,15 @@ impl Renderer {
         render_pass.draw_indexed(0..6, 0, 0..1);
     }
 
-    pub fn screenshot(&self, app: &App) -> Result<()> {
-        let gc = &app.graphics;
-        let size = app.simulation.get_size();
+    pub fn screenshot(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        simulation: &Simulation,
+    ) -> Result<()> {
+        let size = simulation.get_size();
 
-        let texture = gc.device.create_texture(&TextureDescriptor {
+        let texture = device.create_texture(&TextureDescriptor {
             label: None,
             size: Extent3d {
                 width: size.x,
@@ -229,19 +291,17 @@ impl Renderer {
 
         const ALIGNMENT_BYTES: u64 = COPY_BYTES_PER_ROW_ALIGNMENT as u64 - 1;
         let row_bytes = (size.x as u64 * 4 + ALIGNMENT_BYTES) & !ALIGNMENT_BYTES;
-        let screenshot_buffer = gc.device.create_buffer(&wgpu::BufferDescriptor {
+        let screenshot_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: None,
             size: row_bytes * size.y as u64,
             usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
             mapped_at_creation: false,
         });
 
-        let mut encoder = gc
-            .device
-            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
 
         let view = texture.create_view(&TextureViewDescriptor::default());
-        self.render(app, &mut encoder, &view);
+        self.render(device, queue, simulation, size, &mut encoder, &view);
 
         encoder.copy_texture_to_buffer(
             ImageCopyTexture {
@@ -265,13 +325,13 @@ impl Renderer {
             },
         );
 
-        gc.queue.submit([encoder.finish()]);
+        queue.submit([encoder.finish()]);
 
         let screenshot_slice = screenshot_buffer.slice(..);
         let (tx, rx) = crossbeam_channel::bounded(1);
         screenshot_slice.map_async(MapMode::Read, move |_| tx.send(()).unwrap());
 
-        gc.device.poll(Maintain::Wait);
+        device.poll(Maintain::Wait);
         rx.recv().unwrap();
 
         let data = screenshot_slice.get_mapped_range();
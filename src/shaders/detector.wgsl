@@ -0,0 +1,20 @@
+@group(0) @binding(0) var<uniform> ctx: Context;
+@group(0) @binding(1) var<storage, read> field: array<f32>;
+@group(0) @binding(2) var<storage, read> cells: array<u32>;
+@group(0) @binding(3) var<storage, read_write> values: array<f32>;
+
+struct Context {
+    // Index of the first cell of the time level to read
+    offset: u32,
+    count: u32,
+}
+
+// Copies the value of each listed cell, so detectors only read back what they need
+@compute
+@workgroup_size(64, 1, 1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let i = global_id.x;
+    if i < ctx.count {
+        values[i] = field[ctx.offset + cells[i]];
+    }
+}
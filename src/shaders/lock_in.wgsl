@@ -0,0 +1,33 @@
+@group(0) @binding(0) var<uniform> ctx: Context;
+@group(0) @binding(1) var<storage, read> states: array<f32>;
+@group(0) @binding(2) var<storage, read> phases: array<vec2<f32>>;
+@group(0) @binding(3) var<storage, read_write> lock_in: array<vec2<f32>>;
+
+struct Context {
+    size: vec2<u32>,
+    // Index of the first cell of the time level just computed
+    offset: u32,
+    frequencies: u32,
+    // Samples already in the means
+    samples: u32,
+}
+
+// Running mean of 2u·e^(-iωt) for each frequency, given (cos ωt, sin ωt)
+@compute
+@workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if x >= ctx.size.x || y >= ctx.size.y {
+        return;
+    }
+
+    let cell = y * ctx.size.x + x;
+    let u = states[ctx.offset + cell];
+    let n = f32(ctx.samples);
+    for (var i = 0u; i < ctx.frequencies; i++) {
+        let phase = phases[i];
+        let j = ctx.size.x * ctx.size.y * i + cell;
+        lock_in[j] = lock_in[j] * (n / (n + 1.0)) + 2.0 * u * vec2<f32>(phase.x, -phase.y) / (n + 1.0);
+    }
+}
@@ -1,20 +1,25 @@
 @group(0) @binding(0) var<uniform> ctx: Context;
 @group(0) @binding(1) var<storage, read> states: array<f32>;
 @group(0) @binding(2) var<storage, read> average_energy: array<f32>;
+@group(0) @binding(3) var<storage, read> lock_in: array<vec2<f32>>;
 
 struct Context {
     size: vec2<u32>,
     window: vec2<u32>,
 
     tick: u32,
-    // 1 << 0: reflective boundary
-    // 1 << 1: energy_view
+    // 1 << 0: energy_view
+    // 1 << 1: bilinear sampling
+    // 1 << 2: lock-in amplitude view
+    // 1 << 3: lock-in phase view
     flags: u32,
     gain: f32,
     energy_gain: f32,
 
     pan: vec2<f32>,
     zoom: f32,
+    // Index of the first cell of the lock-in frequency to show
+    lock_in_offset: u32,
 }
 
 // VERTEX SHADER //
@@ -49,6 +54,29 @@ const COLOR_SCHEME: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
     vec3<f32>(1.0, 1.0, 1.0),
 );
 
+const TAU: f32 = 6.28318530717958647692528676655900577;
+
+fn scheme_color(value: f32) -> vec3<f32> {
+    let scheme_index = u32(value * 3.0);
+    let val = value * 3.0 - f32(scheme_index);
+
+    // clean code i swear
+    var color = vec3<f32>(0.0, 0.0, 0.0);
+    if (scheme_index == 0) {
+        color = COLOR_SCHEME[0] * (1.0 - val) + COLOR_SCHEME[1] * val;
+    } else if (scheme_index == 1) {
+        color = COLOR_SCHEME[1] * (1.0 - val) + COLOR_SCHEME[2] * val;
+    } else if (scheme_index == 2) {
+        color = COLOR_SCHEME[2] * (1.0 - val) + COLOR_SCHEME[3] * val;
+    } else if (scheme_index == 3) {
+        color = COLOR_SCHEME[3] * (1.0 - val) + COLOR_SCHEME[4] * val;
+    } else if (scheme_index == 4) {
+        color = COLOR_SCHEME[4] * (1.0 - val) + COLOR_SCHEME[5] * val;
+    }
+
+    return color;
+}
+
 fn index(x: u32, y: u32, n: u32) -> u32 {
     return (ctx.size.x * ctx.size.y * n) + (y * ctx.size.x) + x;
 }
@@ -73,13 +101,37 @@ fn bilinear_sample_avg_energy(origin: vec2<f32>, pos: vec2<i32>) -> f32 {
 }
 
 fn sample_avg_energy(origin: vec2<f32>, pos: vec2<i32>) -> f32 {
-    if (ctx.flags & 0x04) != 0 {
+    if (ctx.flags & 0x02) != 0 {
         return bilinear_sample_avg_energy(origin, pos);
     } else {
         return nearest_neighbor_sample_avg_energy(pos);
     }
 }
 
+fn nearest_neighbor_sample_lock_in(pos: vec2<i32>) -> vec2<f32> {
+    return lock_in[ctx.lock_in_offset + u32(pos.y) * ctx.size.x + u32(pos.x)];
+}
+
+fn bilinear_sample_lock_in(origin: vec2<f32>, pos: vec2<i32>) -> vec2<f32> {
+    let x = vec2<i32>(max(0, min(i32(ctx.size.x) - 1, pos.x)), max(0, min(i32(ctx.size.x) - 1, pos.x + 1)));
+    let y = vec2<i32>(max(0, min(i32(ctx.size.y) - 1, pos.y)), max(0, min(i32(ctx.size.y) - 1, pos.y + 1)));
+
+    let f = fract((origin - ctx.pan) * ctx.zoom);
+    return mix(
+        mix(nearest_neighbor_sample_lock_in(vec2<i32>(x.x, y.x)), nearest_neighbor_sample_lock_in(vec2<i32>(x.y, y.x)), f.x),
+        mix(nearest_neighbor_sample_lock_in(vec2<i32>(x.x, y.y)), nearest_neighbor_sample_lock_in(vec2<i32>(x.y, y.y)), f.x),
+        f.y
+    );
+}
+
+fn sample_lock_in(origin: vec2<f32>, pos: vec2<i32>) -> vec2<f32> {
+    if (ctx.flags & 0x02) != 0 {
+        return bilinear_sample_lock_in(origin, pos);
+    } else {
+        return nearest_neighbor_sample_lock_in(pos);
+    }
+}
+
 fn nearest_neighbor_sample(pos: vec2<i32>, tick: u32) -> f32 {
     return states[index(u32(pos.x), u32(pos.y), tick % 3)];
 }
@@ -100,7 +152,7 @@ fn bilinear_sample(origin: vec2<f32>, pos: vec2<i32>, tick: u32) -> f32 {
 }
 
 fn sample(origin: vec2<f32>, pos: vec2<i32>) -> f32 {
-    if (ctx.flags & 0x04) != 0 {
+    if (ctx.flags & 0x02) != 0 {
         return bilinear_sample(origin, pos, ctx.tick);
     } else {
         return nearest_neighbor_sample(pos, ctx.tick);
@@ -119,26 +171,22 @@ fn frag(in: VertexOutput) -> @location(0) vec4<f32> {
     }
 
 
-    if (ctx.flags & 0x02) != 0 {
-        var val = clamp(sample_avg_energy(in.position.xy, pos) * ctx.energy_gain, 0.0, 1.0);
-        let scheme_index = u32(val * 3.0);
-        val = val * 3.0 - f32(scheme_index);
-
-        // clean code i swear
-        var color = vec3<f32>(0.0, 0.0, 0.0);
-        if (scheme_index == 0) {
-            color = COLOR_SCHEME[0] * (1.0 - val) + COLOR_SCHEME[1] * val;
-        } else if (scheme_index == 1) {
-            color = COLOR_SCHEME[1] * (1.0 - val) + COLOR_SCHEME[2] * val;
-        } else if (scheme_index == 2) {
-            color = COLOR_SCHEME[2] * (1.0 - val) + COLOR_SCHEME[3] * val;
-        } else if (scheme_index == 3) {
-            color = COLOR_SCHEME[3] * (1.0 - val) + COLOR_SCHEME[4] * val;
-        } else if (scheme_index == 4) {
-            color = COLOR_SCHEME[4] * (1.0 - val) + COLOR_SCHEME[5] * val;
-        }
-
-        return vec4<f32>(color, 1.0);
+    if (ctx.flags & 0x01) != 0 {
+        let val = clamp(sample_avg_energy(in.position.xy, pos) * ctx.energy_gain, 0.0, 1.0);
+        return vec4<f32>(scheme_color(val), 1.0);
+    }
+
+    if (ctx.flags & 0x04) != 0 {
+        let val = clamp(length(sample_lock_in(in.position.xy, pos)) * ctx.gain, 0.0, 1.0);
+        return vec4<f32>(scheme_color(val), 1.0);
+    }
+
+    if (ctx.flags & 0x08) != 0 {
+        // Hue from the phase, darkened where the amplitude is small
+        let value = sample_lock_in(in.position.xy, pos);
+        let hue = fract(atan2(value.y, value.x) / TAU);
+        let color = clamp(abs(fract(hue + vec3<f32>(1.0, 2.0 / 3.0, 1.0 / 3.0)) * 6.0 - 3.0) - 1.0, vec3(0.0), vec3(1.0));
+        return vec4<f32>(color * clamp(length(value) * ctx.gain, 0.0, 1.0), 1.0);
     }
 
     let val = sample(in.position.xy, pos) * ctx.gain;
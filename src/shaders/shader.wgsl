@@ -11,6 +11,25 @@ fn tick(x: u32, y: u32, mul: ptr<function, f32>, distance: ptr<function, f32>, c
 @group(0) @binding(5) var<storage, read_write> audio_out: array<f32>;
 // #endif
 
+// #if PML
+@group(0) @binding(6) var<storage, read_write> pml: array<vec2<f32>>;
+// #endif
+
+@group(0) @binding(7) var<storage, read> sources: array<Source>;
+
+const ABSORBING: u32 = 0u;
+const DIRICHLET: u32 = 1u;
+const NEUMANN: u32 = 2u;
+const PERIODIC: u32 = 3u;
+
+// Central difference weights from the center outward, see `stencil_weights`
+// #if STENCIL_4
+const STENCIL: array<f32, 3> = array<f32, 3>(-5.0 / 2.0, 4.0 / 3.0, -1.0 / 12.0);
+// #endif
+// #if STENCIL_6
+const STENCIL: array<f32, 4> = array<f32, 4>(-49.0 / 18.0, 3.0 / 2.0, -3.0 / 20.0, 1.0 / 90.0);
+// #endif
+
 const PI: f32 = 3.14159265358979323846264338327950288;
 const TAU: f32 = 2.0 * PI;
 
@@ -22,12 +41,26 @@ struct Context {
     tick: u32,
     ticks_per_dispatch: u32,
     flags: u32,
-    // 1 << 0: reflective boundary
-    // 1 << 1: energy_view
+    // 1 << 0: energy_view
+    // 1 << 1: bilinear sampling
+    // 1 << 2: lock-in amplitude view
+    // 1 << 3: lock-in phase view
+    boundaries: u32,
+    // Two bits per edge in the order left, right, top, bottom
+    sources: u32,
 
     c: f32,
     amplitude: f32,
     frequency: f32,
+    loss: f32,
+    // Damping per tick of cells with a map alpha of 255
+}
+
+// See `SourceContext`
+struct Source {
+    position: vec2<f32>,
+    width: f32,
+    value: f32,
 }
 
 fn index(x: u32, y: u32, n: u32) -> u32 {
@@ -44,12 +77,108 @@ fn get_map(x: u32, y: u32) -> vec4<u32> {
     );
 }
 
+// Condition of an edge, in the order left, right, top, bottom.
+fn boundary(edge: u32) -> u32 {
+    return (ctx.boundaries >> (edge * 2)) & 0x03;
+}
+
+// The first edge the cell lies on with its own update, 4 if there is none.
+// Stencils read across Neumann and periodic edges instead.
+fn closed_edge(x: u32, y: u32) -> u32 {
+    if x == 0 && boundary(0u) < NEUMANN {
+        return 0u;
+    } else if x == ctx.size.x - 1 && boundary(1u) < NEUMANN {
+        return 1u;
+    } else if y == 0 && boundary(2u) < NEUMANN {
+        return 2u;
+    } else if y == ctx.size.y - 1 && boundary(3u) < NEUMANN {
+        return 3u;
+    }
+
+    return 4u;
+}
+
+fn inward_offset(edge: u32) -> vec2<i32> {
+    switch edge {
+        case 0u: { return vec2(1, 0); }
+        case 1u: { return vec2(-1, 0); }
+        case 2u: { return vec2(0, 1); }
+        default: { return vec2(0, -1); }
+    }
+}
+
+// Index of a nearby cell, wrapping around periodic edges and mirroring across the others.
+fn neighbor(x: u32, y: u32, offset: vec2<i32>, n: u32) -> u32 {
+    let size = vec2<i32>(ctx.size);
+    var pos = vec2<i32>(i32(x), i32(y)) + offset;
+
+    if pos.x < 0 {
+        pos.x = select(-pos.x, pos.x + size.x, boundary(0u) == PERIODIC);
+    } else if pos.x >= size.x {
+        pos.x = select(2 * (size.x - 1) - pos.x, pos.x - size.x, boundary(1u) == PERIODIC);
+    }
+
+    if pos.y < 0 {
+        pos.y = select(-pos.y, pos.y + size.y, boundary(2u) == PERIODIC);
+    } else if pos.y >= size.y {
+        pos.y = select(2 * (size.y - 1) - pos.y, pos.y - size.y, boundary(3u) == PERIODIC);
+    }
+
+    return index(u32(pos.x), u32(pos.y), n);
+}
+
+// Distance to the closest edge with its own update, which wide stencils can't read past.
+fn closed_distance(x: u32, y: u32) -> u32 {
+    var out = max(ctx.size.x, ctx.size.y);
+    if boundary(0u) < NEUMANN {
+        out = min(out, x);
+    }
+    if boundary(1u) < NEUMANN {
+        out = min(out, ctx.size.x - 1 - x);
+    }
+    if boundary(2u) < NEUMANN {
+        out = min(out, y);
+    }
+    if boundary(3u) < NEUMANN {
+        out = min(out, ctx.size.y - 1 - y);
+    }
+
+    return out;
+}
+
+// Cells too close to a closed edge for the wide stencil fall back to the second order one.
+fn laplacian(x: u32, y: u32, n: u32) -> f32 {
+    // #if STENCIL_RADIUS
+    if closed_distance(x, y) >= STENCIL_RADIUS {
+        var weights = STENCIL;
+        var out = 2.0 * weights[0] * states[index(x, y, n)];
+        for (var i = 1; i <= i32(STENCIL_RADIUS); i++) {
+            out += weights[i] * (states[neighbor(x, y, vec2(-i, 0), n)]
+                + states[neighbor(x, y, vec2(i, 0), n)]
+                + states[neighbor(x, y, vec2(0, -i), n)]
+                + states[neighbor(x, y, vec2(0, i), n)]);
+        }
+        return out;
+    }
+    // #endif
+
+    return states[neighbor(x, y, vec2(-1, 0), n)]
+        + states[neighbor(x, y, vec2(1, 0), n)]
+        + states[neighbor(x, y, vec2(0, -1), n)]
+        + states[neighbor(x, y, vec2(0, 1), n)]
+        - 4.0 * states[index(x, y, n)];
+}
+
 @compute
 @workgroup_size(8, 8, 1)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let tick = ctx.tick;
     let x = global_id.x;
     let y = global_id.y;
+    if x >= ctx.size.x || y >= ctx.size.y {
+        return;
+    }
+
     let map_value = get_map(x, y);
 
     var mul = f32(map_value.r == 0);
@@ -64,34 +193,30 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let ni = index(x, y, next);
     let ci = index(x, y, current);
 
-    if (ctx.flags & 0x01) == 0 {
-        if x == 0 {
-            states[ni] = states[ci] + (states[index(x + 1, y, current)] - states[ci]) * ctx.c;
-            return;
-        } else if x == ctx.size.x - 1 {
-            states[ni] = states[ci] - (states[ci] - states[index(x - 1, y, current)]) * ctx.c;
-            return;
-        } else if y == 0 {
-            states[ni] = states[ci] + (states[index(x, y + 1, current)] - states[ci]) * ctx.c;
-            return;
-        } else if y == ctx.size.y - 1 {
-            states[ni] = states[ci] - (states[ci] - states[index(x, y - 1, current)]) * ctx.c;
-            return;
+    // #if PML
+    if in_pml(x, y) {
+        states[ni] = pml_tick(x, y, c) * mul;
+        return;
+    }
+    // #endif
+
+    let edge = closed_edge(x, y);
+    if edge < 4u {
+        if boundary(edge) == DIRICHLET {
+            states[ni] = 0.0;
+        } else {
+            // First order one-way wave equation
+            let inward = states[neighbor(x, y, inward_offset(edge), current)];
+            states[ni] = states[ci] + (inward - states[ci]) * ctx.c;
         }
-    } else if x == 0 || y == 0 || x == ctx.size.x - 1 || y == ctx.size.y - 1 {
-        states[ni] = 0.0;
         return;
     }
 
-    states[ni] = 2.0 * states[index(x, y, current)]
-        - states[index(x, y, last)]
-        + pow(c, 2.0) * (
-            states[index(x - 1, y, current)]
-            + states[index(x + 1, y, current)]
-            + states[index(x, y - 1, current)]
-            + states[index(x, y + 1, current)]
-            - 4.0 * states[index(x, y, current)]
-        );
+    // Central difference of the damping term, see `Parameters::loss`
+    let loss = ctx.loss * f32(map_value.a) / 255.0;
+    states[ni] = (2.0 * states[ci]
+        - (1.0 - loss / 2.0) * states[index(x, y, last)]
+        + pow(c, 2.0) * laplacian(x, y, current)) / (1.0 + loss / 2.0);
     states[ni] *= mul;
 
     // #if OSCILLATOR
@@ -106,6 +231,79 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     states[ni] += ctx.amplitude * exp(-abs(distance)) * audio_in[ctx.tick];
     // #endif
 
+    for (var i = 0u; i < ctx.sources; i++) {
+        states[ni] += source_value(sources[i], vec2<f32>(f32(x), f32(y)));
+    }
+
     let nd = f32(tick) + 1.0;
     average_energy[index(x, y, 0u)] = average_energy[index(x, y, 0u)] * (f32(tick) / nd) + pow(states[ni], 2.0) / nd;
 }
+
+fn source_value(source: Source, pos: vec2<f32>) -> f32 {
+    let distance = distance(pos, source.position) / source.width;
+    return source.value * exp(-distance * distance / 2.0);
+}
+
+// #if PML
+// Includes one extra cell so the first interior cell sees the flux out of the layer.
+fn in_pml(x: u32, y: u32) -> bool {
+    return (boundary(0u) == ABSORBING && x <= PML)
+        || (boundary(1u) == ABSORBING && x >= ctx.size.x - 1 - PML)
+        || (boundary(2u) == ABSORBING && y <= PML)
+        || (boundary(3u) == ABSORBING && y >= ctx.size.y - 1 - PML);
+}
+
+// Quadratic damping profile, tuned for a normal incidence reflection of 1e-3, stronger profiles go unstable.
+fn pml_sigma(pos: vec2<f32>) -> vec2<f32> {
+    let size = vec2<f32>(ctx.size - vec2(1u)) - f32(PML);
+    let near = select(vec2(0.0), f32(PML) - pos, vec2(boundary(0u) == ABSORBING, boundary(2u) == ABSORBING));
+    let far = select(vec2(0.0), pos - size, vec2(boundary(1u) == ABSORBING, boundary(3u) == ABSORBING));
+    let depth = max(max(near, far), vec2(0.0)) / f32(PML);
+    return 3.0 * log(1e3) / 2.0 * ctx.c / f32(PML) * depth * depth;
+}
+
+// Second order PML from Grote and Sim, with the auxiliary field stored on the +x and +y cell faces.
+fn pml_tick(x: u32, y: u32, c: f32) -> f32 {
+    if closed_edge(x, y) < 4u {
+        return 0.0;
+    }
+
+    let current = (ctx.tick + 2) % 3;
+    let last = (ctx.tick + 1) % 3;
+    let write = ctx.tick % 2;
+    let read = (ctx.tick + 1) % 2;
+
+    let u = states[index(x, y, current)];
+    let right = states[neighbor(x, y, vec2(1, 0), current)];
+    let down = states[neighbor(x, y, vec2(0, 1), current)];
+
+    // The auxiliary field is odd across a symmetry plane
+    let psi = pml[index(x, y, read)];
+    var back = -psi;
+    if x > 0 || boundary(0u) == PERIODIC {
+        back.x = pml[neighbor(x, y, vec2(-1, 0), read)].x;
+    }
+    if y > 0 || boundary(2u) == PERIODIC {
+        back.y = pml[neighbor(x, y, vec2(0, -1), read)].y;
+    }
+    let divergence = psi.x - back.x + psi.y - back.y;
+
+    let c2 = pow(c, 2.0);
+    let pos = vec2<f32>(f32(x), f32(y));
+    let sigma = pml_sigma(pos);
+    let s = sigma.x + sigma.y;
+
+    let sx = pml_sigma(pos + vec2(0.5, 0.0));
+    let sy = pml_sigma(pos + vec2(0.0, 0.5));
+    pml[index(x, y, write)] = vec2<f32>(
+        ((1.0 - sx.x / 2.0) * psi.x + c2 * (sx.y - sx.x) * (right - u)) / (1.0 + sx.x / 2.0),
+        ((1.0 - sy.y / 2.0) * psi.y + c2 * (sy.x - sy.y) * (down - u)) / (1.0 + sy.y / 2.0),
+    );
+
+    return (2.0 * u
+        - (1.0 - s / 2.0) * states[index(x, y, last)]
+        - sigma.x * sigma.y * u
+        + c2 * laplacian(x, y, current)
+        + divergence) / (1.0 + s / 2.0);
+}
+// #endif
@@ -0,0 +1,47 @@
+@group(0) @binding(0) var<uniform> ctx: Context;
+@group(0) @binding(1) var<storage, read> states: array<f32>;
+@group(0) @binding(2) var<storage, read_write> peak: Peak;
+
+struct Context {
+    size: vec2<u32>,
+    // Index of the first cell of the time level to search
+    offset: u32,
+}
+
+// See `Peak`, with the magnitude stored as bits
+struct Peak {
+    magnitude: atomic<u32>,
+    index: atomic<u32>,
+}
+
+var<workgroup> local_max: atomic<u32>;
+
+// Clearing the sign bit gives bits that order the same as the magnitudes, with NaN above infinity
+fn magnitude(x: u32, y: u32) -> u32 {
+    if x >= ctx.size.x || y >= ctx.size.y {
+        return 0u;
+    }
+
+    return bitcast<u32>(states[ctx.offset + y * ctx.size.x + x]) & 0x7FFFFFFFu;
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn find_max(@builtin(global_invocation_id) global_id: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    atomicMax(&local_max, magnitude(global_id.x, global_id.y));
+    workgroupBarrier();
+
+    if local == 0u {
+        atomicMax(&peak.magnitude, atomicLoad(&local_max));
+    }
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn find_index(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let x = global_id.x;
+    let y = global_id.y;
+    if x < ctx.size.x && y < ctx.size.y && magnitude(x, y) == atomicLoad(&peak.magnitude) {
+        atomicMin(&peak.index, y * ctx.size.x + x);
+    }
+}
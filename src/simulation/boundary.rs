@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+/// Condition applied to one edge of the domain.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Boundary {
+    /// Lets waves leave the domain, through the PML if one is configured.
+    #[default]
+    Absorbing = 0,
+    /// Holds the outermost cells at zero.
+    Dirichlet = 1,
+    /// Mirrors the field across the outermost cells, making the edge a symmetry plane.
+    Neumann = 2,
+    /// Wraps around to the opposite edge, which must also be periodic.
+    Periodic = 3,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Boundaries {
+    pub left: Boundary,
+    pub right: Boundary,
+    pub top: Boundary,
+    pub bottom: Boundary,
+}
+
+impl Boundary {
+    pub const ALL: [Boundary; 4] = [
+        Boundary::Absorbing,
+        Boundary::Dirichlet,
+        Boundary::Neumann,
+        Boundary::Periodic,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Boundary::Absorbing => "Absorbing",
+            Boundary::Dirichlet => "Dirichlet",
+            Boundary::Neumann => "Neumann",
+            Boundary::Periodic => "Periodic",
+        }
+    }
+
+    /// Whether stencils read across the edge, rather than the outermost cells having their own update.
+    pub fn reads_across(&self) -> bool {
+        matches!(self, Boundary::Neumann | Boundary::Periodic)
+    }
+}
+
+impl Boundaries {
+    pub fn uniform(boundary: Boundary) -> Self {
+        Self {
+            left: boundary,
+            right: boundary,
+            top: boundary,
+            bottom: boundary,
+        }
+    }
+
+    /// Periodic edges only make sense in opposite pairs.
+    pub fn is_valid(&self) -> bool {
+        (self.left == Boundary::Periodic) == (self.right == Boundary::Periodic)
+            && (self.top == Boundary::Periodic) == (self.bottom == Boundary::Periodic)
+    }
+
+    /// Packs the edges into two bits each, in the order left, right, top, bottom.
+    pub fn bits(&self) -> u32 {
+        self.left as u32
+            | (self.right as u32) << 2
+            | (self.top as u32) << 4
+            | (self.bottom as u32) << 6
+    }
+}
@@ -0,0 +1,159 @@
+use std::{fs, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    lock_in::LockIn,
+    solver::{Solver, SolverState},
+    Simulation, SimulationParameters,
+};
+
+/// Identifies checkpoint files.
+const MAGIC: &[u8; 8] = b"WAVECKPT";
+const VERSION: u32 = 1;
+
+/// Describes the data in a checkpoint file, stored as TOML.
+///
+/// The file is made of the magic bytes `WAVECKPT`, the format version and header length as little-endian u32s, the header, then little-endian f32s for:
+/// - all three time levels
+/// - the average energy
+/// - both levels of the PML's auxiliary field, if it has one
+/// - the lock-in means of each frequency, if it has one
+/// - the audio pickup samples
+#[derive(Serialize, Deserialize)]
+struct CheckpointHeader {
+    size: (u32, u32),
+    stencil_order: u32,
+    pml_thickness: u32,
+    /// Number of lock-in frequencies, zero without a lock-in.
+    lock_in: usize,
+    /// Every pickup sample since the start of the run.
+    audio_samples: usize,
+    parameters: SimulationParameters,
+}
+
+impl<S: Solver> Simulation<S> {
+    /// Writes everything needed to continue the run from the current tick.
+    pub fn save_checkpoint(&mut self, path: &Path) -> Result<()> {
+        let tick = self.parameters.tick;
+        let state = self.solver.save_state(tick);
+        let audio = match &self.audio {
+            Some((_, output)) => [output.samples(), &state.audio].concat(),
+            None => Vec::new(),
+        };
+
+        let header = CheckpointHeader {
+            size: (self.size.x, self.size.y),
+            stencil_order: self.stencil_order,
+            pml_thickness: self.pml_thickness,
+            lock_in: self.lock_in_frequencies(),
+            audio_samples: audio.len(),
+            parameters: self.parameters.clone(),
+        };
+        let header = toml::to_string(&header)?;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        out.extend_from_slice(header.as_bytes());
+        for data in [
+            &state.states,
+            &state.average_energy,
+            bytemuck::cast_slice(&state.pml),
+            bytemuck::cast_slice(&state.lock_in),
+            &audio,
+        ] {
+            out.extend(data.iter().flat_map(|x| x.to_le_bytes()));
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, out)?;
+        Ok(())
+    }
+
+    /// Continues from a checkpoint of the same scene, leaving the simulation paused.
+    pub fn load_checkpoint(&mut self, path: &Path) -> Result<()> {
+        let bytes = fs::read(path)?;
+        self.parse_checkpoint(&bytes)
+            .with_context(|| format!("Invalid checkpoint {}", path.display()))
+    }
+
+    fn parse_checkpoint(&mut self, bytes: &[u8]) -> Result<()> {
+        ensure!(
+            bytes.len() >= 16 && bytes.starts_with(MAGIC),
+            "Not a checkpoint."
+        );
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let (version, header_len) = (u32_at(8), u32_at(12) as usize);
+        ensure!(version == VERSION, "Unsupported version {version}.");
+        ensure!(bytes.len() >= 16 + header_len, "File is too short.");
+
+        let header = std::str::from_utf8(&bytes[16..16 + header_len])?;
+        let header = toml::from_str::<CheckpointHeader>(header)?;
+        ensure!(
+            header.size == (self.size.x, self.size.y),
+            "Checkpoint is {}×{} but the simulation is {}×{}.",
+            header.size.0,
+            header.size.1,
+            self.size.x,
+            self.size.y
+        );
+        ensure!(
+            header.stencil_order == self.stencil_order
+                && header.pml_thickness == self.pml_thickness
+                && header.lock_in == self.lock_in_frequencies(),
+            "Checkpoint has a different stencil order, PML thickness or number of lock-in frequencies."
+        );
+
+        let cells = (self.size.x * self.size.y) as usize;
+        let pml = if self.pml_thickness > 0 { 4 * cells } else { 0 };
+        let lock_in = 2 * cells * header.lock_in;
+        let mut data = (bytes[16 + header_len..].chunks_exact(4))
+            .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        ensure!(
+            data.len() == 4 * cells + pml + lock_in + header.audio_samples,
+            "File has the wrong length."
+        );
+
+        let mut audio = data.split_off(4 * cells + pml + lock_in);
+        let lock_in = data.split_off(4 * cells + pml);
+        let pml = data.split_off(4 * cells);
+        let average_energy = data.split_off(3 * cells);
+
+        let tick = header.parameters.tick;
+        if let Some((_, output)) = &mut self.audio {
+            let buffered = self.solver.buffered_audio(tick);
+            ensure!(audio.len() >= buffered, "Checkpoint is missing audio.");
+            let buffered = audio.split_off(audio.len() - buffered);
+            output.restart(&audio)?;
+            audio = buffered;
+        } else {
+            audio.clear();
+        }
+
+        self.solver.load_state(
+            tick,
+            SolverState {
+                states: data,
+                average_energy,
+                pml: bytemuck::cast_vec(pml),
+                lock_in: bytemuck::cast_vec(lock_in),
+                audio,
+            },
+        );
+
+        self.parameters = header.parameters;
+        self.lock_in.iter_mut().for_each(LockIn::clear);
+        self.parameters.running = false;
+        self.backup_tick = None;
+        self.peak_check = None;
+        self.instability = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Result};
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::misc::npy;
+
+use super::{snapshot::SnapshotType, solver::Solver, Simulation};
+
+/// Records the field over some cells every `interval` ticks, configured with `[[detector]]` or `add_detector` in scripts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Detector {
+    pub shape: Shape,
+    #[serde(default)]
+    pub quantity: Quantity,
+    /// Ticks between samples.
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// First tick to sample on.
+    #[serde(default)]
+    pub start: u64,
+    /// A `.csv` or `.npy` file the samples are written to once the run finishes.
+    pub output: PathBuf,
+}
+
+/// Cells a detector covers, in cell coordinates.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    Point([u32; 2]),
+    /// Every cell along the segment between two points, recorded separately.
+    Line([[u32; 2]; 2]),
+    /// The mean over a rectangle, given by two opposite corners.
+    Rect([[u32; 2]; 2]),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantity {
+    #[default]
+    Field,
+    /// The square of the field.
+    Intensity,
+    /// The running average of the squared field, as shown in the energy view.
+    Energy,
+}
+
+/// A detector's cells and the samples recorded so far.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    pub detector: Detector,
+    path: PathBuf,
+    /// Index of each cell covered by the detector.
+    cells: Vec<u32>,
+    /// Column names for CSV output.
+    columns: Vec<String>,
+
+    /// Read ids that haven't arrived yet, with the tick and time they were taken on.
+    pending: Vec<(u64, u64, f64)>,
+    pub samples: Vec<(u64, f64, Vec<f32>)>,
+}
+
+fn default_interval() -> u64 {
+    1
+}
+
+impl Shape {
+    pub fn cells(&self) -> Vec<Vector2<u32>> {
+        match *self {
+            Shape::Point([x, y]) => vec![Vector2::new(x, y)],
+            Shape::Line([start, end]) => {
+                let (start, end) = (Vector2::from(start).cast(), Vector2::from(end).cast());
+                let delta: Vector2<f32> = end - start;
+                let steps = delta.x.abs().max(delta.y.abs()) as u32;
+                (0..=steps)
+                    .map(|i| {
+                        let t = if steps == 0 {
+                            0.0
+                        } else {
+                            i as f32 / steps as f32
+                        };
+                        (start + delta * t).map(|x| x.round() as u32)
+                    })
+                    .collect()
+            }
+            Shape::Rect([a, b]) => {
+                let (x0, x1) = (a[0].min(b[0]), a[0].max(b[0]));
+                let (y0, y1) = (a[1].min(b[1]), a[1].max(b[1]));
+                (y0..=y1)
+                    .flat_map(|y| (x0..=x1).map(move |x| Vector2::new(x, y)))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Recorder {
+    pub fn new(detector: Detector, base_path: &Path, size: Vector2<u32>) -> Result<Self> {
+        let extension = detector.output.extension().and_then(|x| x.to_str());
+        if !matches!(extension, Some("csv" | "npy")) {
+            bail!("Detectors must be written to a .csv or .npy file.");
+        }
+        ensure!(
+            detector.interval > 0,
+            "Detector interval must be at least one."
+        );
+
+        let cells = detector.shape.cells();
+        if let Some(cell) = cells.iter().find(|x| x.x >= size.x || x.y >= size.y) {
+            bail!(
+                "Detector cell ({}, {}) is outside of the {}×{} domain.",
+                cell.x,
+                cell.y,
+                size.x,
+                size.y
+            );
+        }
+
+        let columns = match detector.shape {
+            Shape::Point(_) => vec!["value".into()],
+            Shape::Line(_) => cells.iter().map(|x| format!("x{}_y{}", x.x, x.y)).collect(),
+            Shape::Rect(_) => vec!["mean".into()],
+        };
+
+        Ok(Self {
+            path: base_path.join(&detector.output),
+            cells: cells.iter().map(|x| x.y * size.x + x.x).collect(),
+            columns,
+            detector,
+
+            pending: Vec::new(),
+            samples: Vec::new(),
+        })
+    }
+
+    /// Stores the cells of a finished read, returning the data if it belongs to another detector.
+    pub fn receive(&mut self, id: u64, data: Vec<f32>) -> Option<Vec<f32>> {
+        let Some(index) = self.pending.iter().position(|x| x.0 == id) else {
+            return Some(data);
+        };
+
+        let (_, tick, time) = self.pending.remove(index);
+        let mut values = match self.detector.quantity {
+            Quantity::Intensity => data.iter().map(|x| x * x).collect(),
+            Quantity::Field | Quantity::Energy => data,
+        };
+
+        if let Shape::Rect(_) = self.detector.shape {
+            values = vec![values.iter().sum::<f32>() / values.len() as f32];
+        }
+
+        self.samples.push((tick, time, values));
+        None
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.samples.clear();
+    }
+
+    /// Drops the samples taken after `tick`, which the simulation has been rolled back to.
+    pub fn rollback(&mut self, tick: u64) {
+        self.pending.retain(|x| x.1 <= tick);
+        self.samples.retain(|x| x.0 <= tick);
+    }
+
+    /// Writes every sample as a CSV row of the tick, time and values, or an `.npy` array with a row for each sample.
+    pub fn save(&self) -> Result<()> {
+        let data = if self.path.extension().is_some_and(|x| x == "npy") {
+            let values = (self.samples.iter())
+                .flat_map(|(_, _, values)| values.iter().copied())
+                .collect::<Vec<_>>();
+            match self.detector.shape {
+                Shape::Line(_) => npy::encode(&[self.samples.len(), self.cells.len()], &values),
+                Shape::Point(_) | Shape::Rect(_) => npy::encode(&[self.samples.len()], &values),
+            }
+        } else {
+            let mut out = format!("tick,time,{}\n", self.columns.join(","));
+            for (tick, time, values) in &self.samples {
+                write!(out, "{tick},{time:e}")?;
+                for value in values {
+                    write!(out, ",{value:e}")?;
+                }
+                out.push('\n');
+            }
+            out.into_bytes()
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, data)?;
+        Ok(())
+    }
+}
+
+impl<S: Solver> Simulation<S> {
+    /// Starts reading back the cells of every detector due on the current tick.
+    pub(super) fn sample_detectors(&mut self) {
+        let tick = self.parameters.tick;
+        let time = tick as f64 * self.parameters.dt as f64;
+        for recorder in &mut self.detectors {
+            let detector = &recorder.detector;
+            if tick < detector.start || !(tick - detector.start).is_multiple_of(detector.interval) {
+                continue;
+            }
+
+            let kind = match detector.quantity {
+                Quantity::Field | Quantity::Intensity => SnapshotType::State,
+                Quantity::Energy => SnapshotType::Energy,
+            };
+
+            let id = self.next_read;
+            self.next_read += 1;
+            self.solver.request_cells(kind, tick, &recorder.cells, id);
+            recorder.pending.push((id, tick, time));
+        }
+    }
+
+    /// Writes the samples recorded by every detector.
+    pub fn save_detectors(&self) -> Result<()> {
+        self.detectors.iter().try_for_each(Recorder::save)
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    fmt::{self, Display, Write as _},
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use super::{detector::Shape, snapshot::SnapshotType, solver::Solver, Simulation};
+
+/// Measures the energy flowing across lines, configured with a `[flux]` table.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FluxConfig {
+    #[serde(rename = "monitor")]
+    pub monitors: Vec<FluxMonitor>,
+    /// A `.csv` file the power through and energy passed each monitor are written to for every tick.
+    pub output: Option<PathBuf>,
+    /// Output of an earlier run without the object being measured, which the energies are normalized to.
+    pub reference: Option<PathBuf>,
+    /// Monitor between the source and the object, where the reflected energy is missing from the reference.
+    pub incident: Option<String>,
+    /// Monitor past the object.
+    pub transmitted: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FluxMonitor {
+    pub name: String,
+    /// Flow is counted along the normal `(dy, -dx)`, so a line drawn from top to bottom measures flow to the right.
+    pub line: [[u32; 2]; 2],
+}
+
+/// Running totals for every flux monitor.
+pub struct Flux {
+    config: FluxConfig,
+    path: Option<PathBuf>,
+    monitors: Vec<MonitorCells>,
+    /// Each line cell followed by its neighbors at +x, -x, +y and -y.
+    cells: Vec<u32>,
+    /// Square of the wave speed multiplier from the map at each line cell.
+    speed: Vec<f32>,
+    /// Energy passed each monitor in the reference run.
+    reference: Option<Vec<f64>>,
+
+    pending: Vec<PendingSample>,
+    last: Option<(u64, Vec<f32>)>,
+    samples: Vec<FluxSample>,
+    /// Energy that has passed each monitor so far.
+    pub energy: Vec<f64>,
+}
+
+struct MonitorCells {
+    /// Line cells belonging to this monitor.
+    range: Range<usize>,
+    normal: Vector2<f32>,
+    /// Length of the line covered by each cell, in cells.
+    spacing: f32,
+}
+
+/// Power through each monitor on a tick, and the energy that has passed it so far.
+struct FluxSample {
+    tick: u64,
+    time: f64,
+    power: Vec<f64>,
+    energy: Vec<f64>,
+}
+
+struct PendingSample {
+    id: u64,
+    tick: u64,
+    v: f32,
+    dt: f32,
+    dx: f32,
+}
+
+/// Fractions of the incident energy from the reference run, see `FluxConfig`.
+#[derive(Debug, Clone, Copy)]
+pub struct FluxReport {
+    pub transmitted: f64,
+    pub reflected: f64,
+    pub absorbed: f64,
+}
+
+impl Flux {
+    pub fn new(
+        config: FluxConfig,
+        base_path: &Path,
+        size: Vector2<u32>,
+        map: &[u8],
+    ) -> Result<Self> {
+        let mut monitors = Vec::new();
+        let (mut cells, mut speed) = (Vec::new(), Vec::new());
+        for monitor in &config.monitors {
+            let [start, end] = monitor.line.map(|x| Vector2::from(x).cast::<f32>());
+            let delta = end - start;
+            ensure!(
+                delta.norm() > 0.0,
+                "Flux monitor `{}` has no length.",
+                monitor.name
+            );
+
+            let line = Shape::Line(monitor.line).cells();
+            if let Some(cell) = (line.iter())
+                .find(|x| x.x == 0 || x.y == 0 || x.x + 1 >= size.x || x.y + 1 >= size.y)
+            {
+                bail!(
+                    "Flux monitor `{}` reaches ({}, {}), but has to stay inside the edges of the {}×{} domain.",
+                    monitor.name,
+                    cell.x,
+                    cell.y,
+                    size.x,
+                    size.y
+                );
+            }
+
+            let start_cell = speed.len();
+            for cell in &line {
+                let index = cell.y * size.x + cell.x;
+                cells.extend([index, index + 1, index - 1, index + size.x, index - size.x]);
+                let multiplier = map[index as usize * 4 + 2] as f32 / 255.0 * 2.0;
+                speed.push(multiplier.powi(2));
+            }
+
+            monitors.push(MonitorCells {
+                range: start_cell..speed.len(),
+                normal: Vector2::new(delta.y, -delta.x).normalize(),
+                spacing: delta.norm() / (line.len() - 1) as f32,
+            });
+        }
+
+        let reference = (config.reference.as_ref())
+            .map(|path| {
+                let path = base_path.join(path);
+                read_reference(&path, &config.monitors)
+                    .with_context(|| format!("Invalid flux reference {}", path.display()))
+            })
+            .transpose()?;
+
+        for name in [&config.incident, &config.transmitted]
+            .into_iter()
+            .flatten()
+        {
+            ensure!(
+                config.monitors.iter().any(|x| &x.name == name),
+                "No flux monitor is named `{name}`."
+            );
+        }
+
+        Ok(Self {
+            path: config.output.as_ref().map(|x| base_path.join(x)),
+            energy: vec![0.0; monitors.len()],
+            config,
+            monitors,
+            cells,
+            speed,
+            reference,
+
+            pending: Vec::new(),
+            last: None,
+            samples: Vec::new(),
+        })
+    }
+
+    pub fn monitors(&self) -> impl Iterator<Item = &str> {
+        self.config.monitors.iter().map(|x| x.name.as_str())
+    }
+
+    /// Adds the flux since the last tick, if the read is for this.
+    /// Calculated as `-c² ∂u/∂t ∇u`, with the time derivative taken between ticks and the gradient averaged over them.
+    pub fn receive(&mut self, id: u64, data: Vec<f32>) {
+        let Some(index) = self.pending.iter().position(|x| x.id == id) else {
+            return;
+        };
+
+        let sample = self.pending.remove(index);
+        let last = self.last.replace((sample.tick, data));
+        let Some((last_tick, last)) = last.filter(|(tick, _)| tick + 1 == sample.tick) else {
+            return;
+        };
+        let current = &self.last.as_ref().unwrap().1;
+
+        let (dt, dx) = (sample.dt as f64, sample.dx as f64);
+        let gradient = |values: &[f32], i: usize| {
+            let cell = &values[i * 5..i * 5 + 5];
+            Vector2::new(cell[1] - cell[2], cell[3] - cell[4]).cast::<f64>() / (2.0 * dx)
+        };
+
+        let power = (self.monitors.iter())
+            .map(|monitor| {
+                let normal = monitor.normal.cast::<f64>();
+                let flux = (monitor.range.clone())
+                    .map(|i| {
+                        let du = (current[i * 5] - last[i * 5]) as f64 / dt;
+                        let grad = (gradient(current, i) + gradient(&last, i)) / 2.0;
+                        let c2 = (sample.v as f64).powi(2) * self.speed[i] as f64;
+                        -c2 * du * grad.dot(&normal)
+                    })
+                    .sum::<f64>();
+                flux * monitor.spacing as f64 * dx
+            })
+            .collect::<Vec<_>>();
+
+        for (energy, power) in self.energy.iter_mut().zip(&power) {
+            *energy += power * dt;
+        }
+
+        self.samples.push(FluxSample {
+            tick: sample.tick,
+            time: (last_tick as f64 + 0.5) * dt,
+            power,
+            energy: self.energy.clone(),
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.last = None;
+        self.samples.clear();
+        self.energy.fill(0.0);
+    }
+
+    /// Drops the samples taken after `tick`, which the simulation has been rolled back to, and the energy they added.
+    /// The cells read on `tick` are gone once a later read has arrived, so the power on the tick after it is then skipped.
+    pub fn rollback(&mut self, tick: u64) {
+        self.pending.retain(|x| x.tick <= tick);
+        self.samples.retain(|x| x.tick <= tick);
+        if self.last.as_ref().is_some_and(|(last, _)| *last > tick) {
+            self.last = None;
+        }
+
+        match self.samples.last() {
+            Some(sample) => self.energy.clone_from(&sample.energy),
+            None => self.energy.fill(0.0),
+        }
+    }
+
+    pub fn report(&self) -> Option<FluxReport> {
+        let reference = self.reference.as_ref()?;
+        let index = |name: &Option<String>| {
+            let name = name.as_ref()?;
+            self.config.monitors.iter().position(|x| &x.name == name)
+        };
+        let (incident, transmitted) = (
+            index(&self.config.incident)?,
+            index(&self.config.transmitted)?,
+        );
+
+        let total = reference[incident];
+        let transmitted = self.energy[transmitted] / total;
+        let reflected = (total - self.energy[incident]) / total;
+        Some(FluxReport {
+            transmitted,
+            reflected,
+            absorbed: 1.0 - transmitted - reflected,
+        })
+    }
+
+    /// Writes the power through each monitor on every tick, along with the energy that has passed it.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut out = String::from("tick,time");
+        for name in self.monitors() {
+            write!(out, ",{name}_power,{name}_energy")?;
+        }
+        out.push('\n');
+
+        for sample in &self.samples {
+            write!(out, "{},{:e}", sample.tick, sample.time)?;
+            for (power, energy) in sample.power.iter().zip(&sample.energy) {
+                write!(out, ",{power:e},{energy:e}")?;
+            }
+            out.push('\n');
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, out)?;
+        Ok(())
+    }
+}
+
+/// Reads the energy that passed each monitor from the last row of an earlier run's output.
+fn read_reference(path: &Path, monitors: &[FluxMonitor]) -> Result<Vec<f64>> {
+    let file = fs::read_to_string(path)?;
+    let mut lines = file.lines();
+    let header = lines
+        .next()
+        .context("File is empty.")?
+        .split(',')
+        .collect::<Vec<_>>();
+    let last = lines
+        .last()
+        .context("File has no samples.")?
+        .split(',')
+        .collect::<Vec<_>>();
+
+    (monitors.iter())
+        .map(|monitor| {
+            let column = format!("{}_energy", monitor.name);
+            let index = (header.iter().position(|x| *x == column))
+                .with_context(|| format!("Missing column `{column}`."))?;
+            Ok(last.get(index).context("Row is too short.")?.parse()?)
+        })
+        .collect()
+}
+
+impl<S: Solver> Simulation<S> {
+    /// Starts reading back the cells around every flux monitor, which is needed on every tick.
+    pub(super) fn sample_flux(&mut self) {
+        let Some(flux) = &mut self.flux else {
+            return;
+        };
+
+        let id = self.next_read;
+        self.next_read += 1;
+
+        let params = &self.parameters;
+        (self.solver).request_cells(SnapshotType::State, params.tick, &flux.cells, id);
+        flux.pending.push(PendingSample {
+            id,
+            tick: params.tick,
+            v: params.v,
+            dt: params.dt,
+            dx: params.dx,
+        });
+    }
+
+    pub fn flux(&self) -> Option<&Flux> {
+        self.flux.as_ref()
+    }
+}
+
+impl Display for FluxReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Transmitted {:.2}%, reflected {:.2}%, absorbed {:.2}% of the incident energy.",
+            self.transmitted * 100.0,
+            self.reflected * 100.0,
+            self.absorbed * 100.0
+        )
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    f64::consts::TAU,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Result};
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::misc::{
+    color::{self, Grading},
+    npy::NpzWriter,
+    units,
+    util::save_dated_file,
+    writer::Writer,
+};
+
+use super::{solver::Solver, Simulation, SimulationParameters};
+
+/// Finds the amplitude and phase of every cell at the oscillator frequency and any extra ones, configured with a `[lock_in]` table.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockInConfig {
+    /// First tick to accumulate, which should be after the field has settled.
+    pub start: u64,
+    /// Number of ticks to accumulate, running until the end without one.
+    /// Spanning a whole number of periods of every frequency avoids leakage between them.
+    pub window: Option<u64>,
+    /// Accumulate the oscillator's frequency before the others.
+    pub oscillator: bool,
+    /// Frequencies (Hz) to accumulate.
+    #[serde(deserialize_with = "units::frequency")]
+    pub frequencies: Vec<f32>,
+    /// Wavelengths (m) to accumulate after the frequencies, converted with the wave speed.
+    pub wavelengths: Wavelengths,
+    /// An `.npz` file the result is written to when the window ends, or when the run finishes without one.
+    pub output: Option<PathBuf>,
+    /// A `.png` file the energy at every wavelength is written to, colored and added up like the light it would be.
+    pub composite: Option<PathBuf>,
+    /// Brightness of the composite, where a flat spectrum with an energy of `1 / exposure` is white.
+    pub exposure: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Wavelengths {
+    List(#[serde(deserialize_with = "units::length")] Vec<f32>),
+    /// Every `step` from `start` up to `end`.
+    Range {
+        #[serde(deserialize_with = "units::length")]
+        start: f32,
+        #[serde(deserialize_with = "units::length")]
+        end: f32,
+        #[serde(deserialize_with = "units::length")]
+        step: f32,
+    },
+}
+
+/// Running means of `2u·e^(-iωt)` kept by the solver, which converge to the complex amplitude of each cell.
+pub struct LockIn {
+    config: LockInConfig,
+    base_path: PathBuf,
+    size: Vector2<u32>,
+    /// Reads that haven't arrived yet.
+    pending: Vec<PendingLockIn>,
+    /// Set once the result has been written to the files from the config.
+    written: bool,
+}
+
+struct PendingLockIn {
+    id: u64,
+    output: Option<Target>,
+    composite: Option<Target>,
+    exposure: f32,
+
+    tick: u64,
+    samples: u64,
+    frequencies: Vec<f32>,
+    wave_speed: f32,
+}
+
+/// Where one of the files of a result is written.
+enum Target {
+    Path(PathBuf),
+    /// A dated file in `states`.
+    Dated,
+}
+
+impl LockInConfig {
+    pub fn frequency_count(&self) -> usize {
+        self.oscillator as usize + self.frequencies.len() + self.wavelengths.values().len()
+    }
+}
+
+impl Wavelengths {
+    pub fn values(&self) -> Vec<f32> {
+        match *self {
+            Wavelengths::List(ref list) => list.clone(),
+            Wavelengths::Range { start, end, step } if step > 0.0 => {
+                let steps = ((end - start) / step + 1e-3).floor().max(-1.0) as i64;
+                (0..=steps).map(|i| start + step * i as f32).collect()
+            }
+            Wavelengths::Range { .. } => Vec::new(),
+        }
+    }
+}
+
+impl LockIn {
+    pub fn new(config: LockInConfig, base_path: &Path, size: Vector2<u32>) -> Result<Self> {
+        ensure!(
+            config.window != Some(0),
+            "Lock-in window must be at least one tick."
+        );
+        ensure!(
+            config.frequency_count() > 0,
+            "Lock-in needs at least one frequency or wavelength."
+        );
+        ensure!(
+            (config
+                .frequencies
+                .iter()
+                .chain(&config.wavelengths.values()))
+            .all(|x| *x > 0.0),
+            "Lock-in frequencies and wavelengths must be positive."
+        );
+
+        let extension = |path: &Option<PathBuf>, extension| {
+            (path.as_ref()).is_none_or(|x| x.extension().is_some_and(|x| x == extension))
+        };
+        ensure!(
+            extension(&config.output, "npz"),
+            "Lock-in results must be written to a .npz file."
+        );
+        ensure!(
+            extension(&config.composite, "png"),
+            "Lock-in composites must be written to a .png file."
+        );
+
+        Ok(Self {
+            config,
+            base_path: base_path.to_path_buf(),
+            size,
+            pending: Vec::new(),
+            written: false,
+        })
+    }
+
+    /// Number of frequencies accumulated.
+    pub fn frequency_count(&self) -> usize {
+        self.config.frequency_count()
+    }
+
+    /// The oscillator's frequency if it is used, then the frequencies and wavelengths from the config.
+    pub fn frequencies(&self, params: &SimulationParameters) -> Vec<f32> {
+        let wavelengths = self.config.wavelengths.values();
+        (self
+            .config
+            .oscillator
+            .then_some(params.frequency)
+            .into_iter())
+        .chain(self.config.frequencies.iter().copied())
+        .chain(wavelengths.iter().map(|x| params.v / x))
+        .collect()
+    }
+
+    /// Whether the config has any files to write the result to.
+    fn has_outputs(&self) -> bool {
+        self.config.output.is_some() || self.config.composite.is_some()
+    }
+
+    /// Number of ticks accumulated before `tick` is computed.
+    pub fn samples(&self, tick: u64) -> u64 {
+        let samples = tick.saturating_sub(self.config.start);
+        self.config.window.map_or(samples, |x| samples.min(x))
+    }
+
+    fn active(&self, tick: u64) -> bool {
+        tick >= self.config.start
+            && self
+                .config
+                .window
+                .is_none_or(|x| tick - self.config.start < x)
+    }
+
+    /// Queues the amplitudes of a finished read to be written, returning the data if it isn't for this.
+    pub fn receive(&mut self, id: u64, data: Vec<f32>, writer: &mut Writer) -> Option<Vec<f32>> {
+        let Some(index) = self.pending.iter().position(|x| x.id == id) else {
+            return Some(data);
+        };
+
+        let pending = self.pending.remove(index);
+        let size = self.size;
+        writer.push(move || pending.write(size, &data));
+        None
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.written = false;
+    }
+
+    /// Drops the reads of means accumulated past `tick`, which the simulation has been rolled back to.
+    /// The result is written again if the window now ends after it.
+    pub fn rollback(&mut self, tick: u64) {
+        self.pending.retain(|x| x.tick <= tick);
+        let end = self.config.window.map(|x| self.config.start + x);
+        self.written &= end.is_some_and(|x| x <= tick);
+    }
+}
+
+impl PendingLockIn {
+    /// Writes the amplitude, phase and energy of each frequency as `(frequency, height, width)` arrays, along with the composite, run on the writer thread.
+    fn write(self, size: Vector2<u32>, data: &[f32]) -> Result<()> {
+        let shape = [self.frequencies.len(), size.y as usize, size.x as usize];
+        let (amplitude, phase) = (data.chunks_exact(2))
+            .map(|x| (x[0].hypot(x[1]), x[1].atan2(x[0])))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        // The mean of the squared field for a sinusoid of this amplitude
+        let energy = amplitude.iter().map(|x| x * x / 2.0).collect::<Vec<_>>();
+        let wavelengths = (self.frequencies.iter())
+            .map(|x| self.wave_speed / x)
+            .collect::<Vec<_>>();
+
+        if let Some(target) = self.output {
+            let mut npz = NpzWriter::new(Cursor::new(Vec::new()));
+            npz.add("amplitude", &shape, &amplitude)?;
+            npz.add("phase", &shape, &phase)?;
+            npz.add("energy", &shape, &energy)?;
+            npz.add("frequencies", &[shape[0]], &self.frequencies)?;
+            npz.add("wavelengths", &[shape[0]], &wavelengths)?;
+            npz.add("tick", &[], &[self.tick])?;
+            npz.add("samples", &[], &[self.samples])?;
+            fs::write(
+                target.resolve("lock_in", "npz")?,
+                npz.finish()?.into_inner(),
+            )?;
+        }
+
+        if let Some(target) = self.composite {
+            let layers = (wavelengths.iter())
+                .zip(energy.chunks_exact(shape[1] * shape[2]))
+                .map(|(wavelength, energy)| (wavelength * 1e9, energy))
+                .collect::<Vec<_>>();
+            let grading = Grading {
+                exposure: self.exposure,
+                ..Default::default()
+            };
+            color::composite(size.x, size.y, &layers, &grading)
+                .save(target.resolve("composite", "png")?)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Target {
+    fn resolve(self, name: &str, extension: &str) -> Result<PathBuf> {
+        match self {
+            Target::Path(path) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                Ok(path)
+            }
+            Target::Dated => save_dated_file("states", name, extension),
+        }
+    }
+}
+
+impl<S: Solver> Simulation<S> {
+    /// Adds the level just computed to the lock-in means, starting to write them out once the window ends.
+    pub(super) fn accumulate_lock_in(&mut self) {
+        let Some(lock_in) = &self.lock_in else {
+            return;
+        };
+
+        let params = &self.parameters;
+        if !lock_in.active(params.tick) {
+            return;
+        }
+
+        let time = params.tick as f64 * params.dt as f64;
+        let phases = (lock_in.frequencies(params).into_iter())
+            .map(|frequency| {
+                let phase = (TAU * frequency as f64 * time) % TAU;
+                [phase.cos() as f32, phase.sin() as f32]
+            })
+            .collect::<Vec<_>>();
+        let samples = lock_in.samples(params.tick);
+        self.solver.lock_in(params.tick, &phases, samples);
+
+        if lock_in.config.window == Some(samples + 1) && lock_in.has_outputs() {
+            self.write_lock_in(self.parameters.tick + 1);
+        }
+    }
+
+    /// Starts reading back the lock-in result, which is written to dated files in `states`.
+    pub fn save_lock_in(&mut self) {
+        let Some(lock_in) = &self.lock_in else {
+            return;
+        };
+
+        let composite = lock_in.config.composite.is_some().then_some(Target::Dated);
+        self.request_lock_in(Some(Target::Dated), composite, self.parameters.tick);
+    }
+
+    /// Writes the result to the files from the config if it hasn't been already, used once the run finishes.
+    pub(super) fn finish_lock_in(&mut self) {
+        let tick = self.parameters.tick;
+        if let Some(lock_in) = &self.lock_in {
+            if lock_in.has_outputs() && !lock_in.written && lock_in.samples(tick) > 0 {
+                self.write_lock_in(tick);
+            }
+        }
+    }
+
+    fn write_lock_in(&mut self, tick: u64) {
+        let lock_in = self.lock_in.as_mut().unwrap();
+        lock_in.written = true;
+
+        let base_path = &lock_in.base_path;
+        let target =
+            |path: &Option<PathBuf>| path.as_ref().map(|x| Target::Path(base_path.join(x)));
+        let (output, composite) = (
+            target(&lock_in.config.output),
+            target(&lock_in.config.composite),
+        );
+        self.request_lock_in(output, composite, tick);
+    }
+
+    /// Reads back the means once every tick before `tick` has been accumulated.
+    fn request_lock_in(&mut self, output: Option<Target>, composite: Option<Target>, tick: u64) {
+        let Some(lock_in) = &mut self.lock_in else {
+            return;
+        };
+
+        let id = self.next_read;
+        self.next_read += 1;
+        self.solver.request_lock_in(id);
+
+        lock_in.pending.push(PendingLockIn {
+            id,
+            output,
+            composite,
+            exposure: lock_in.config.exposure,
+
+            tick,
+            samples: lock_in.samples(tick),
+            frequencies: lock_in.frequencies(&self.parameters),
+            wave_speed: self.parameters.v,
+        });
+    }
+
+    pub fn lock_in(&self) -> Option<&LockIn> {
+        self.lock_in.as_ref()
+    }
+
+    pub(super) fn lock_in_frequencies(&self) -> usize {
+        self.lock_in.as_ref().map_or(0, LockIn::frequency_count)
+    }
+}
+
+impl Default for LockInConfig {
+    fn default() -> Self {
+        Self {
+            start: 0,
+            window: None,
+            oscillator: true,
+            frequencies: Vec::new(),
+            wavelengths: Wavelengths::List(Vec::new()),
+            output: None,
+            composite: None,
+            exposure: 1.0,
+        }
+    }
+}
@@ -1,387 +1,369 @@
-use std::{
-    borrow::Cow,
-    cmp::Ordering,
-    f32::consts::TAU,
-    fs::{self, File},
-};
+use std::path::PathBuf;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use bitflags::bitflags;
-use encase::ShaderType;
-use image::{DynamicImage, GenericImage, ImageReader};
 use nalgebra::Vector2;
-use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
-    BindGroupDescriptor, BindGroupEntry, Buffer, BufferAddress, BufferDescriptor, BufferUsages,
-    CommandEncoder, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device,
-    Queue, ShaderModuleDescriptor, ShaderSource,
-};
-use winit::dpi::PhysicalSize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::Config,
-    misc::{
-        audio::Audio,
-        preprocess::{Data, Preprocessor},
-    },
-    GraphicsContext,
+    config::{Config, RecordConfig, Stability},
+    misc::audio::AudioOutput,
 };
 
-const TICK_SIGNATURE: &str = "fn tick(x: u32, y: u32, mul: ptr<function, f32>, distance: ptr<function, f32>, c: ptr<function, f32>)";
-
+pub mod boundary;
+pub mod checkpoint;
+pub mod detector;
+pub mod flux;
+pub mod lock_in;
 mod scripting;
 pub mod snapshot;
+pub mod solver;
+pub mod source;
+pub mod stability;
+pub mod sweep;
+pub mod waveform;
+use boundary::Boundaries;
+use detector::Recorder;
+use flux::Flux;
+use lock_in::LockIn;
 use scripting::Scripting;
 use snapshot::SnapshotQueue;
-
-pub struct Simulation {
-    compute_pipeline: ComputePipeline,
+use solver::{GpuSolver, Peak, Scene, Solver};
+use source::Source;
+use stability::{Instability, PeakCheck};
+use sweep::Sweep;
+use waveform::WaveformWriter;
+
+pub struct Simulation<S = GpuSolver> {
+    solver: S,
     size: Vector2<u32>,
-
-    states: Buffer,
-    map_buffer: Buffer,
-    average_energy_buffer: Buffer,
-    staging_buffer: Buffer,
-    audio: Option<Audio>,
+    stencil_order: u32,
+    pml_thickness: u32,
+    config_path: Option<PathBuf>,
+    /// Packed RGBA map from the scene, kept for snapshots.
+    map: Vec<u8>,
+
+    audio: Option<(usize, AudioOutput)>,
+    /// Writers for the waveforms of sources with a `reference`, by their index in `parameters.sources`.
+    references: Vec<(usize, WaveformWriter)>,
+    detectors: Vec<Recorder>,
+    flux: Option<Flux>,
+    lock_in: Option<LockIn>,
     script: Option<Scripting>,
-
+    sweep: Option<Sweep>,
+    backup_tick: Option<u64>,
+    peak_check: Option<PeakCheck>,
+    /// Id of the next read requested from the solver.
+    next_read: u64,
+
+    pub stability: Stability,
+    /// Whether and how often rendered frames are being recorded.
+    pub record: RecordConfig,
+    /// Set by the last stability check if it failed.
+    pub instability: Option<Instability>,
     pub snapshot: SnapshotQueue,
+    /// Checkpoints to write, optionally with a file name.
+    pub checkpoints: Vec<Option<String>>,
+    pub queue_screenshot: bool,
     pub parameters: SimulationParameters,
 }
 
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SimulationParameters {
     pub ticks_per_dispatch: u32,
     pub tick: u64,
     pub running: bool,
     pub flags: SimulationFlags,
+    pub boundaries: Boundaries,
     pub user: u32,
 
-    pub v: f32,  // [length][time]^-1
-    pub dt: f32, // [time]
-    pub dx: f32, // [length]
+    pub v: f32,    // [length][time]^-1
+    pub dt: f32,   // [time]
+    pub dx: f32,   // [length]
+    pub loss: f32, // [time]^-1
 
     pub amplitude: f32,
     pub frequency: f32,
+    pub sources: Vec<Source>,
 }
 
 bitflags! {
-    #[derive(Clone, Copy)]
+    #[derive(Serialize, Deserialize, Clone, Copy)]
     pub struct SimulationFlags: u32 {
-        const REFLECTIVE_BOUNDARY = 1 << 0;
-        const ENERGY_VIEW = 1 << 1;
-        const BILINIER_SAMPLING = 1 << 2;
+        const ENERGY_VIEW = 1 << 0;
+        const BILINIER_SAMPLING = 1 << 1;
+        const AMPLITUDE_VIEW = 1 << 2;
+        const PHASE_VIEW = 1 << 3;
     }
 }
 
-#[derive(ShaderType)]
-pub struct SimulationContext {
-    pub size: Vector2<u32>,
-    pub window: Vector2<u32>,
-    pub user: u32,
+impl<S: Solver> Simulation<S> {
+    pub fn new(config: &Config, solver: impl FnOnce(&Scene) -> Result<S>) -> Result<Self> {
+        let scene = Scene::load(config)?;
+        let solver = solver(&scene)?;
 
-    pub tick: u32,
-    pub ticks_per_dispatch: u32,
-    pub flags: u32,
-
-    pub c: f32,
-    pub amplitude: f32,
-    pub frequency: f32,
-}
-
-impl Simulation {
-    pub fn new(device: &Device, config: &Config) -> Result<Self> {
-        let map = config
-            .map
+        let audio = config
+            .audio
             .as_ref()
-            .map(|map| {
-                let mut image = DynamicImage::new_rgba8(config.size.0, config.size.1);
-                let map = ImageReader::open(config.base_path().join(map))?.decode()?;
-                let x = (config.size.0 - map.width()) / 2;
-                let y = (config.size.1 - map.height()) / 2;
-                image
-                    .copy_from(&map, x, y)
-                    .context("Map must have a size equal or smaller than the simulation size.")?;
-                Ok::<_, anyhow::Error>(image)
+            .zip(scene.audio.as_ref())
+            .map(|(config_audio, input)| {
+                let output = AudioOutput::new(config.base_path().join(&config_audio.output))?;
+                Ok::<_, anyhow::Error>((input.samples.len(), output))
             })
             .transpose()?;
 
-        let audio = config
-            .audio
-            .as_ref()
-            .map(|x| {
-                Audio::new(
-                    device,
-                    File::open(config.base_path().join(&x.input))?,
-                    File::create(config.base_path().join(&x.output))?,
+        let references = (config.sources.iter().enumerate())
+            .filter_map(|(i, source)| Some((i, source.reference.as_ref()?)))
+            .map(|(i, path)| {
+                let writer = WaveformWriter::new(&config.base_path().join(path))?;
+                Ok((i, writer))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let detectors = (config.detectors.iter())
+            .map(|x| Recorder::new(x.clone(), &config.base_path(), scene.size))
+            .collect::<Result<Vec<_>>>()?;
+
+        let flux = (!config.flux.monitors.is_empty())
+            .then(|| {
+                Flux::new(
+                    config.flux.clone(),
+                    &config.base_path(),
+                    scene.size,
+                    &scene.map,
                 )
             })
             .transpose()?;
 
-        let script = config
-            .script
-            .as_ref()
-            .map(|x| Scripting::from_file(config.base_path().join(x)));
-
-        let mut raw_shader = Cow::Borrowed(include_str!("../shaders/shader.wgsl"));
-        if let Some(ref shader) = config.shader {
-            let shader = fs::read_to_string(config.base_path().join(shader)).unwrap();
-            let line_end = raw_shader.find('\n').unwrap();
-            raw_shader = Cow::Owned(format!(
-                "{TICK_SIGNATURE} {{\n{shader}\n}}{}",
-                &raw_shader[line_end..]
-            ));
-        }
-
-        let mut preprocessor = Preprocessor::new();
-        if let Some(audio) = &config.audio {
-            preprocessor = preprocessor.define("AUDIO", Data::vec2(audio.pickup.0, audio.pickup.1));
-        } else {
-            preprocessor = preprocessor.define("OSCILLATOR", Data::Null);
-        }
-
-        let raw_shader = preprocessor.process(&raw_shader);
-        let compute_shader = device.create_shader_module(ShaderModuleDescriptor {
-            label: None,
-            source: ShaderSource::Wgsl(raw_shader.into()),
-        });
-
-        let map_data = match map {
-            Some(map) => map.into_rgba8().into_raw(),
-            None => {
-                let mut out = vec![0; (config.size.0 * config.size.1) as usize * 4];
-                out.chunks_exact_mut(4).for_each(|x| x[2] = 128);
-                out
-            }
-        };
-        let map_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: map_data.as_slice(),
-            usage: BufferUsages::STORAGE,
-        });
-
-        let state_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: (config.size.0 * config.size.1 * 3 * 4) as u64,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-        let average_energy_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: (config.size.0 * config.size.1 * 4) as u64,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-
-        let staging_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: (config.size.0 * config.size.1 * 4) as u64,
-            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
+        let lock_in = (config.lock_in.clone())
+            .map(|x| LockIn::new(x, &config.base_path(), scene.size))
+            .transpose()?;
 
-        let compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: None,
-            layout: None,
-            module: &compute_shader,
-            entry_point: "main",
+        let script = (config.script.as_ref()).map(|x| {
+            let base_path = config.base_path();
+            Scripting::from_file(base_path.join(x), &base_path, scene.size)
         });
 
-        let mut flags = SimulationFlags::BILINIER_SAMPLING;
-        if config.parameters.reflective_boundary {
-            flags |= SimulationFlags::REFLECTIVE_BOUNDARY;
-        }
+        let sweep = (config.sweep.clone())
+            .map(|x| {
+                let script = script.as_ref().is_some_and(|x| x.has_function("sweep"));
+                Sweep::new(x, &config.base_path(), config.snapshot_format, script)
+            })
+            .transpose()?;
 
         let mut this = Self {
-            compute_pipeline,
-            size: Vector2::new(config.size.0, config.size.1),
+            solver,
+            size: scene.size,
+            stencil_order: scene.stencil_order,
+            pml_thickness: scene.pml_thickness,
+            config_path: config.path.clone(),
+            map: scene.map,
 
-            states: state_buffer,
-            map_buffer,
-            average_energy_buffer,
-            staging_buffer,
             audio,
+            references,
+            detectors,
+            flux,
+            lock_in,
             script,
-
-            snapshot: Default::default(),
-            parameters: SimulationParameters {
-                ticks_per_dispatch: 1,
-                tick: 0,
-                running: false,
-                flags,
-                user: 0,
-
-                dt: config.parameters.dt,
-                dx: config.parameters.dx,
-
-                v: config.parameters.v,
-                amplitude: config.oscillator.amplitude,
-                frequency: config.oscillator.frequency,
-            },
+            sweep,
+            backup_tick: None,
+            peak_check: None,
+            next_read: 0,
+
+            stability: config.stability.clone(),
+            record: config.record.clone(),
+            instability: None,
+            snapshot: SnapshotQueue::new(config.snapshot_format, config.mesh),
+            checkpoints: Vec::new(),
+            queue_screenshot: false,
+            parameters: SimulationParameters::new(config),
         };
 
-        this.script_update(None, "init");
+        this.script_update("init");
+        this.start_sweep_run();
         Ok(this)
     }
 
-    pub fn get_state(&self) -> &Buffer {
-        &self.states
+    pub fn solver(&self) -> &S {
+        &self.solver
     }
 
-    pub fn get_average_energy_buffer(&self) -> &Buffer {
-        &self.average_energy_buffer
+    pub fn solver_mut(&mut self) -> &mut S {
+        &mut self.solver
     }
 
     pub fn get_size(&self) -> Vector2<u32> {
         self.size
     }
 
-    pub fn update(
-        &mut self,
-        gc: &GraphicsContext,
-        encoder: &mut CommandEncoder,
-        window_size: PhysicalSize<u32>,
-    ) {
-        if !self.parameters.running {
-            return;
+    pub fn get_stencil_order(&self) -> u32 {
+        self.stencil_order
+    }
+
+    pub fn has_script(&self) -> bool {
+        self.script.is_some()
+    }
+
+    pub fn detectors(&self) -> &[Recorder] {
+        &self.detectors
+    }
+
+    /// Runs `ticks_per_dispatch` ticks, pausing and returning the error if an output can't be written.
+    pub fn update(&mut self) -> Result<()> {
+        let result = self.tick_dispatch();
+        if result.is_err() {
+            self.parameters.running = false;
         }
+        result
+    }
 
+    fn tick_dispatch(&mut self) -> Result<()> {
         for _ in 0..self.parameters.ticks_per_dispatch {
-            let buf = self.get_context_buffer(&gc.device, window_size);
+            if !self.parameters.running {
+                break;
+            }
+
+            self.solver.tick(&self.parameters);
+            self.accumulate_lock_in();
 
             let params = &mut self.parameters;
-            let bind_group_layout = self.compute_pipeline.get_bind_group_layout(0);
-            let mut entries = vec![
-                BindGroupEntry {
-                    binding: 0,
-                    resource: buf.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: self.map_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: self.states.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 3,
-                    resource: self.average_energy_buffer.as_entire_binding(),
-                },
-            ];
-
-            if let Some(audio) = &self.audio {
-                entries.extend([
-                    BindGroupEntry {
-                        binding: 4,
-                        resource: audio.audio_in_buffer.as_entire_binding(),
-                    },
-                    BindGroupEntry {
-                        binding: 5,
-                        resource: audio.audio_out_buffer.as_entire_binding(),
-                    },
-                ]);
+            if let Some((audio_in_len, audio)) = &mut self.audio {
+                audio.write(&self.solver.take_audio());
+                if *audio_in_len == params.tick as usize {
+                    params.running = false;
+                }
             }
 
-            let bind_group = gc.device.create_bind_group(&BindGroupDescriptor {
-                label: None,
-                layout: &bind_group_layout,
-                entries: &entries,
-            });
-
-            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
-                label: None,
-                timestamp_writes: None,
-            });
-            compute_pass.set_pipeline(&self.compute_pipeline);
-            compute_pass.set_bind_group(0, &bind_group, &[]);
-            compute_pass.dispatch_workgroups(self.size.x.div_ceil(8), self.size.y.div_ceil(8), 1);
-            drop(compute_pass);
-
-            if let Some(audio) = &mut self.audio {
-                match audio.audio_in_len.cmp(&(params.tick as usize)) {
-                    Ordering::Equal => params.running = false,
-                    Ordering::Greater => audio.tick(params.tick, gc, encoder),
-                    _ => {}
-                }
+            for (i, writer) in &mut self.references {
+                let Some(source) = params.sources.get(*i) else {
+                    continue;
+                };
+                let time = params.tick as f64 * params.dt as f64;
+                writer.write(params.tick, time, source.value(params.tick, params.dt))?;
             }
 
             params.tick += 1;
-            self.script_update(Some(&gc.queue), "update");
+            if !self.check_stability()? {
+                break;
+            }
+
+            self.sample_detectors();
+            self.sample_flux();
+
+            self.script_update("update");
+            self.step_sweep()?;
         }
+
+        Ok(())
     }
 
-    fn script_update(&mut self, queue: Option<&Queue>, func: &str) {
+    /// Removes a source, along with the writer for its reference waveform.
+    pub fn remove_source(&mut self, index: usize) {
+        self.parameters.sources.remove(index);
+        self.references.retain(|(i, _)| *i != index);
+        for (i, _) in &mut self.references {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+    }
+
+    fn script_update(&mut self, func: &str) {
         if let Some(script) = &mut self.script {
             let response = script.update(&mut self.parameters, func);
 
-            if let (Some(queue), true) = (queue, response.reset) {
-                self.reset_states(queue);
-                self.reset_average_energy(queue);
+            if response.reset {
+                self.reset_states();
+                self.reset_average_energy();
             }
 
             self.snapshot.extend(response.snapshot);
+            self.checkpoints.extend(response.checkpoint);
+
+            self.detectors.extend(response.detectors);
+            self.queue_screenshot |= response.screenshot;
+            if let Some(recording) = response.recording {
+                self.record.enabled = recording;
+            }
         }
     }
 
-    pub fn get_context_buffer(&self, device: &Device, window_size: PhysicalSize<u32>) -> Buffer {
-        let params = &self.parameters;
-        let context = SimulationContext {
-            size: self.size,
-            window: Vector2::new(window_size.width, window_size.height),
-            user: params.user,
+    /// Waits for queued snapshots, detector samples, lock-in results and audio still being read back, then writes them.
+    pub fn flush(&mut self) -> Result<()> {
+        self.finish_lock_in();
+        self.flush_snapshots()?;
+        self.finish_stability_check();
+        if let Some((_, audio)) = &mut self.audio {
+            audio.write(&self.solver.take_audio());
+        }
 
-            tick: params.tick as u32,
-            ticks_per_dispatch: params.ticks_per_dispatch,
-            flags: params.flags.bits(),
+        self.save_detectors()?;
+        match &self.flux {
+            Some(flux) => flux.save(),
+            None => Ok(()),
+        }
+    }
 
-            c: params.v * (params.dt / params.dx),
-            amplitude: params.amplitude,
-            frequency: TAU * params.dt * params.frequency,
-        };
+    /// Hands out finished reads to the stability check, snapshots, detectors, lock-in and flux monitors waiting on them.
+    fn poll_reads(&mut self, wait: bool) -> Result<()> {
+        for (id, data) in self.solver.poll_reads(wait)? {
+            if let Some(check) = self.peak_check.as_mut().filter(|x| x.id == id) {
+                check.peak = Some(Peak::from_read(&data, self.size.x));
+                continue;
+            }
 
-        device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: &context.to_wgsl_bytes(),
-            usage: BufferUsages::UNIFORM,
-        })
-    }
+            let mut data = self.snapshot.receive(id, data);
+            for detector in &mut self.detectors {
+                data = data.and_then(|data| detector.receive(id, data));
+            }
+
+            if let Some(lock_in) = &mut self.lock_in {
+                let writer = &mut self.snapshot.writer;
+                data = data.and_then(|data| lock_in.receive(id, data, writer));
+            }
 
-    pub fn stage_energy(&self, encoder: &mut CommandEncoder) -> &Buffer {
-        encoder.copy_buffer_to_buffer(
-            &self.average_energy_buffer,
-            0,
-            &self.staging_buffer,
-            0,
-            self.staging_buffer.size(),
-        );
-        &self.staging_buffer
+            if let (Some(flux), Some(data)) = (&mut self.flux, data) {
+                flux.receive(id, data);
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn reset_states(&mut self, queue: &Queue) {
+    pub fn reset_states(&mut self) {
         self.parameters.tick = 0;
-        let empty_buffer = vec![0f32; (self.size.x * self.size.y * 3) as usize];
-        queue.write_buffer(
-            &self.states,
-            BufferAddress::default(),
-            bytemuck::cast_slice(&empty_buffer),
-        )
+        self.backup_tick = None;
+        self.peak_check = None;
+        self.instability = None;
+        self.detectors.iter_mut().for_each(Recorder::clear);
+        self.flux.iter_mut().for_each(Flux::clear);
+        self.lock_in.iter_mut().for_each(LockIn::clear);
+        self.solver.reset_states();
     }
 
-    pub fn reset_average_energy(&mut self, queue: &Queue) {
-        let empty_buffer = vec![0f32; (self.size.x * self.size.y) as usize];
-        queue.write_buffer(
-            &self.average_energy_buffer,
-            BufferAddress::default(),
-            bytemuck::cast_slice(&empty_buffer),
-        )
+    pub fn reset_average_energy(&mut self) {
+        self.solver.reset_average_energy();
     }
 }
 
-impl SimulationContext {
-    pub fn to_wgsl_bytes(&self) -> Vec<u8> {
-        let mut buffer = encase::UniformBuffer::new(Vec::new());
-        buffer.write(self).unwrap();
-        buffer.into_inner()
+impl SimulationParameters {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            ticks_per_dispatch: 1,
+            tick: 0,
+            running: false,
+            flags: SimulationFlags::BILINIER_SAMPLING,
+            boundaries: config.parameters.boundaries(),
+            user: 0,
+
+            dt: config.parameters.dt,
+            dx: config.parameters.dx,
+            loss: config.parameters.loss,
+
+            v: config.parameters.v,
+            amplitude: config.oscillator.amplitude,
+            frequency: config.oscillator.frequency,
+            sources: config.sources.clone(),
+        }
     }
 }
@@ -1,32 +1,46 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use rhai::{Dynamic, Engine, OptimizationLevel, Scope, AST, INT};
+use nalgebra::Vector2;
+use rhai::{Dynamic, Engine, EvalAltResult, Map, OptimizationLevel, Scope, AST, INT};
 
 use crate::simulation::SimulationParameters;
 
-use super::snapshot::SnapshotType;
+use super::{detector::Recorder, snapshot::SnapshotType};
 
 pub struct Scripting {
     engine: Engine,
     scope: Scope<'static>,
 
     script: AST,
+    /// Values of the script's variables in the current sweep run.
+    sweep: Map,
+    /// Directory detector outputs are relative to.
+    base_path: PathBuf,
+    size: Vector2<u32>,
 }
 
 #[derive(Clone)]
 struct Context {
     params: SimulationParameters,
+    sweep: Map,
+    base_path: PathBuf,
+    size: Vector2<u32>,
     response: PostTickResponse,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct PostTickResponse {
     pub reset: bool,
+    pub screenshot: bool,
+    /// Starts or stops recording frames.
+    pub recording: Option<bool>,
     pub snapshot: Vec<(SnapshotType, Option<String>)>,
+    pub checkpoint: Vec<Option<String>>,
+    pub detectors: Vec<Recorder>,
 }
 
 impl Scripting {
-    pub fn from_file(path: PathBuf) -> Self {
+    pub fn from_file(path: PathBuf, base_path: &Path, size: Vector2<u32>) -> Self {
         let mut engine = Engine::new();
         let mut scope = Scope::new();
         engine.set_optimization_level(OptimizationLevel::None);
@@ -37,14 +51,22 @@ impl Scripting {
             .register_get("tick", Context::get_tick)
             .register_fn("pause", Context::pause)
             .register_fn("reset", Context::reset)
+            .register_fn("screenshot", Context::screenshot)
+            .register_fn("start_recording", Context::start_recording)
+            .register_fn("stop_recording", Context::stop_recording)
             .register_fn("snapshot_state", Context::snapshot_state_name)
             .register_fn("snapshot_state", Context::snapshot_state)
             .register_fn("snapshot_energy", Context::snapshot_energy_name)
             .register_fn("snapshot_energy", Context::snapshot_energy)
+            .register_fn("checkpoint", Context::checkpoint_name)
+            .register_fn("checkpoint", Context::checkpoint)
+            .register_fn("add_detector", Context::add_detector)
+            .register_get("sweep", Context::get_sweep)
             .register_set("user", Context::set_user)
             .register_get_set("v", Context::get_v, Context::set_v)
             .register_get_set("dt", Context::get_dt, Context::set_dt)
             .register_get_set("dx", Context::get_dx, Context::set_dx)
+            .register_get_set("loss", Context::get_loss, Context::set_loss)
             .register_get_set("amplitude", Context::get_amplitude, Context::set_amplitude)
             .register_get_set("frequency", Context::get_frequency, Context::set_frequency);
 
@@ -55,12 +77,29 @@ impl Scripting {
             engine,
             scope,
             script,
+            sweep: Map::new(),
+            base_path: base_path.to_owned(),
+            size,
         }
     }
 
+    pub fn has_function(&self, name: &str) -> bool {
+        self.script.iter_functions().any(|x| x.name == name)
+    }
+
+    /// Sets the values read from `sim.sweep`.
+    pub fn set_sweep(&mut self, values: &[(String, f64)]) {
+        self.sweep = (values.iter())
+            .map(|(name, value)| (name.into(), Dynamic::from_float(*value)))
+            .collect();
+    }
+
     pub fn update(&mut self, params: &mut SimulationParameters, func: &str) -> PostTickResponse {
         let ctx = Context {
             params: params.clone(),
+            sweep: self.sweep.clone(),
+            base_path: self.base_path.clone(),
+            size: self.size,
             response: PostTickResponse::default(),
         };
 
@@ -88,6 +127,18 @@ impl Context {
         self.response.reset = true;
     }
 
+    fn screenshot(&mut self) {
+        self.response.screenshot = true;
+    }
+
+    fn start_recording(&mut self) {
+        self.response.recording = Some(true);
+    }
+
+    fn stop_recording(&mut self) {
+        self.response.recording = Some(false);
+    }
+
     fn snapshot_state(&mut self) {
         self.response.snapshot.push((SnapshotType::State, None));
     }
@@ -108,6 +159,28 @@ impl Context {
             .push((SnapshotType::Energy, Some(name.to_string())));
     }
 
+    fn checkpoint(&mut self) {
+        self.response.checkpoint.push(None);
+    }
+
+    fn checkpoint_name(&mut self, name: &str) {
+        self.response.checkpoint.push(Some(name.to_string()));
+    }
+
+    /// Values of the current sweep run, by parameter name.
+    fn get_sweep(&mut self) -> Map {
+        self.sweep.clone()
+    }
+
+    /// Takes a map with the same fields as a `[[detector]]` table, failing if it doesn't describe a valid detector.
+    fn add_detector(&mut self, detector: Map) -> Result<(), Box<EvalAltResult>> {
+        let detector = rhai::serde::from_dynamic(&detector.into())?;
+        let recorder = Recorder::new(detector, &self.base_path, self.size)
+            .map_err(|e| format!("Failed to add detector: {e}"))?;
+        self.response.detectors.push(recorder);
+        Ok(())
+    }
+
     fn set_user(&mut self, user: Dynamic) {
         if let Ok(int) = user.as_int() {
             self.params.user = (int as i32) as u32;
@@ -142,6 +215,14 @@ impl Context {
         self.params.dx as f64
     }
 
+    fn set_loss(&mut self, loss: f64) {
+        self.params.loss = loss as f32;
+    }
+
+    fn get_loss(&mut self) -> f64 {
+        self.params.loss as f64
+    }
+
     fn set_amplitude(&mut self, amplitude: f64) {
         self.params.amplitude = amplitude as f32;
     }
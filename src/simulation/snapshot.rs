@@ -1,16 +1,111 @@
-use wgpu::{Buffer, CommandEncoder};
+use std::{
+    fs,
+    io::Cursor,
+    mem,
+    path::{Path, PathBuf},
+};
 
-use super::Simulation;
+use anyhow::{bail, ensure, Context, Result};
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+use crate::misc::{
+    mesh::{Mesh, MeshOptions},
+    npy::{self, NpzReader, NpzWriter},
+    util::save_dated_file,
+    writer::Writer,
+};
+
+use super::{solver::Solver, Simulation, SimulationParameters};
+
+/// Identifies snapshots with a header, older ones start directly with the width and height.
+const MAGIC: &[u8; 8] = b"WAVESNAP";
+const VERSION: u32 = 1;
+/// Data type code for little-endian f32 cells.
+const DTYPE_F32: u8 = 0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
 pub enum SnapshotType {
     State,
     Energy,
 }
 
-#[derive(Default)]
+/// A snapshot file, made of a header followed by the cells in row-major order.
+///
+/// All values are little-endian, with the header laid out as:
+/// - `0..8`: the magic bytes `WAVESNAP`
+/// - `8..12`: format version (u32)
+/// - `12..16`: header length in bytes, which is where the data starts (u32)
+/// - `16..24`: width and height (u32)
+/// - `24..32`: tick (u64)
+/// - `32..40`: simulated time in seconds (f64)
+/// - `40..60`: dx, dt, v, frequency and amplitude (f32)
+/// - `60`: data type, 0 for f32
+/// - `61`: snapshot type, 0 for state and 1 for energy
+/// - `62..64`: length of the config path (u16)
+/// - `64..`: config path as UTF-8, padded to a multiple of four bytes
+pub struct Snapshot {
+    pub size: Vector2<u32>,
+    /// Missing for files written before the header was added.
+    pub metadata: Option<SnapshotMetadata>,
+    pub data: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SnapshotMetadata {
+    pub kind: SnapshotType,
+    pub tick: u64,
+    /// Simulated time in seconds.
+    pub time: f64,
+
+    pub dx: f32,
+    pub dt: f32,
+    pub v: f32,
+    pub frequency: f32,
+    pub amplitude: f32,
+
+    /// Config the simulation was loaded from.
+    pub config: Option<PathBuf>,
+}
+
+/// File format of a snapshot, picked from the extension of its name if it has one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotFormat {
+    /// The `Snapshot` layout.
+    #[default]
+    Bin,
+    /// A NumPy float32 array with a shape of (height, width).
+    Npy,
+    /// A NumPy archive with the state, energy and map, along with the tick and parameters.
+    Npz,
+    /// A binary STL height field mesh, see `Mesh`.
+    Stl,
+    /// A binary PLY height field mesh.
+    Ply,
+}
+
 pub struct SnapshotQueue {
-    snapshots: Vec<(SnapshotType, Option<String>)>,
+    snapshots: Vec<(SnapshotType, Option<String>, SnapshotFormat)>,
+    /// Snapshots waiting on the solver to read back their fields.
+    pending: Vec<PendingSnapshot>,
+    /// Writes snapshots and lock-in results in the background.
+    pub(super) writer: Writer,
+    /// Format of snapshots without an extension.
+    pub format: SnapshotFormat,
+    /// How snapshots written as meshes are built.
+    pub mesh: MeshOptions,
+}
+
+/// A snapshot with its parameters captured when it was queued.
+struct PendingSnapshot {
+    name: Option<String>,
+    format: SnapshotFormat,
+    mesh: MeshOptions,
+    metadata: SnapshotMetadata,
+    /// Read ids and the data once it arrives, in the order of `SnapshotFormat::fields`.
+    fields: Vec<(u64, Option<Vec<f32>>)>,
 }
 
 impl SnapshotType {
@@ -21,42 +116,542 @@ impl SnapshotType {
         }
     }
 
-    pub fn stage<'a>(
-        &self,
-        simulation: &'a Simulation,
-        encoder: &mut CommandEncoder,
-    ) -> &'a Buffer {
+    fn id(&self) -> u8 {
+        match self {
+            SnapshotType::State => 0,
+            SnapshotType::Energy => 1,
+        }
+    }
+}
+
+impl SnapshotFormat {
+    pub const ALL: [SnapshotFormat; 5] = [Self::Bin, Self::Npy, Self::Npz, Self::Stl, Self::Ply];
+
+    pub fn extension(&self) -> &'static str {
         match self {
-            SnapshotType::State => simulation.stage_state(encoder),
-            SnapshotType::Energy => simulation.stage_energy(encoder),
+            SnapshotFormat::Bin => "bin",
+            SnapshotFormat::Npy => "npy",
+            SnapshotFormat::Npz => "npz",
+            SnapshotFormat::Stl => "stl",
+            SnapshotFormat::Ply => "ply",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?;
+        Self::ALL.into_iter().find(|x| extension == x.extension())
+    }
+
+    pub fn is_mesh(&self) -> bool {
+        matches!(self, SnapshotFormat::Stl | SnapshotFormat::Ply)
+    }
+
+    /// Fields that have to be read back for a snapshot of `kind`.
+    fn fields(&self, kind: &SnapshotType) -> Vec<SnapshotType> {
+        match self {
+            SnapshotFormat::Npz => vec![SnapshotType::State, SnapshotType::Energy],
+            _ => vec![kind.clone()],
+        }
+    }
+}
+
+impl Snapshot {
+    /// Loads a snapshot in any format, picked from its extension, with `.npz` archives giving their energy.
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let snapshot = match SnapshotFormat::from_path(path).unwrap_or_default() {
+            SnapshotFormat::Bin => Self::parse(&bytes),
+            SnapshotFormat::Npy => Self::parse_npy(&bytes),
+            SnapshotFormat::Npz => Self::parse_npz(&bytes),
+            SnapshotFormat::Stl | SnapshotFormat::Ply => bail!(
+                "{} is a mesh, which can't be loaded as a snapshot.",
+                path.display()
+            ),
+        };
+        snapshot.with_context(|| format!("Invalid snapshot {}", path.display()))
+    }
+
+    /// Parses a snapshot, falling back to the headerless format if the magic bytes are missing.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let f32_at = |offset| f32::from_bits(u32_at(offset));
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        ensure!(bytes.len() >= 8, "File is too short.");
+
+        if !bytes.starts_with(MAGIC) {
+            let size = Vector2::new(u32_at(0), u32_at(4));
+            return Ok(Self {
+                size,
+                metadata: None,
+                data: cells(&bytes[8..], size)?,
+            });
+        }
+
+        ensure!(bytes.len() >= 64, "File is too short.");
+        let (version, header_len) = (u32_at(8), u32_at(12) as usize);
+        ensure!(version <= VERSION, "Unsupported version {version}.");
+        ensure!(
+            bytes[60] == DTYPE_F32,
+            "Unsupported data type {}.",
+            bytes[60]
+        );
+
+        let kind = match bytes[61] {
+            0 => SnapshotType::State,
+            1 => SnapshotType::Energy,
+            x => bail!("Unknown snapshot type {x}."),
+        };
+        let config_len = u16::from_le_bytes([bytes[62], bytes[63]]) as usize;
+        ensure!(header_len >= 64 + config_len, "Header is too short.");
+        ensure!(bytes.len() >= header_len, "File is too short.");
+        let config = match config_len {
+            0 => None,
+            n => Some(PathBuf::from(std::str::from_utf8(&bytes[64..64 + n])?)),
+        };
+
+        let size = Vector2::new(u32_at(16), u32_at(20));
+        Ok(Self {
+            size,
+            metadata: Some(SnapshotMetadata {
+                kind,
+                tick: u64_at(24),
+                time: f64::from_bits(u64_at(32)),
+                dx: f32_at(40),
+                dt: f32_at(44),
+                v: f32_at(48),
+                frequency: f32_at(52),
+                amplitude: f32_at(56),
+                config,
+            }),
+            data: cells(&bytes[header_len..], size)?,
+        })
+    }
+
+    /// Parses a `(height, width)` NumPy array, which has no metadata.
+    fn parse_npy(bytes: &[u8]) -> Result<Self> {
+        let (shape, data) = npy::decode::<f32>(bytes)?;
+        let &[height, width] = shape.as_slice() else {
+            bail!("Expected a 2D array, found a shape of {shape:?}.");
+        };
+
+        Ok(Self {
+            size: Vector2::new(width as u32, height as u32),
+            metadata: None,
+            data,
+        })
+    }
+
+    /// Parses the energy and parameters from an archive written by `encode_npz`.
+    fn parse_npz(bytes: &[u8]) -> Result<Self> {
+        let mut npz = NpzReader::new(Cursor::new(bytes))?;
+        let (shape, data) = npz.get::<f32>("energy")?;
+        let &[height, width] = shape.as_slice() else {
+            bail!("Expected a 2D array, found a shape of {shape:?}.");
+        };
+
+        let mut scalar = |name| Ok::<_, anyhow::Error>(npz.get::<f32>(name)?.1[0]);
+        let (dx, dt, v) = (scalar("dx")?, scalar("dt")?, scalar("v")?);
+        let (frequency, amplitude) = (scalar("frequency")?, scalar("amplitude")?);
+        Ok(Self {
+            size: Vector2::new(width as u32, height as u32),
+            metadata: Some(SnapshotMetadata {
+                kind: SnapshotType::Energy,
+                tick: npz.get::<u64>("tick")?.1[0],
+                time: npz.get::<f64>("time")?.1[0],
+                dx,
+                dt,
+                v,
+                frequency,
+                amplitude,
+                config: None,
+            }),
+            data,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64 + self.data.len() * 4);
+        let Some(metadata) = &self.metadata else {
+            out.extend_from_slice(&self.size.x.to_le_bytes());
+            out.extend_from_slice(&self.size.y.to_le_bytes());
+            out.extend_from_slice(bytemuck::cast_slice(&self.data));
+            return out;
+        };
+
+        let config = metadata
+            .config
+            .as_ref()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // Cut on a character boundary so the path stays valid UTF-8
+        let mut end = config.len().min(u16::MAX as usize);
+        while !config.is_char_boundary(end) {
+            end -= 1;
+        }
+        let config = &config.as_bytes()[..end];
+        let header_len = (64 + config.len()).next_multiple_of(4);
+
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(header_len as u32).to_le_bytes());
+        out.extend_from_slice(&self.size.x.to_le_bytes());
+        out.extend_from_slice(&self.size.y.to_le_bytes());
+        out.extend_from_slice(&metadata.tick.to_le_bytes());
+        out.extend_from_slice(&metadata.time.to_le_bytes());
+        for value in [
+            metadata.dx,
+            metadata.dt,
+            metadata.v,
+            metadata.frequency,
+            metadata.amplitude,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
         }
+        out.extend_from_slice(&[DTYPE_F32, metadata.kind.id()]);
+        out.extend_from_slice(&(config.len() as u16).to_le_bytes());
+        out.extend_from_slice(config);
+        out.resize(header_len, 0);
+
+        out.extend_from_slice(bytemuck::cast_slice(&self.data));
+        out
     }
 }
 
+impl SnapshotMetadata {
+    pub fn new(kind: SnapshotType, params: &SimulationParameters, config: Option<PathBuf>) -> Self {
+        Self {
+            kind,
+            tick: params.tick,
+            time: params.tick as f64 * params.dt as f64,
+            dx: params.dx,
+            dt: params.dt,
+            v: params.v,
+            frequency: params.frequency,
+            amplitude: params.amplitude,
+            config,
+        }
+    }
+}
+
+/// Reinterprets little-endian f32 cells, checking there is one for every position.
+fn cells(bytes: &[u8], size: Vector2<u32>) -> Result<Vec<f32>> {
+    let cells = size.x as usize * size.y as usize;
+    ensure!(
+        bytes.len() == cells * 4,
+        "Expected {cells} cells for a {}×{} snapshot, found {} bytes.",
+        size.x,
+        size.y,
+        bytes.len()
+    );
+
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+        .collect())
+}
+
 impl SnapshotQueue {
+    pub fn new(format: SnapshotFormat, mesh: MeshOptions) -> Self {
+        Self {
+            snapshots: Vec::new(),
+            pending: Vec::new(),
+            writer: Writer::new(),
+            format,
+            mesh,
+        }
+    }
+
+    /// Queues a snapshot, in the format from the extension of its name or `format` without one.
     pub fn push(&mut self, snapshot: SnapshotType, name: Option<String>) {
-        self.snapshots.push((snapshot, name));
+        let format = (name.as_deref())
+            .and_then(|x| SnapshotFormat::from_path(Path::new(x)))
+            .unwrap_or(self.format);
+        self.snapshots.push((snapshot, name, format));
+    }
+
+    /// Queues a snapshot with a dated name in the given format.
+    pub fn push_format(&mut self, snapshot: SnapshotType, format: SnapshotFormat) {
+        self.snapshots.push((snapshot, None, format));
     }
 
     pub fn extend(&mut self, snapshots: Vec<(SnapshotType, Option<String>)>) {
-        self.snapshots.extend(snapshots);
+        for (snapshot, name) in snapshots {
+            self.push(snapshot, name);
+        }
     }
 
-    pub fn pop(&mut self) -> Option<(SnapshotType, Option<String>)> {
+    fn pop(&mut self) -> Option<(SnapshotType, Option<String>, SnapshotFormat)> {
         self.snapshots.pop()
     }
+
+    /// Stores a finished read, returning the data if no snapshot is waiting on it.
+    pub fn receive(&mut self, id: u64, data: Vec<f32>) -> Option<Vec<f32>> {
+        let field = (self.pending.iter_mut())
+            .flat_map(|x| x.fields.iter_mut())
+            .find(|(field, _)| *field == id);
+        match field {
+            Some((_, field)) => {
+                *field = Some(data);
+                None
+            }
+            None => Some(data),
+        }
+    }
 }
 
-impl Simulation {
-    fn stage_state(&self, encoder: &mut CommandEncoder) -> &Buffer {
-        let offset = (self.parameters.tick % 3) * (self.size.x * self.size.y * 4) as u64;
-        encoder.copy_buffer_to_buffer(
-            &self.states,
-            offset,
-            &self.staging_buffer,
-            0,
-            self.staging_buffer.size(),
-        );
-        &self.staging_buffer
+impl PendingSnapshot {
+    fn is_ready(&self) -> bool {
+        self.fields.iter().all(|(_, data)| data.is_some())
+    }
+
+    /// Encodes and writes the snapshot to the `states` directory, run on the writer thread.
+    fn write(self, size: Vector2<u32>, map: Vec<u8>) -> Result<()> {
+        let shape = [size.y as usize, size.x as usize];
+        let mut fields = self.fields.into_iter().map(|(_, data)| data.unwrap());
+        let kind = self.metadata.kind.clone();
+        let data = match self.format {
+            SnapshotFormat::Bin => Snapshot {
+                size,
+                metadata: Some(self.metadata),
+                data: fields.next().unwrap(),
+            }
+            .encode(),
+            SnapshotFormat::Npy => npy::encode(&shape, &fields.next().unwrap()),
+            SnapshotFormat::Npz => {
+                let (state, energy) = (fields.next().unwrap(), fields.next().unwrap());
+                encode_npz(shape, &self.metadata, &state, &energy, &map)?
+            }
+            SnapshotFormat::Stl | SnapshotFormat::Ply => {
+                let mesh = Mesh::height_field(size, &fields.next().unwrap(), &self.mesh);
+                match self.format {
+                    SnapshotFormat::Stl => mesh.encode_stl(),
+                    _ => mesh.encode_ply(),
+                }
+            }
+        };
+
+        let path = if let Some(name) = self.name {
+            fs::create_dir_all("states")?;
+            Path::new("states").join(name)
+        } else {
+            save_dated_file("states", kind.name(), self.format.extension())?
+        };
+
+        fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+/// Bundles both snapshot types with the map and parameters, see `SnapshotFormat::Npz`.
+fn encode_npz(
+    shape: [usize; 2],
+    metadata: &SnapshotMetadata,
+    state: &[f32],
+    energy: &[f32],
+    map: &[u8],
+) -> Result<Vec<u8>> {
+    let mut npz = NpzWriter::new(Cursor::new(Vec::new()));
+    npz.add("state", &shape, state)?;
+    npz.add("energy", &shape, energy)?;
+    npz.add("map", &[shape[0], shape[1], 4], map)?;
+
+    npz.add("tick", &[], &[metadata.tick])?;
+    npz.add("time", &[], &[metadata.time])?;
+    for (name, value) in [
+        ("dx", metadata.dx),
+        ("dt", metadata.dt),
+        ("v", metadata.v),
+        ("frequency", metadata.frequency),
+        ("amplitude", metadata.amplitude),
+    ] {
+        npz.add(name, &[], &[value])?;
+    }
+
+    Ok(npz.finish()?.into_inner())
+}
+
+impl<S: Solver> Simulation<S> {
+    /// Writes any queued checkpoints, starts reading back queued snapshots and writes the ones that have arrived.
+    /// Snapshots are written to the `states` directory in the background, so errors may come from an earlier call.
+    pub fn save_snapshots(&mut self) -> Result<()> {
+        self.process_snapshots(false)
+    }
+
+    /// Waits until every queued snapshot has been written.
+    pub fn flush_snapshots(&mut self) -> Result<()> {
+        self.process_snapshots(true)
+    }
+
+    /// Requests the fields of every queued snapshot from the solver, so they're read before anything changes them.
+    pub(super) fn request_snapshots(&mut self) {
+        let queue = &mut self.snapshot;
+        while let Some((kind, name, format)) = queue.pop() {
+            let fields = (format.fields(&kind).into_iter())
+                .map(|field| {
+                    let id = self.next_read;
+                    self.next_read += 1;
+                    self.solver.request_read(field, self.parameters.tick, id);
+                    (id, None)
+                })
+                .collect();
+
+            let path = self.config_path.clone();
+            queue.pending.push(PendingSnapshot {
+                name,
+                format,
+                mesh: queue.mesh,
+                metadata: SnapshotMetadata::new(kind, &self.parameters, path),
+                fields,
+            });
+        }
+    }
+
+    fn process_snapshots(&mut self, wait: bool) -> Result<()> {
+        for name in mem::take(&mut self.checkpoints) {
+            let path = match name {
+                Some(name) => Path::new("checkpoints").join(name),
+                None => save_dated_file("checkpoints", "checkpoint", "ckpt")?,
+            };
+            self.save_checkpoint(&path)?;
+        }
+
+        self.request_snapshots();
+        self.poll_reads(wait)?;
+        let queue = &mut self.snapshot;
+        let (ready, pending) = mem::take(&mut queue.pending)
+            .into_iter()
+            .partition::<Vec<_>, _>(PendingSnapshot::is_ready);
+        queue.pending = pending;
+
+        for snapshot in ready {
+            let size = self.size;
+            let map = match snapshot.format {
+                SnapshotFormat::Npz => self.map.clone(),
+                _ => Vec::new(),
+            };
+            queue.writer.push(move || snapshot.write(size, map));
+        }
+
+        if wait {
+            queue.writer.flush()
+        } else {
+            queue.writer.poll()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use nalgebra::Vector2;
+
+    use super::{encode_npz, Snapshot, SnapshotMetadata, SnapshotType};
+
+    fn snapshot(config: Option<&str>) -> Snapshot {
+        Snapshot {
+            size: Vector2::new(3, 2),
+            metadata: Some(SnapshotMetadata {
+                kind: SnapshotType::Energy,
+                tick: 1 << 40,
+                time: 1.25e-9,
+                dx: 5e-6,
+                dt: 1.16e-14,
+                v: 299_792_458.0,
+                frequency: 4.3e14,
+                amplitude: 0.05,
+                config: config.map(PathBuf::from),
+            }),
+            data: vec![0.0, -1.5, 2.0, f32::MAX, 1e-30, 6.0],
+        }
+    }
+
+    #[test]
+    fn round_trips_header() {
+        for config in [None, Some("configs/lens.toml"), Some("a.toml")] {
+            let bytes = snapshot(config).encode();
+            assert_eq!(bytes.len() % 4, 0);
+
+            let parsed = Snapshot::parse(&bytes).unwrap();
+            let (expected, metadata) = (snapshot(config), parsed.metadata.unwrap());
+            let expected_metadata = expected.metadata.unwrap();
+            assert_eq!(parsed.size, expected.size);
+            assert_eq!(parsed.data, expected.data);
+            assert_eq!(metadata.kind, expected_metadata.kind);
+            assert_eq!(metadata.tick, expected_metadata.tick);
+            assert_eq!(metadata.time, expected_metadata.time);
+            assert_eq!(metadata.dx, expected_metadata.dx);
+            assert_eq!(metadata.dt, expected_metadata.dt);
+            assert_eq!(metadata.v, expected_metadata.v);
+            assert_eq!(metadata.frequency, expected_metadata.frequency);
+            assert_eq!(metadata.amplitude, expected_metadata.amplitude);
+            assert_eq!(metadata.config, expected_metadata.config);
+        }
+    }
+
+    #[test]
+    fn truncates_long_configs() {
+        let config = "é".repeat(40_000);
+        let bytes = snapshot(Some(&config)).encode();
+        let metadata = Snapshot::parse(&bytes).unwrap().metadata.unwrap();
+        assert_eq!(metadata.config, Some(PathBuf::from("é".repeat(32_767))));
+    }
+
+    #[test]
+    fn parses_legacy_snapshots() {
+        let mut bytes = Vec::new();
+        for value in [3u32, 2] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let parsed = Snapshot::parse(&bytes).unwrap();
+        assert_eq!(parsed.size, Vector2::new(3, 2));
+        assert!(parsed.metadata.is_none());
+        assert_eq!(parsed.data, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        // Snapshots without metadata are still written in the legacy layout
+        let encoded = Snapshot {
+            metadata: None,
+            ..parsed
+        }
+        .encode();
+        assert_eq!(encoded, bytes);
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        let bytes = snapshot(None).encode();
+        assert!(Snapshot::parse(&bytes[..4]).is_err());
+        assert!(Snapshot::parse(&bytes[..40]).is_err());
+        assert!(Snapshot::parse(&bytes[..bytes.len() - 4]).is_err());
+
+        let mut future = bytes.clone();
+        future[8] = 2;
+        assert!(Snapshot::parse(&future).is_err());
+        let mut kind = bytes;
+        kind[61] = 7;
+        assert!(Snapshot::parse(&kind).is_err());
+    }
+
+    #[test]
+    fn round_trips_npz_energy() {
+        let expected = snapshot(None);
+        let metadata = expected.metadata.unwrap();
+        let state = vec![9.0; 6];
+        let bytes = encode_npz([2, 3], &metadata, &state, &expected.data, &[0; 24]).unwrap();
+
+        let parsed = Snapshot::parse_npz(&bytes).unwrap();
+        let parsed_metadata = parsed.metadata.unwrap();
+        assert_eq!(parsed.size, expected.size);
+        assert_eq!(parsed.data, expected.data);
+        assert_eq!(parsed_metadata.tick, metadata.tick);
+        assert_eq!(parsed_metadata.time, metadata.time);
+        assert_eq!(parsed_metadata.dt, metadata.dt);
+        assert_eq!(parsed_metadata.amplitude, metadata.amplitude);
     }
 }
@@ -0,0 +1,672 @@
+use std::{cmp::Reverse, f32::consts::TAU, mem};
+
+use anyhow::{bail, Result};
+use nalgebra::Vector2;
+use rayon::prelude::*;
+
+use crate::simulation::{
+    boundary::{Boundaries, Boundary},
+    snapshot::SnapshotType,
+    source::SourceContext,
+    SimulationParameters,
+};
+
+use super::{stencil_weights, Scene, Solver, SolverState};
+
+/// Multi-threaded reference implementation of `shader.wgsl`.
+/// Custom tick shaders can not be run on the CPU, so configs using one are rejected.
+pub struct CpuSolver {
+    size: Vector2<u32>,
+
+    states: [Vec<f32>; 3],
+    map: Vec<[u8; 4]>,
+    average_energy: Vec<f32>,
+    pml: [Vec<[f32; 2]>; 2],
+    pml_thickness: u32,
+    stencil: &'static [f32],
+    /// Lock-in means of each frequency, one field after another.
+    lock_in: Vec<[f32; 2]>,
+
+    audio_in: Option<(Vec<f32>, Vector2<u32>)>,
+    audio_out: Vec<f32>,
+    backup: Option<Backup>,
+    candidate: Option<Backup>,
+    /// Reads requested since the last `poll_reads`, which finish immediately.
+    reads: Vec<(u64, Vec<f32>)>,
+}
+
+/// Copy of the simulation state taken by `Solver::backup`.
+#[derive(Clone)]
+struct Backup {
+    states: [Vec<f32>; 3],
+    average_energy: Vec<f32>,
+    pml: [Vec<[f32; 2]>; 2],
+    lock_in: Vec<[f32; 2]>,
+}
+
+/// Values from the simulation context that are constant across one tick.
+struct TickContext {
+    width: usize,
+    height: usize,
+    pml_thickness: usize,
+    stencil: &'static [f32],
+
+    tick: u64,
+    boundaries: Boundaries,
+    c: f32,
+    amplitude: f32,
+    frequency: f32,
+    loss: f32,
+    audio_sample: Option<f32>,
+    sources: Vec<SourceContext>,
+}
+
+/// The time levels read while computing the next one.
+struct Levels<'a> {
+    current: &'a [f32],
+    last: &'a [f32],
+    pml: &'a [[f32; 2]],
+}
+
+impl CpuSolver {
+    pub fn new(scene: &Scene) -> Result<Self> {
+        if scene.shader.is_some() {
+            bail!("Custom shaders are only supported by the GPU solver.");
+        }
+
+        let cells = (scene.size.x * scene.size.y) as usize;
+        Ok(Self {
+            size: scene.size,
+
+            states: [vec![0.0; cells], vec![0.0; cells], vec![0.0; cells]],
+            map: scene
+                .map
+                .chunks_exact(4)
+                .map(|x| [x[0], x[1], x[2], x[3]])
+                .collect(),
+            average_energy: vec![0.0; cells],
+            pml: [vec![[0.0; 2]; cells], vec![[0.0; 2]; cells]],
+            pml_thickness: scene.pml_thickness,
+            stencil: stencil_weights(scene.stencil_order).unwrap(),
+            lock_in: vec![[0.0; 2]; cells * scene.lock_in],
+
+            audio_in: scene
+                .audio
+                .as_ref()
+                .map(|audio| (audio.samples.clone(), audio.pickup)),
+            audio_out: Vec::new(),
+            backup: None,
+            candidate: None,
+            reads: Vec::new(),
+        })
+    }
+}
+
+impl Solver for CpuSolver {
+    fn tick(&mut self, params: &SimulationParameters) {
+        let ctx = TickContext {
+            width: self.size.x as usize,
+            height: self.size.y as usize,
+            pml_thickness: self.pml_thickness as usize,
+            stencil: self.stencil,
+
+            tick: params.tick,
+            boundaries: params.boundaries,
+            c: params.v * (params.dt / params.dx),
+            amplitude: params.amplitude,
+            frequency: TAU * params.dt * params.frequency,
+            loss: params.loss * params.dt,
+            audio_sample: self.audio_in.as_ref().map(|(samples, _)| {
+                samples
+                    .get(params.tick as usize)
+                    .copied()
+                    .unwrap_or_default()
+            }),
+            sources: (params.sources.iter()).map(|x| x.context(params)).collect(),
+        };
+
+        let [s0, s1, s2] = &mut self.states;
+        let (next, current, last) = match params.tick % 3 {
+            0 => (s0, &*s2, &*s1),
+            1 => (s1, &*s0, &*s2),
+            _ => (s2, &*s1, &*s0),
+        };
+        let [p0, p1] = &mut self.pml;
+        let (pml_next, pml) = match params.tick % 2 {
+            0 => (p0, &*p1),
+            _ => (p1, &*p0),
+        };
+        let levels = Levels { current, last, pml };
+
+        // The shader records the pickup before the audio input is added.
+        if let Some((_, pickup)) = &self.audio_in {
+            let (x, y) = (pickup.x as usize, pickup.y as usize);
+            let i = y * ctx.width + x;
+            let value = boundary(&ctx, &levels, x, y)
+                .unwrap_or_else(|| interior(&ctx, self.map[i], &levels, x, y));
+            self.audio_out.push(value);
+        }
+
+        let width = ctx.width;
+        let map = &self.map;
+        next.par_chunks_mut(width)
+            .zip(self.average_energy.par_chunks_mut(width))
+            .zip(pml_next.par_chunks_mut(width))
+            .enumerate()
+            .for_each(|(y, ((next, energy), pml_next))| {
+                for x in 0..width {
+                    let i = y * width + x;
+                    if in_pml(&ctx, x, y) {
+                        let mul = (map[i][0] == 0) as u8 as f32;
+                        next[x] = pml_tick(&ctx, map[i], &levels, &mut pml_next[x], x, y) * mul;
+                        continue;
+                    }
+
+                    next[x] = match boundary(&ctx, &levels, x, y) {
+                        Some(value) => value,
+                        None => {
+                            let value =
+                                interior(&ctx, map[i], &levels, x, y) + source(&ctx, map[i], x, y);
+                            let nd = ctx.tick as f32 + 1.0;
+                            energy[x] = energy[x] * (ctx.tick as f32 / nd) + value.powi(2) / nd;
+                            value
+                        }
+                    };
+                }
+            });
+    }
+
+    fn take_audio(&mut self) -> Vec<f32> {
+        mem::take(&mut self.audio_out)
+    }
+
+    fn read_state(&mut self, tick: u64) -> Vec<f32> {
+        self.states[((tick + 2) % 3) as usize].clone()
+    }
+
+    fn read_average_energy(&mut self) -> Vec<f32> {
+        self.average_energy.clone()
+    }
+
+    fn request_read(&mut self, kind: SnapshotType, tick: u64, id: u64) {
+        let data = match kind {
+            SnapshotType::State => self.read_state(tick),
+            SnapshotType::Energy => self.read_average_energy(),
+        };
+        self.reads.push((id, data));
+    }
+
+    fn request_cells(&mut self, kind: SnapshotType, tick: u64, cells: &[u32], id: u64) {
+        let field = match kind {
+            SnapshotType::State => &self.states[((tick + 2) % 3) as usize],
+            SnapshotType::Energy => &self.average_energy,
+        };
+        let data = cells.iter().map(|&x| field[x as usize]).collect();
+        self.reads.push((id, data));
+    }
+
+    fn poll_reads(&mut self, _wait: bool) -> Result<Vec<(u64, Vec<f32>)>> {
+        Ok(mem::take(&mut self.reads))
+    }
+
+    fn lock_in(&mut self, tick: u64, phases: &[[f32; 2]], samples: u64) {
+        let state = &self.states[(tick % 3) as usize];
+        let n = samples as f32;
+        for (means, [cos, sin]) in self.lock_in.chunks_exact_mut(state.len()).zip(phases) {
+            means.par_iter_mut().zip(state).for_each(|(mean, u)| {
+                let sample = [2.0 * u * cos, 2.0 * u * -sin];
+                *mean = [0, 1].map(|i| mean[i] * (n / (n + 1.0)) + sample[i] / (n + 1.0));
+            });
+        }
+    }
+
+    fn request_lock_in(&mut self, id: u64) {
+        let data = bytemuck::cast_slice(&self.lock_in).to_vec();
+        self.reads.push((id, data));
+    }
+
+    fn save_state(&mut self, _tick: u64) -> SolverState {
+        let pml = match self.pml_thickness {
+            0 => Vec::new(),
+            _ => self.pml.concat(),
+        };
+
+        SolverState {
+            states: self.states.concat(),
+            average_energy: self.average_energy.clone(),
+            pml,
+            lock_in: self.lock_in.clone(),
+            audio: self.audio_out.clone(),
+        }
+    }
+
+    fn load_state(&mut self, _tick: u64, state: SolverState) {
+        let cells = self.average_energy.len();
+        for (level, state) in self.states.iter_mut().zip(state.states.chunks_exact(cells)) {
+            level.copy_from_slice(state);
+        }
+        for (level, state) in self.pml.iter_mut().zip(state.pml.chunks_exact(cells)) {
+            level.copy_from_slice(state);
+        }
+
+        self.average_energy = state.average_energy;
+        self.lock_in = state.lock_in;
+        self.audio_out = state.audio;
+    }
+
+    fn buffered_audio(&self, _tick: u64) -> usize {
+        0
+    }
+
+    fn request_peak(&mut self, tick: u64, id: u64) {
+        let state = &self.states[((tick + 2) % 3) as usize];
+        let (bits, Reverse(index)) = (state.par_iter().enumerate())
+            .map(|(i, x)| (x.to_bits() & 0x7FFF_FFFF, Reverse(i)))
+            .max()
+            .unwrap();
+        let data = vec![f32::from_bits(bits), f32::from_bits(index as u32)];
+        self.reads.push((id, data));
+    }
+
+    fn backup(&mut self) {
+        self.candidate = Some(Backup {
+            states: self.states.clone(),
+            average_energy: self.average_energy.clone(),
+            pml: self.pml.clone(),
+            lock_in: self.lock_in.clone(),
+        });
+    }
+
+    fn keep_backup(&mut self) {
+        if let Some(candidate) = self.candidate.take() {
+            self.backup = Some(candidate);
+        }
+    }
+
+    fn restore_backup(&mut self) {
+        if let Some(backup) = self.backup.clone() {
+            self.states = backup.states;
+            self.average_energy = backup.average_energy;
+            self.pml = backup.pml;
+            self.lock_in = backup.lock_in;
+        }
+    }
+
+    fn reset_states(&mut self) {
+        self.states.iter_mut().for_each(|x| x.fill(0.0));
+        self.pml.iter_mut().for_each(|x| x.fill([0.0; 2]));
+        self.lock_in.fill([0.0; 2]);
+    }
+
+    fn reset_average_energy(&mut self) {
+        self.average_energy.fill(0.0);
+    }
+}
+
+/// The first edge the cell lies on with its own update, along with the offset pointing inward.
+/// Stencils read across Neumann and periodic edges instead.
+fn closed_edge(ctx: &TickContext, x: usize, y: usize) -> Option<(Boundary, [isize; 2])> {
+    let edges = ctx.boundaries;
+    [
+        (x == 0, edges.left, [1, 0]),
+        (x == ctx.width - 1, edges.right, [-1, 0]),
+        (y == 0, edges.top, [0, 1]),
+        (y == ctx.height - 1, edges.bottom, [0, -1]),
+    ]
+    .into_iter()
+    .find(|(on_edge, boundary, _)| *on_edge && !boundary.reads_across())
+    .map(|(_, boundary, inward)| (boundary, inward))
+}
+
+/// Index of a nearby cell, wrapping around periodic edges and mirroring across the others.
+fn neighbor(ctx: &TickContext, x: usize, y: usize, offset: [isize; 2]) -> usize {
+    let resolve = |pos: usize, offset: isize, size: usize, start: Boundary, end: Boundary| {
+        let (pos, size) = (pos as isize + offset, size as isize);
+        (if pos < 0 {
+            match start {
+                Boundary::Periodic => pos + size,
+                _ => -pos,
+            }
+        } else if pos >= size {
+            match end {
+                Boundary::Periodic => pos - size,
+                _ => 2 * (size - 1) - pos,
+            }
+        } else {
+            pos
+        }) as usize
+    };
+
+    let edges = ctx.boundaries;
+    let x = resolve(x, offset[0], ctx.width, edges.left, edges.right);
+    let y = resolve(y, offset[1], ctx.height, edges.top, edges.bottom);
+    y * ctx.width + x
+}
+
+/// Updates the cells on absorbing and Dirichlet edges, returning None for all others.
+fn boundary(ctx: &TickContext, levels: &Levels, x: usize, y: usize) -> Option<f32> {
+    let (boundary, inward) = closed_edge(ctx, x, y)?;
+    Some(match boundary {
+        Boundary::Dirichlet => 0.0,
+        _ => {
+            let c = levels.current[y * ctx.width + x];
+            c + (levels.current[neighbor(ctx, x, y, inward)] - c) * ctx.c
+        }
+    })
+}
+
+fn wave_speed(ctx: &TickContext, map: [u8; 4]) -> f32 {
+    ctx.c * (map[2] as f32 / 255.0 * 2.0)
+}
+
+/// Distance to the closest edge with its own update, which wide stencils can't read past.
+fn closed_distance(ctx: &TickContext, x: usize, y: usize) -> usize {
+    let edges = ctx.boundaries;
+    [
+        (edges.left, x),
+        (edges.right, ctx.width - 1 - x),
+        (edges.top, y),
+        (edges.bottom, ctx.height - 1 - y),
+    ]
+    .into_iter()
+    .filter(|(boundary, _)| !boundary.reads_across())
+    .map(|(_, distance)| distance)
+    .min()
+    .unwrap_or(usize::MAX)
+}
+
+/// Cells too close to a closed edge for the wide stencil fall back to the second order one.
+fn laplacian(ctx: &TickContext, current: &[f32], x: usize, y: usize) -> f32 {
+    let at = |offset| current[neighbor(ctx, x, y, offset)];
+    let mut weights = ctx.stencil;
+    if closed_distance(ctx, x, y) < weights.len() - 1 {
+        weights = stencil_weights(2).unwrap();
+    }
+
+    (weights.iter().enumerate().skip(1)).fold(
+        2.0 * weights[0] * current[y * ctx.width + x],
+        |out, (i, weight)| {
+            let i = i as isize;
+            out + weight * (at([-i, 0]) + at([i, 0]) + at([0, -i]) + at([0, i]))
+        },
+    )
+}
+
+fn interior(ctx: &TickContext, map: [u8; 4], levels: &Levels, x: usize, y: usize) -> f32 {
+    let mul = (map[0] == 0) as u8 as f32;
+    let c = wave_speed(ctx, map);
+    let (current, last) = (levels.current, levels.last);
+    let i = y * ctx.width + x;
+    let loss = ctx.loss * map[3] as f32 / 255.0;
+    (2.0 * current[i] - (1.0 - loss / 2.0) * last[i] + c.powi(2) * laplacian(ctx, current, x, y))
+        / (1.0 + loss / 2.0)
+        * mul
+}
+
+/// Oscillator or audio input injected at emitters, along with the point sources.
+fn source(ctx: &TickContext, map: [u8; 4], x: usize, y: usize) -> f32 {
+    let distance = 255.0 - map[1] as f32;
+    let source = ctx.amplitude * (-distance.abs()).exp();
+    let emitter = match ctx.audio_sample {
+        Some(sample) => source * sample,
+        None => source * ((ctx.tick as u32 as f32 * ctx.frequency) % TAU).cos(),
+    };
+
+    let pos = Vector2::new(x as f32, y as f32);
+    (ctx.sources.iter()).fold(emitter, |out, source| out + source.value(pos))
+}
+
+fn in_pml(ctx: &TickContext, x: usize, y: usize) -> bool {
+    let (n, edges) = (ctx.pml_thickness, ctx.boundaries);
+    let absorbing = |boundary| boundary == Boundary::Absorbing;
+    n > 0
+        && (absorbing(edges.left) && x <= n
+            || absorbing(edges.right) && x >= ctx.width - 1 - n
+            || absorbing(edges.top) && y <= n
+            || absorbing(edges.bottom) && y >= ctx.height - 1 - n)
+}
+
+fn pml_sigma(ctx: &TickContext, pos: Vector2<f32>) -> Vector2<f32> {
+    let n = ctx.pml_thickness as f32;
+    let edges = ctx.boundaries;
+    let absorbing = |boundary| (boundary == Boundary::Absorbing) as u8 as f32;
+    let near = Vector2::new(absorbing(edges.left), absorbing(edges.top));
+    let far = Vector2::new(absorbing(edges.right), absorbing(edges.bottom));
+    let size = Vector2::new(ctx.width as f32 - 1.0, ctx.height as f32 - 1.0);
+
+    let strength = 3.0 * 1e3f32.ln() / 2.0 * ctx.c / n;
+    Vector2::from_fn(|i, _| {
+        let depth = ((n - pos[i]) * near[i])
+            .max((pos[i] - (size[i] - n)) * far[i])
+            .max(0.0)
+            / n;
+        strength * depth * depth
+    })
+}
+
+/// See `pml_tick` in `shader.wgsl`.
+fn pml_tick(
+    ctx: &TickContext,
+    map: [u8; 4],
+    levels: &Levels,
+    psi_next: &mut [f32; 2],
+    x: usize,
+    y: usize,
+) -> f32 {
+    if closed_edge(ctx, x, y).is_some() {
+        return 0.0;
+    }
+
+    let (current, pml) = (levels.current, levels.pml);
+    let i = y * ctx.width + x;
+    let u = current[i];
+    let right = current[neighbor(ctx, x, y, [1, 0])];
+    let down = current[neighbor(ctx, x, y, [0, 1])];
+
+    // The auxiliary field is odd across a symmetry plane
+    let psi = pml[i];
+    let mut back = psi.map(|x| -x);
+    if x > 0 || ctx.boundaries.left == Boundary::Periodic {
+        back[0] = pml[neighbor(ctx, x, y, [-1, 0])][0];
+    }
+    if y > 0 || ctx.boundaries.top == Boundary::Periodic {
+        back[1] = pml[neighbor(ctx, x, y, [0, -1])][1];
+    }
+    let divergence = psi[0] - back[0] + psi[1] - back[1];
+
+    let c2 = wave_speed(ctx, map).powi(2);
+    let pos = Vector2::new(x as f32, y as f32);
+    let sigma = pml_sigma(ctx, pos);
+    let s = sigma.x + sigma.y;
+
+    let sx = pml_sigma(ctx, pos + Vector2::new(0.5, 0.0));
+    let sy = pml_sigma(ctx, pos + Vector2::new(0.0, 0.5));
+    *psi_next = [
+        ((1.0 - sx.x / 2.0) * psi[0] + c2 * (sx.y - sx.x) * (right - u)) / (1.0 + sx.x / 2.0),
+        ((1.0 - sy.y / 2.0) * psi[1] + c2 * (sy.x - sy.y) * (down - u)) / (1.0 + sy.y / 2.0),
+    ];
+
+    (2.0 * u - (1.0 - s / 2.0) * levels.last[i] - sigma.x * sigma.y * u
+        + c2 * laplacian(ctx, current, x, y)
+        + divergence)
+        / (1.0 + s / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        simulation::{
+            boundary::{Boundaries, Boundary},
+            solver::{Scene, Solver},
+            SimulationParameters,
+        },
+    };
+
+    use super::CpuSolver;
+
+    /// Builds a solver with a Courant number of 0.5 and no oscillator.
+    fn setup(size: (u32, u32), boundaries: Boundaries) -> (CpuSolver, SimulationParameters) {
+        let mut config = Config::default();
+        config.size = size;
+        config.parameters.dt = 0.5;
+        config.parameters.dx = 1.0;
+        config.parameters.v = 1.0;
+        config.parameters.boundary = Some(boundaries);
+        config.parameters.stencil_order = 4;
+        config.oscillator.amplitude = 0.0;
+
+        let solver = CpuSolver::new(&Scene::load(&config).unwrap()).unwrap();
+        (solver, SimulationParameters::new(&config))
+    }
+
+    /// Starts the field at rest with a Gaussian pulse of unit height.
+    fn pulse(solver: &mut CpuSolver, center: (f32, f32), width: f32) {
+        let size = solver.size;
+        let field = (0..size.x * size.y)
+            .map(|i| {
+                let (x, y) = ((i % size.x) as f32, (i / size.x) as f32);
+                let distance = (x - center.0).powi(2) + (y - center.1).powi(2);
+                (-distance / (2.0 * width * width)).exp()
+            })
+            .collect::<Vec<_>>();
+        solver.states = [vec![0.0; field.len()], field.clone(), field];
+    }
+
+    fn run(solver: &mut CpuSolver, params: &mut SimulationParameters, ticks: u64) -> Vec<f32> {
+        for _ in 0..ticks {
+            solver.tick(params);
+            params.tick += 1;
+        }
+        solver.read_state(params.tick)
+    }
+
+    /// Value with the largest magnitude along the first row between `start` and `end`.
+    fn extremum(field: &[f32], start: usize, end: usize) -> f32 {
+        (field[start..end].iter().copied())
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap()
+    }
+
+    /// Sends the left half of a plane pulse into the left edge and returns it once it is back where it started.
+    fn reflect(left: Boundary) -> f32 {
+        let boundaries = Boundaries {
+            left,
+            right: Boundary::Absorbing,
+            top: Boundary::Periodic,
+            bottom: Boundary::Periodic,
+        };
+        let (mut solver, mut params) = setup((200, 4), boundaries);
+        pulse(&mut solver, (40.0, 0.0), 4.0);
+        // Uniform along y, so it travels as a plane wave
+        for level in &mut solver.states[1..] {
+            for row in 1..4 {
+                let (first, rest) = level.split_at_mut(200 * row);
+                rest[..200].copy_from_slice(&first[..200]);
+            }
+        }
+
+        // 80 cells at half a cell per tick
+        let field = run(&mut solver, &mut params, 160);
+        extremum(&field, 10, 70)
+    }
+
+    #[test]
+    fn spreads_symmetrically() {
+        let boundaries = Boundaries::uniform(Boundary::Absorbing);
+        let (mut solver, mut params) = setup((33, 33), boundaries);
+        pulse(&mut solver, (16.0, 16.0), 2.0);
+        let field = run(&mut solver, &mut params, 20);
+
+        let at = |x: usize, y: usize| field[y * 33 + x];
+        assert!(at(16, 16).abs() < 0.5);
+        for y in 0..33 {
+            for x in 0..33 {
+                let value = at(x, y);
+                for mirrored in [at(32 - x, y), at(x, 32 - y), at(y, x)] {
+                    assert!((value - mirrored).abs() < 1e-5, "{value} != {mirrored}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn absorbing_boundary_lets_waves_out() {
+        let energy = |boundary| {
+            let (mut solver, mut params) = setup((48, 48), Boundaries::uniform(boundary));
+            pulse(&mut solver, (24.0, 24.0), 3.0);
+            let field = run(&mut solver, &mut params, 300);
+            field.iter().map(|x| x * x).sum::<f32>()
+        };
+
+        let absorbed = energy(Boundary::Absorbing);
+        let reflected = energy(Boundary::Dirichlet);
+        assert!(reflected > 1.0);
+        assert!(absorbed < reflected * 0.2, "{absorbed} vs {reflected}");
+    }
+
+    #[test]
+    fn dirichlet_inverts_reflection() {
+        assert!(reflect(Boundary::Dirichlet) < -0.3);
+    }
+
+    #[test]
+    fn neumann_keeps_reflection_sign() {
+        assert!(reflect(Boundary::Neumann) > 0.3);
+    }
+
+    #[test]
+    fn periodic_wraps_symmetrically() {
+        let size = (32, 24);
+        let boundaries = Boundaries::uniform(Boundary::Periodic);
+        let (mut centered, mut params) = setup(size, boundaries);
+        pulse(&mut centered, (16.0, 12.0), 3.0);
+        let centered = run(&mut centered, &mut params, 60);
+
+        // The same pulse shifted across the corner
+        let (shift_x, shift_y) = (20, 15);
+        let (mut wrapped, mut params) = setup(size, boundaries);
+        pulse(&mut wrapped, (16.0, 12.0), 3.0);
+        for level in &mut wrapped.states {
+            let old = level.clone();
+            for (i, value) in old.into_iter().enumerate() {
+                let (x, y) = (i as u32 % size.0, i as u32 / size.0);
+                let (x, y) = ((x + shift_x) % size.0, (y + shift_y) % size.1);
+                level[(y * size.0 + x) as usize] = value;
+            }
+        }
+        let wrapped = run(&mut wrapped, &mut params, 60);
+
+        assert!(centered.iter().any(|x| x.abs() > 0.05));
+        for (i, value) in centered.into_iter().enumerate() {
+            let (x, y) = (i as u32 % size.0, i as u32 / size.0);
+            let (x, y) = ((x + shift_x) % size.0, (y + shift_y) % size.1);
+            let shifted = wrapped[(y * size.0 + x) as usize];
+            assert!((value - shifted).abs() < 1e-5, "{value} != {shifted}");
+        }
+    }
+
+    #[test]
+    fn loss_decays_energy() {
+        let energy = |loss: f32, alpha: u8| {
+            let (mut solver, mut params) = setup((32, 32), Boundaries::uniform(Boundary::Periodic));
+            solver.map.iter_mut().for_each(|x| x[3] = alpha);
+            params.loss = loss;
+            pulse(&mut solver, (16.0, 16.0), 3.0);
+            // Loss only damps motion, so the mean of a closed domain would never decay
+            for level in &mut solver.states[1..] {
+                let mean = level.iter().sum::<f32>() / level.len() as f32;
+                level.iter_mut().for_each(|x| *x -= mean);
+            }
+            let field = run(&mut solver, &mut params, 100);
+            field.iter().map(|x| x * x).sum::<f32>()
+        };
+
+        let lossless = energy(0.0, 255);
+        let lossy = energy(0.1, 255);
+        assert!(lossless > 1.0);
+        assert!(lossy < lossless * 0.1, "{lossy} vs {lossless}");
+        // Cells without any of the loss are unaffected
+        assert_eq!(energy(0.1, 0), lossless);
+    }
+}
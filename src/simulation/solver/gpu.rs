@@ -0,0 +1,922 @@
+use std::{borrow::Cow, f32::consts::TAU, mem, sync::Arc};
+
+use anyhow::Result;
+use bytemuck::Pod;
+use encase::ShaderType;
+use nalgebra::Vector2;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroupDescriptor, BindGroupEntry, Buffer, BufferAddress, BufferDescriptor, BufferUsages,
+    CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+    Device, Queue, ShaderModuleDescriptor, ShaderSource,
+};
+
+use crate::{
+    misc::{
+        audio::OUTPUT_BUFFER_SIZE,
+        preprocess::{Data, Preprocessor},
+        readback::Readback,
+        util::download_buffer,
+    },
+    simulation::{snapshot::SnapshotType, source::SourceContext, SimulationParameters},
+};
+
+use super::{Scene, Solver, SolverState};
+
+const TICK_SIGNATURE: &str = "fn tick(x: u32, y: u32, mul: ptr<function, f32>, distance: ptr<function, f32>, c: ptr<function, f32>)";
+
+pub struct GpuSolver {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+
+    compute_pipeline: ComputePipeline,
+    gather_pipeline: ComputePipeline,
+    lock_in_pipeline: ComputePipeline,
+    size: Vector2<u32>,
+
+    states: Buffer,
+    map_buffer: Buffer,
+    average_energy_buffer: Buffer,
+    pml_buffer: Option<Buffer>,
+    lock_in_buffer: Option<Buffer>,
+    staging_buffer: Buffer,
+    audio: Option<AudioBuffers>,
+    peak_search: PeakSearch,
+    backup: Option<Backup>,
+    candidate: Option<Backup>,
+    readback: Readback<ReadTag>,
+    /// Finished reads not yet returned by `poll_reads`.
+    reads: Vec<(u64, Vec<f32>)>,
+    /// A read that failed outside of `poll_reads`, returned by its next call.
+    error: Option<anyhow::Error>,
+
+    /// Window size passed on to user shaders.
+    pub window: Vector2<u32>,
+}
+
+struct AudioBuffers {
+    audio_in_buffer: Buffer,
+    audio_out_buffer: Buffer,
+    samples: Vec<f32>,
+}
+
+/// What a pending readback is for.
+enum ReadTag {
+    /// A full block of the audio output ring.
+    Audio,
+    /// A field requested with `Solver::request_read`.
+    Field(u64),
+}
+
+/// Pipelines for `Solver::request_peak`, see `stability.wgsl`.
+struct PeakSearch {
+    find_max: ComputePipeline,
+    find_index: ComputePipeline,
+    peak_buffer: Buffer,
+}
+
+#[derive(ShaderType)]
+struct GatherContext {
+    offset: u32,
+    count: u32,
+}
+
+#[derive(ShaderType)]
+struct LockInContext {
+    size: Vector2<u32>,
+    offset: u32,
+    frequencies: u32,
+    samples: u32,
+}
+
+#[derive(ShaderType)]
+struct PeakContext {
+    size: Vector2<u32>,
+    offset: u32,
+}
+
+/// Copies of the buffers saved by `Solver::backup`.
+struct Backup {
+    states: Buffer,
+    average_energy: Buffer,
+    pml: Option<Buffer>,
+    lock_in: Option<Buffer>,
+}
+
+#[derive(ShaderType)]
+pub struct SimulationContext {
+    pub size: Vector2<u32>,
+    pub window: Vector2<u32>,
+    pub user: u32,
+
+    pub tick: u32,
+    pub ticks_per_dispatch: u32,
+    pub flags: u32,
+    pub boundaries: u32,
+    pub sources: u32,
+
+    pub c: f32,
+    pub amplitude: f32,
+    pub frequency: f32,
+    pub loss: f32,
+}
+
+impl GpuSolver {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>, scene: &Scene) -> Result<Self> {
+        let mut raw_shader = Cow::Borrowed(include_str!("../../shaders/shader.wgsl"));
+        if let Some(ref shader) = scene.shader {
+            let line_end = raw_shader.find('\n').unwrap();
+            raw_shader = Cow::Owned(format!(
+                "{TICK_SIGNATURE} {{\n{shader}\n}}{}",
+                &raw_shader[line_end..]
+            ));
+        }
+
+        let mut preprocessor = Preprocessor::new();
+        if let Some(audio) = &scene.audio {
+            preprocessor = preprocessor.define("AUDIO", Data::vec2(audio.pickup.x, audio.pickup.y));
+        } else {
+            preprocessor = preprocessor.define("OSCILLATOR", Data::Null);
+        }
+
+        if scene.stencil_order > 2 {
+            preprocessor = preprocessor
+                .define(&format!("STENCIL_{}", scene.stencil_order), Data::Null)
+                .define("STENCIL_RADIUS", Data::U32(scene.stencil_order / 2));
+        }
+
+        if scene.pml_thickness > 0 {
+            preprocessor = preprocessor.define("PML", Data::U32(scene.pml_thickness));
+        }
+
+        let raw_shader = preprocessor.process(&raw_shader);
+        let compute_shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(raw_shader.into()),
+        });
+
+        let cells = (scene.size.x * scene.size.y) as u64;
+        let map_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: scene.map.as_slice(),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let state_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: cells * 3 * 4,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let average_energy_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: cells * 4,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let pml_buffer = (scene.pml_thickness > 0).then(|| {
+            device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: cells * 2 * 2 * 4,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        });
+
+        let lock_in_buffer = (scene.lock_in > 0).then(|| {
+            device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: cells * scene.lock_in as u64 * 2 * 4,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        });
+
+        let staging_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: cells * 4,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let audio = scene.audio.as_ref().map(|audio| {
+            let audio_in_buffer = device.create_buffer_init(&BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&audio.samples),
+                usage: BufferUsages::STORAGE,
+            });
+
+            let buf_size = OUTPUT_BUFFER_SIZE as u64 * 4;
+            let audio_out_buffer = device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: buf_size,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            AudioBuffers {
+                audio_in_buffer,
+                audio_out_buffer,
+                samples: Vec::new(),
+            }
+        });
+
+        let compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: None,
+            module: &compute_shader,
+            entry_point: "main",
+        });
+
+        let gather_shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(include_str!("../../shaders/detector.wgsl").into()),
+        });
+        let gather_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: None,
+            module: &gather_shader,
+            entry_point: "main",
+        });
+
+        let lock_in_shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(include_str!("../../shaders/lock_in.wgsl").into()),
+        });
+        let lock_in_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: None,
+            layout: None,
+            module: &lock_in_shader,
+            entry_point: "main",
+        });
+
+        let peak_search = PeakSearch::new(&device);
+        let readback = Readback::new(device.clone(), queue.clone());
+        Ok(Self {
+            device,
+            queue,
+
+            compute_pipeline,
+            gather_pipeline,
+            lock_in_pipeline,
+            size: scene.size,
+
+            states: state_buffer,
+            map_buffer,
+            average_energy_buffer,
+            pml_buffer,
+            lock_in_buffer,
+            staging_buffer,
+            audio,
+            peak_search,
+            backup: None,
+            candidate: None,
+            readback,
+            reads: Vec::new(),
+            error: None,
+
+            window: scene.size,
+        })
+    }
+
+    pub fn get_state(&self) -> &Buffer {
+        &self.states
+    }
+
+    pub fn get_average_energy_buffer(&self) -> &Buffer {
+        &self.average_energy_buffer
+    }
+
+    /// The lock-in means, falling back to the average energy without a lock-in so there is always something to bind.
+    pub fn get_lock_in_buffer(&self) -> &Buffer {
+        (self.lock_in_buffer.as_ref()).unwrap_or(&self.average_energy_buffer)
+    }
+
+    pub fn get_context_buffer(&self, params: &SimulationParameters) -> Buffer {
+        let context = SimulationContext {
+            size: self.size,
+            window: self.window,
+            user: params.user,
+
+            tick: params.tick as u32,
+            ticks_per_dispatch: params.ticks_per_dispatch,
+            flags: params.flags.bits(),
+            boundaries: params.boundaries.bits(),
+            sources: params.sources.len() as u32,
+
+            c: params.v * (params.dt / params.dx),
+            amplitude: params.amplitude,
+            frequency: TAU * params.dt * params.frequency,
+            loss: params.loss * params.dt,
+        };
+
+        self.device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: &context.to_wgsl_bytes(),
+            usage: BufferUsages::UNIFORM,
+        })
+    }
+
+    pub fn get_sources_buffer(&self, params: &SimulationParameters) -> Buffer {
+        let mut sources = (params.sources.iter())
+            .map(|x| x.context(params))
+            .collect::<Vec<_>>();
+
+        // Storage buffers can't be empty, so add a source that is never read.
+        if sources.is_empty() {
+            sources.push(SourceContext::default());
+        }
+
+        let mut buffer = encase::StorageBuffer::new(Vec::new());
+        buffer.write(&sources).unwrap();
+        self.device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: &buffer.into_inner(),
+            usage: BufferUsages::STORAGE,
+        })
+    }
+
+    fn download(&self, buffer: &Buffer, offset: BufferAddress) -> Vec<f32> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(
+            buffer,
+            offset,
+            &self.staging_buffer,
+            0,
+            self.staging_buffer.size(),
+        );
+        self.queue.submit([encoder.finish()]);
+
+        download_buffer(&self.staging_buffer, &self.device)
+    }
+
+    /// Reads back a whole buffer through a temporary staging buffer.
+    fn download_all<T: Pod>(&self, buffer: &Buffer) -> Vec<T> {
+        let staging_buffer = self.device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: buffer.size(),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, buffer.size());
+        self.queue.submit([encoder.finish()]);
+
+        download_buffer(&staging_buffer, &self.device)
+    }
+
+    /// Hands out the readbacks that have finished.
+    fn poll(&mut self, wait: bool) -> Result<()> {
+        for (tag, data) in self.readback.poll::<f32>(wait)? {
+            match tag {
+                ReadTag::Audio => self.audio.as_mut().unwrap().samples.extend(data),
+                ReadTag::Field(id) => self.reads.push((id, data)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Polls from somewhere that can't fail, keeping any error for `poll_reads`.
+    fn poll_deferred(&mut self, wait: bool) {
+        if let Err(e) = self.poll(wait) {
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+impl Solver for GpuSolver {
+    fn tick(&mut self, params: &SimulationParameters) {
+        let buf = self.get_context_buffer(params);
+        let sources = self.get_sources_buffer(params);
+
+        let bind_group_layout = self.compute_pipeline.get_bind_group_layout(0);
+        let mut entries = vec![
+            BindGroupEntry {
+                binding: 0,
+                resource: buf.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: self.map_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: self.states.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: self.average_energy_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 7,
+                resource: sources.as_entire_binding(),
+            },
+        ];
+
+        if let Some(audio) = &self.audio {
+            entries.extend([
+                BindGroupEntry {
+                    binding: 4,
+                    resource: audio.audio_in_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: audio.audio_out_buffer.as_entire_binding(),
+                },
+            ]);
+        }
+
+        if let Some(pml) = &self.pml_buffer {
+            entries.push(BindGroupEntry {
+                binding: 6,
+                resource: pml.as_entire_binding(),
+            });
+        }
+
+        let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &bind_group_layout,
+            entries: &entries,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.compute_pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.size.x.div_ceil(8), self.size.y.div_ceil(8), 1);
+        drop(compute_pass);
+        self.queue.submit([encoder.finish()]);
+
+        // Copy out the output ring once it has been filled
+        if let Some(audio) = &self.audio {
+            let tick = params.tick as usize;
+            if tick > 0 && tick % OUTPUT_BUFFER_SIZE == OUTPUT_BUFFER_SIZE - 1 {
+                let size = audio.audio_out_buffer.size();
+                (self.readback).read(&audio.audio_out_buffer, 0, size, ReadTag::Audio);
+            }
+        }
+    }
+
+    fn take_audio(&mut self) -> Vec<f32> {
+        self.poll_deferred(false);
+        self.audio
+            .as_mut()
+            .map(|audio| mem::take(&mut audio.samples))
+            .unwrap_or_default()
+    }
+
+    fn read_state(&mut self, tick: u64) -> Vec<f32> {
+        let offset = ((tick + 2) % 3) * (self.size.x * self.size.y * 4) as u64;
+        self.download(&self.states, offset)
+    }
+
+    fn read_average_energy(&mut self) -> Vec<f32> {
+        self.download(&self.average_energy_buffer, 0)
+    }
+
+    fn request_read(&mut self, kind: SnapshotType, tick: u64, id: u64) {
+        let size = (self.size.x * self.size.y * 4) as u64;
+        let (buffer, offset) = match kind {
+            SnapshotType::State => (&self.states, ((tick + 2) % 3) * size),
+            SnapshotType::Energy => (&self.average_energy_buffer, 0),
+        };
+        (self.readback).read(buffer, offset, size, ReadTag::Field(id));
+    }
+
+    fn request_cells(&mut self, kind: SnapshotType, tick: u64, cells: &[u32], id: u64) {
+        let (field, offset) = match kind {
+            SnapshotType::State => (
+                &self.states,
+                ((tick + 2) % 3) as u32 * self.size.x * self.size.y,
+            ),
+            SnapshotType::Energy => (&self.average_energy_buffer, 0),
+        };
+        let context = GatherContext {
+            offset,
+            count: cells.len() as u32,
+        };
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(&context).unwrap();
+
+        let device = &self.device;
+        let context = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: &buffer.into_inner(),
+            usage: BufferUsages::UNIFORM,
+        });
+        let cells_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(cells),
+            usage: BufferUsages::STORAGE,
+        });
+        let values = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: cells.len() as u64 * 4,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.gather_pipeline.get_bind_group_layout(0),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: context.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: field.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: cells_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: values.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.gather_pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups((cells.len() as u32).div_ceil(64), 1, 1);
+        drop(compute_pass);
+        self.queue.submit([encoder.finish()]);
+
+        (self.readback).read(&values, 0, values.size(), ReadTag::Field(id));
+    }
+
+    fn poll_reads(&mut self, wait: bool) -> Result<Vec<(u64, Vec<f32>)>> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.poll(wait)?;
+        Ok(mem::take(&mut self.reads))
+    }
+
+    fn lock_in(&mut self, tick: u64, phases: &[[f32; 2]], samples: u64) {
+        let Some(lock_in) = &self.lock_in_buffer else {
+            return;
+        };
+
+        let context = LockInContext {
+            size: self.size,
+            offset: (tick % 3) as u32 * self.size.x * self.size.y,
+            frequencies: phases.len() as u32,
+            samples: samples as u32,
+        };
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(&context).unwrap();
+
+        let device = &self.device;
+        let context = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: &buffer.into_inner(),
+            usage: BufferUsages::UNIFORM,
+        });
+        let phases = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(phases),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.lock_in_pipeline.get_bind_group_layout(0),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: context.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: self.states.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: phases.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: lock_in.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.lock_in_pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.size.x.div_ceil(8), self.size.y.div_ceil(8), 1);
+        drop(compute_pass);
+        self.queue.submit([encoder.finish()]);
+    }
+
+    fn request_lock_in(&mut self, id: u64) {
+        if let Some(lock_in) = &self.lock_in_buffer {
+            (self.readback).read(lock_in, 0, lock_in.size(), ReadTag::Field(id));
+        }
+    }
+
+    fn save_state(&mut self, tick: u64) -> SolverState {
+        self.poll_deferred(true);
+        let pml = (self.pml_buffer.as_ref())
+            .map(|pml| self.download_all(pml))
+            .unwrap_or_default();
+        let lock_in = (self.lock_in_buffer.as_ref())
+            .map(|lock_in| self.download_all(lock_in))
+            .unwrap_or_default();
+        let audio = match &self.audio {
+            Some(audio) => {
+                let mut ring = self.download_all(&audio.audio_out_buffer);
+                ring.truncate(self.buffered_audio(tick));
+                [audio.samples.clone(), ring].concat()
+            }
+            None => Vec::new(),
+        };
+
+        SolverState {
+            states: self.download_all(&self.states),
+            average_energy: self.download_all(&self.average_energy_buffer),
+            pml,
+            lock_in,
+            audio,
+        }
+    }
+
+    fn load_state(&mut self, _tick: u64, state: SolverState) {
+        self.poll_deferred(true);
+        let queue = &self.queue;
+        queue.write_buffer(&self.states, 0, bytemuck::cast_slice(&state.states));
+        queue.write_buffer(
+            &self.average_energy_buffer,
+            0,
+            bytemuck::cast_slice(&state.average_energy),
+        );
+
+        if let Some(pml) = &self.pml_buffer {
+            queue.write_buffer(pml, 0, bytemuck::cast_slice(&state.pml));
+        }
+
+        if let Some(lock_in) = &self.lock_in_buffer {
+            queue.write_buffer(lock_in, 0, bytemuck::cast_slice(&state.lock_in));
+        }
+
+        if let Some(audio) = &mut self.audio {
+            queue.write_buffer(
+                &audio.audio_out_buffer,
+                0,
+                bytemuck::cast_slice(&state.audio),
+            );
+            audio.samples.clear();
+        }
+    }
+
+    fn buffered_audio(&self, tick: u64) -> usize {
+        match self.audio {
+            Some(_) => tick as usize % OUTPUT_BUFFER_SIZE,
+            None => 0,
+        }
+    }
+
+    fn request_peak(&mut self, tick: u64, id: u64) {
+        let search = &self.peak_search;
+        let context = PeakContext {
+            size: self.size,
+            offset: ((tick + 2) % 3) as u32 * self.size.x * self.size.y,
+        };
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(&context).unwrap();
+        let context = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: &buffer.into_inner(),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let pipelines = [&search.find_max, &search.find_index];
+        let bind_groups = pipelines.map(|pipeline| {
+            self.device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: context.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: self.states.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: search.peak_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+
+        self.queue
+            .write_buffer(&search.peak_buffer, 0, bytemuck::cast_slice(&[0, u32::MAX]));
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        for (pipeline, bind_group) in pipelines.into_iter().zip(&bind_groups) {
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(self.size.x.div_ceil(8), self.size.y.div_ceil(8), 1);
+        }
+        drop(compute_pass);
+
+        self.queue.submit([encoder.finish()]);
+
+        let peak = &self.peak_search.peak_buffer;
+        (self.readback).read(peak, 0, peak.size(), ReadTag::Field(id));
+    }
+
+    fn backup(&mut self) {
+        let backup = (self.candidate).get_or_insert_with(|| {
+            Backup::new(
+                &self.device,
+                &self.states,
+                &self.average_energy_buffer,
+                &self.pml_buffer,
+                &self.lock_in_buffer,
+            )
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        for (buffer, copy) in backup.pairs(
+            &self.states,
+            &self.average_energy_buffer,
+            &self.pml_buffer,
+            &self.lock_in_buffer,
+        ) {
+            encoder.copy_buffer_to_buffer(buffer, 0, copy, 0, buffer.size());
+        }
+        self.queue.submit([encoder.finish()]);
+    }
+
+    fn keep_backup(&mut self) {
+        if self.candidate.is_some() {
+            mem::swap(&mut self.backup, &mut self.candidate);
+        }
+    }
+
+    fn restore_backup(&mut self) {
+        let Some(backup) = &self.backup else {
+            return;
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        for (buffer, copy) in backup.pairs(
+            &self.states,
+            &self.average_energy_buffer,
+            &self.pml_buffer,
+            &self.lock_in_buffer,
+        ) {
+            encoder.copy_buffer_to_buffer(copy, 0, buffer, 0, buffer.size());
+        }
+        self.queue.submit([encoder.finish()]);
+    }
+
+    fn reset_states(&mut self) {
+        let empty_buffer = vec![0f32; (self.size.x * self.size.y * 3) as usize];
+        self.queue.write_buffer(
+            &self.states,
+            BufferAddress::default(),
+            bytemuck::cast_slice(&empty_buffer),
+        );
+
+        if let Some(pml) = &self.pml_buffer {
+            let empty_buffer = vec![0f32; (self.size.x * self.size.y * 2 * 2) as usize];
+            self.queue.write_buffer(
+                pml,
+                BufferAddress::default(),
+                bytemuck::cast_slice(&empty_buffer),
+            );
+        }
+
+        if let Some(lock_in) = &self.lock_in_buffer {
+            let empty_buffer = vec![0u8; lock_in.size() as usize];
+            self.queue
+                .write_buffer(lock_in, BufferAddress::default(), &empty_buffer);
+        }
+    }
+
+    fn reset_average_energy(&mut self) {
+        let empty_buffer = vec![0f32; (self.size.x * self.size.y) as usize];
+        self.queue.write_buffer(
+            &self.average_energy_buffer,
+            BufferAddress::default(),
+            bytemuck::cast_slice(&empty_buffer),
+        )
+    }
+}
+
+impl PeakSearch {
+    fn new(device: &Device) -> Self {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(include_str!("../../shaders/stability.wgsl").into()),
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: None,
+                layout: None,
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        Self {
+            find_max: pipeline("find_max"),
+            find_index: pipeline("find_index"),
+            peak_buffer: device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: 8,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+        }
+    }
+}
+
+impl Backup {
+    fn new(
+        device: &Device,
+        states: &Buffer,
+        average_energy: &Buffer,
+        pml: &Option<Buffer>,
+        lock_in: &Option<Buffer>,
+    ) -> Self {
+        let copy = |buffer: &Buffer| {
+            device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: buffer.size(),
+                usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        };
+
+        Self {
+            states: copy(states),
+            average_energy: copy(average_energy),
+            pml: pml.as_ref().map(copy),
+            lock_in: lock_in.as_ref().map(copy),
+        }
+    }
+
+    /// Each buffer along with its copy.
+    fn pairs<'a>(
+        &'a self,
+        states: &'a Buffer,
+        average_energy: &'a Buffer,
+        pml: &'a Option<Buffer>,
+        lock_in: &'a Option<Buffer>,
+    ) -> impl Iterator<Item = (&'a Buffer, &'a Buffer)> {
+        [
+            (states, &self.states),
+            (average_energy, &self.average_energy),
+        ]
+        .into_iter()
+        .chain(pml.iter().zip(&self.pml))
+        .chain(lock_in.iter().zip(&self.lock_in))
+    }
+}
+
+impl SimulationContext {
+    pub fn to_wgsl_bytes(&self) -> Vec<u8> {
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(self).unwrap();
+        buffer.into_inner()
+    }
+}
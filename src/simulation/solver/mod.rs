@@ -0,0 +1,239 @@
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use image::{DynamicImage, GenericImage, ImageReader, Rgba, RgbaImage};
+use nalgebra::Vector2;
+
+use crate::{config::Config, misc::audio::AudioInput};
+
+use super::{lock_in::LockInConfig, snapshot::SnapshotType, SimulationParameters};
+
+mod cpu;
+mod gpu;
+pub use cpu::CpuSolver;
+pub use gpu::GpuSolver;
+
+/// A backend that can advance the discretized wave equation.
+/// The field is stored as three time levels, where the level computed on tick `t` is `t % 3`.
+pub trait Solver {
+    /// Computes the next time level, `params.tick % 3`, from the previous two.
+    fn tick(&mut self, params: &SimulationParameters);
+    /// Takes the audio samples recorded at the pickup since the last call.
+    fn take_audio(&mut self) -> Vec<f32>;
+
+    /// Reads back the most recently computed time level, given the tick about to be computed.
+    fn read_state(&mut self, tick: u64) -> Vec<f32>;
+    /// Reads back the running average of the squared field.
+    fn read_average_energy(&mut self) -> Vec<f32>;
+    /// Starts reading back a field like `read_state` or `read_average_energy`, which is later returned by `poll_reads` with `id`.
+    fn request_read(&mut self, kind: SnapshotType, tick: u64, id: u64);
+    /// Starts reading back only the given cells of a field, returned by `poll_reads` like `request_read`.
+    fn request_cells(&mut self, kind: SnapshotType, tick: u64, cells: &[u32], id: u64);
+    /// Returns the requested reads that have finished, waiting for all of them if `wait` is set.
+    /// Fails if a read couldn't be mapped, after which the read it was for never finishes.
+    fn poll_reads(&mut self, wait: bool) -> Result<Vec<(u64, Vec<f32>)>>;
+
+    /// Adds the level computed on `tick` to the running mean of `2u·e^(-iωt)` kept for each lock-in frequency.
+    /// Takes `(cos ωt, sin ωt)` for each frequency and the number of samples already in the means.
+    fn lock_in(&mut self, tick: u64, phases: &[[f32; 2]], samples: u64);
+    /// Starts reading back the means as interleaved real and imaginary parts, one field per frequency, returned by `poll_reads` like `request_read`.
+    fn request_lock_in(&mut self, id: u64);
+
+    /// Starts finding the largest magnitude in the most recently computed time level, given the tick about to be computed.
+    /// Returned by `poll_reads` like `request_read`, to be decoded with `Peak::from_read`.
+    fn request_peak(&mut self, tick: u64, id: u64);
+
+    /// Reads every buffer needed to continue the run, given the tick about to be computed.
+    fn save_state(&mut self, tick: u64) -> SolverState;
+    /// Replaces every buffer with a saved state, where `audio` has `buffered_audio` samples.
+    fn load_state(&mut self, tick: u64, state: SolverState);
+    /// Number of the latest pickup samples that are held back from `take_audio` until a full block is computed.
+    fn buffered_audio(&self, tick: u64) -> usize;
+
+    /// Copies the field, PML, average energy and lock-in means aside as a candidate, which `keep_backup` makes the one `restore_backup` restores.
+    fn backup(&mut self);
+    fn keep_backup(&mut self);
+    fn restore_backup(&mut self);
+
+    /// Zeroes the field, PML and lock-in means.
+    fn reset_states(&mut self);
+    fn reset_average_energy(&mut self);
+}
+
+/// Every buffer needed to continue a run, laid out the same by both solvers.
+pub struct SolverState {
+    /// All three time levels, one after another.
+    pub states: Vec<f32>,
+    pub average_energy: Vec<f32>,
+    /// Both levels of the PML's auxiliary field, empty without one.
+    pub pml: Vec<[f32; 2]>,
+    /// The lock-in means of every frequency, empty without a lock-in.
+    pub lock_in: Vec<[f32; 2]>,
+    /// Pickup samples not yet returned by `take_audio`.
+    pub audio: Vec<f32>,
+}
+
+/// The largest magnitude in a time level and the first cell it occurs at.
+/// The value is NaN if any cell is NaN.
+#[derive(Debug, Clone, Copy)]
+pub struct Peak {
+    pub value: f32,
+    pub position: Vector2<u32>,
+}
+
+/// Everything loaded from a config that a solver needs to build its domain.
+pub struct Scene {
+    pub size: Vector2<u32>,
+    /// Packed RGBA map, one u32 per cell.
+    /// The alpha channel holds the fraction of `loss` applied to each cell.
+    pub map: Vec<u8>,
+    /// Body of the user's `tick` function.
+    pub shader: Option<String>,
+    pub audio: Option<AudioInput>,
+    /// Thickness of the perfectly matched layer, zero if disabled.
+    pub pml_thickness: u32,
+    pub stencil_order: u32,
+    /// Number of frequencies accumulated by the lock-in, zero if disabled.
+    pub lock_in: usize,
+}
+
+impl Scene {
+    pub fn load(config: &Config) -> Result<Self> {
+        let pml_thickness = config.parameters.pml_thickness;
+        let map = config
+            .map
+            .as_ref()
+            .map(|map| load_image(config, map, Rgba([0, 0, 0, 255])))
+            .transpose()?;
+
+        let mut map = match map {
+            Some(map) => {
+                // Opaque pixels are lossless, so maps without alpha are unchanged.
+                let mut map = map.into_raw();
+                map.chunks_exact_mut(4).for_each(|x| x[3] = 255 - x[3]);
+                map
+            }
+            None => {
+                let mut out = vec![0; (config.size.0 * config.size.1) as usize * 4];
+                out.chunks_exact_mut(4).for_each(|x| x[2] = 128);
+                out
+            }
+        };
+
+        if let Some(loss_map) = &config.loss_map {
+            let loss = load_image(config, loss_map, Rgba([0, 0, 0, 255]))?;
+            let loss = DynamicImage::ImageRgba8(loss).into_luma8();
+            (map.chunks_exact_mut(4).zip(loss.pixels())).for_each(|(x, loss)| x[3] = loss.0[0]);
+        }
+
+        let shader = config
+            .shader
+            .as_ref()
+            .map(|shader| fs::read_to_string(config.base_path().join(shader)))
+            .transpose()?;
+
+        let audio = config
+            .audio
+            .as_ref()
+            .map(|x| {
+                AudioInput::new(
+                    File::open(config.base_path().join(&x.input))?,
+                    Vector2::new(x.pickup.0, x.pickup.1),
+                )
+            })
+            .transpose()?;
+
+        Ok(Self {
+            size: Vector2::new(config.size.0, config.size.1),
+            map,
+            shader,
+            audio,
+            pml_thickness,
+            stencil_order: config.parameters.stencil_order,
+            lock_in: (config.lock_in.as_ref()).map_or(0, LockInConfig::frequency_count),
+        })
+    }
+}
+
+impl Peak {
+    /// Builds a peak from the largest float magnitude as bits, which order the same as the floats and put NaN above infinity.
+    fn from_bits(bits: u32, index: u32, width: u32) -> Self {
+        Self {
+            value: f32::from_bits(bits),
+            position: Vector2::new(index % width, index / width),
+        }
+    }
+
+    /// Decodes a read from `Solver::request_peak`, which holds the magnitude and cell index as bits.
+    pub fn from_read(data: &[f32], width: u32) -> Self {
+        Self::from_bits(data[0].to_bits(), data[1].to_bits(), width)
+    }
+}
+
+/// Loads an image centered in the simulation, with the rest filled with `background`.
+fn load_image(config: &Config, path: &Path, background: Rgba<u8>) -> Result<RgbaImage> {
+    let mut image = RgbaImage::from_pixel(config.size.0, config.size.1, background);
+    let file = ImageReader::open(config.base_path().join(path))?.decode()?;
+    let x = (config.size.0.saturating_sub(file.width())) / 2;
+    let y = (config.size.1.saturating_sub(file.height())) / 2;
+    image
+        .copy_from(&file, x, y)
+        .context("Map must have a size equal or smaller than the simulation size.")?;
+    Ok(image)
+}
+
+/// Central difference weights for the second derivative of the given order, from the center outward.
+pub fn stencil_weights(order: u32) -> Option<&'static [f32]> {
+    Some(match order {
+        2 => &[-2.0, 1.0],
+        4 => &[-5.0 / 2.0, 4.0 / 3.0, -1.0 / 12.0],
+        6 => &[-49.0 / 18.0, 3.0 / 2.0, -3.0 / 20.0, 1.0 / 90.0],
+        _ => return None,
+    })
+}
+
+/// Largest Courant number that keeps the 2D leapfrog scheme stable with the given stencil.
+pub fn courant_limit(weights: &[f32]) -> f32 {
+    // The checkerboard mode has the largest eigenvalue.
+    let highest = (weights.iter().enumerate())
+        .map(|(i, weight)| match i {
+            0 => *weight,
+            _ => 2.0 * weight * (-1f32).powi(i as i32),
+        })
+        .sum::<f32>();
+    (2.0 / highest.abs()).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{courant_limit, stencil_weights};
+
+    #[test]
+    fn stencils_are_second_derivatives() {
+        for order in [2, 4, 6] {
+            let weights = stencil_weights(order).unwrap();
+            let (center, rest) = weights.split_first().unwrap();
+            // Constants vanish and `x²` has a second derivative of two
+            let constant = center + 2.0 * rest.iter().sum::<f32>();
+            let square = (rest.iter().enumerate())
+                .map(|(i, weight)| 2.0 * weight * (i as f32 + 1.0).powi(2))
+                .sum::<f32>();
+            assert!(constant.abs() < 1e-6, "order {order}");
+            assert!((square - 2.0).abs() < 1e-6, "order {order}");
+        }
+
+        assert!(stencil_weights(3).is_none());
+        assert!(stencil_weights(8).is_none());
+    }
+
+    #[test]
+    fn courant_limits() {
+        let limit = |order| courant_limit(stencil_weights(order).unwrap());
+        assert!((limit(2) - 0.5f32.sqrt()).abs() < 1e-6);
+        assert!((limit(4) - 0.375f32.sqrt()).abs() < 1e-6);
+        assert!(limit(6) < limit(4));
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::PathBuf;
+
+use encase::ShaderType;
+use nalgebra::Vector2;
+use serde::{Deserialize, Serialize};
+
+use crate::misc::units;
+
+use super::{waveform::Waveform, SimulationParameters};
+
+/// A point source injected by the compute kernel, configured with `[[source]]`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Source {
+    pub position: Position,
+    pub amplitude: f32,
+    /// Frequency in Hz.
+    #[serde(deserialize_with = "units::frequency")]
+    pub frequency: f32,
+    /// Phase offset in radians.
+    pub phase: f32,
+    pub waveform: Waveform,
+
+    /// First tick the source is active on.
+    pub start: u64,
+    /// Tick the source turns off on, if any.
+    pub stop: Option<u64>,
+    /// Standard deviation of the Gaussian spatial profile in cells.
+    pub width: f32,
+
+    /// A `.wav` or `.csv` file to write the waveform to as the simulation runs, sampled once per tick.
+    pub reference: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Position {
+    Cells(f32, f32),
+    Meters(f32, f32),
+}
+
+/// A source's position in cells and value on the current tick, laid out to match `Source` in `shader.wgsl`.
+#[derive(ShaderType, Default)]
+pub struct SourceContext {
+    pub position: Vector2<f32>,
+    pub width: f32,
+    pub value: f32,
+}
+
+impl Source {
+    /// Value injected on the given tick, before the spatial profile is applied.
+    pub fn value(&self, tick: u64, dt: f32) -> f32 {
+        if tick < self.start || self.stop.is_some_and(|stop| tick >= stop) {
+            return 0.0;
+        }
+
+        let t = (tick - self.start) as f64 * dt as f64;
+        let value = self
+            .waveform
+            .evaluate(t, self.frequency as f64, self.phase as f64);
+        self.amplitude * value as f32
+    }
+
+    pub fn context(&self, params: &SimulationParameters) -> SourceContext {
+        SourceContext {
+            position: self.position.cells(params.dx),
+            width: self.width,
+            value: self.value(params.tick, params.dt),
+        }
+    }
+}
+
+impl SourceContext {
+    /// Contribution of the source to the cell at `pos`.
+    pub fn value(&self, pos: Vector2<f32>) -> f32 {
+        let distance = (pos - self.position).norm() / self.width;
+        self.value * (-distance * distance / 2.0).exp()
+    }
+}
+
+impl Position {
+    pub fn cells(&self, dx: f32) -> Vector2<f32> {
+        match *self {
+            Position::Cells(x, y) => Vector2::new(x, y),
+            Position::Meters(x, y) => Vector2::new(x, y) / dx,
+        }
+    }
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self {
+            position: Position::Cells(0.0, 0.0),
+            amplitude: 5e-2,
+            frequency: 4.3e14,
+            phase: 0.0,
+            waveform: Waveform::Sine,
+
+            start: 0,
+            stop: None,
+            width: 1.0,
+
+            reference: None,
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt::{self, Display};
+
+use anyhow::Result;
+
+use super::{
+    solver::{Peak, Solver},
+    Simulation,
+};
+
+/// Where and when a check found the field had blown up.
+#[derive(Debug, Clone, Copy)]
+pub struct Instability {
+    pub tick: u64,
+    pub peak: Peak,
+    /// Tick the simulation was rolled back to, if enabled.
+    pub rollback: Option<u64>,
+}
+
+/// A check of the field that is waiting on the solver.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct PeakCheck {
+    pub id: u64,
+    pub tick: u64,
+    /// Set once the read has finished.
+    pub peak: Option<Peak>,
+}
+
+impl<S: Solver> Simulation<S> {
+    /// Starts a check of the field every `interval` ticks, pausing and returning false once one finds it has blown up.
+    /// Checks are read back without stalling, so they finish a few ticks later and one is skipped while another is still running.
+    pub(super) fn check_stability(&mut self) -> Result<bool> {
+        if self.peak_check.is_some() {
+            self.poll_reads(false)?;
+            if !self.finish_stability_check() {
+                return Ok(false);
+            }
+        }
+
+        let params = &self.parameters;
+        let interval = self.stability.interval;
+        if interval == 0 || !params.tick.is_multiple_of(interval) || self.peak_check.is_some() {
+            return Ok(true);
+        }
+
+        let id = self.next_read;
+        self.next_read += 1;
+        self.solver.request_peak(params.tick, id);
+        if self.stability.rollback {
+            self.solver.backup();
+        }
+        self.peak_check = Some(PeakCheck {
+            id,
+            tick: params.tick,
+            peak: None,
+        });
+        Ok(true)
+    }
+
+    /// Handles the result of the pending check if it has been read back, pausing and returning false if the field had blown up.
+    pub(super) fn finish_stability_check(&mut self) -> bool {
+        let Some(PeakCheck {
+            tick,
+            peak: Some(peak),
+            ..
+        }) = self.peak_check
+        else {
+            return true;
+        };
+        self.peak_check = None;
+
+        // Written so NaN fails the check
+        if peak.value <= self.stability.max_amplitude {
+            self.instability = None;
+            if self.stability.rollback {
+                self.solver.keep_backup();
+                self.backup_tick = Some(tick);
+            }
+            return true;
+        }
+
+        self.parameters.running = false;
+        let rollback = self.stability.rollback.then(|| self.rollback());
+        self.instability = Some(Instability {
+            tick,
+            peak,
+            rollback,
+        });
+        false
+    }
+
+    /// Restores the last backup that passed a check, or starts over without one, returning the tick rolled back to.
+    /// Detector, flux and lock-in results from after it are dropped, as they were taken from the blown up field.
+    fn rollback(&mut self) -> u64 {
+        let Some(tick) = self.backup_tick else {
+            self.reset_states();
+            self.reset_average_energy();
+            return 0;
+        };
+
+        self.solver.restore_backup();
+        self.parameters.tick = tick;
+        self.detectors.iter_mut().for_each(|x| x.rollback(tick));
+        self.flux.iter_mut().for_each(|x| x.rollback(tick));
+        self.lock_in.iter_mut().for_each(|x| x.rollback(tick));
+        tick
+    }
+}
+
+impl Display for Instability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Peak { value, position } = self.peak;
+        write!(
+            f,
+            "Field became unstable by tick {}, reaching {value:e} at ({}, {}).",
+            self.tick, position.x, position.y
+        )?;
+
+        if let Some(tick) = self.rollback {
+            write!(f, " Rolled back to tick {tick}.")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        simulation::{
+            detector::{Detector, Shape},
+            solver::{courant_limit, stencil_weights, CpuSolver, Solver},
+            source::{Position, Source},
+            Simulation,
+        },
+    };
+
+    /// Drives a point source for up to 256 ticks at the given fraction of the Courant limit.
+    fn run(fraction: f32, rollback: bool) -> Simulation<CpuSolver> {
+        let mut config = Config::default();
+        config.size = (32, 32);
+        config.parameters.dt = courant_limit(stencil_weights(2).unwrap()) * fraction;
+        config.parameters.dx = 1.0;
+        config.parameters.v = 1.0;
+        config.oscillator.amplitude = 0.0;
+        config.stability.rollback = rollback;
+        config.sources.push(Source {
+            position: Position::Cells(16.0, 16.0),
+            amplitude: 1.0,
+            frequency: 0.05,
+            ..Default::default()
+        });
+        config.detectors.push(Detector {
+            shape: Shape::Point([20, 16]),
+            quantity: Default::default(),
+            interval: 1,
+            start: 0,
+            output: "detector.csv".into(),
+        });
+
+        let mut simulation = Simulation::new(&config, CpuSolver::new).unwrap();
+        simulation.parameters.running = true;
+        simulation.parameters.ticks_per_dispatch = 256;
+        simulation.update().unwrap();
+        simulation
+    }
+
+    #[test]
+    fn stable_below_courant_limit() {
+        let simulation = run(0.9, false);
+        assert_eq!(simulation.parameters.tick, 256);
+        assert!(simulation.instability.is_none());
+    }
+
+    #[test]
+    fn detects_blow_up_above_courant_limit() {
+        let simulation = run(1.1, false);
+        let instability = simulation.instability.unwrap();
+        assert!(!simulation.parameters.running);
+        assert!(simulation.parameters.tick < 256);
+        assert!(instability.rollback.is_none());
+
+        let peak = instability.peak.value;
+        assert!(peak.is_nan() || peak > simulation.stability.max_amplitude);
+    }
+
+    #[test]
+    fn rolls_back_to_last_stable_check() {
+        // Grows slowly enough to pass a few checks first
+        let mut simulation = run(1.001, true);
+        let instability = simulation.instability.unwrap();
+        let tick = simulation.parameters.tick;
+        assert_eq!(instability.rollback, Some(tick));
+        assert!(tick < instability.tick);
+
+        assert!(tick > 0 && tick.is_multiple_of(simulation.stability.interval));
+        let field = simulation.solver.read_state(tick);
+        assert!(field
+            .iter()
+            .all(|x| x.abs() <= simulation.stability.max_amplitude));
+
+        let samples = &simulation.detectors()[0].samples;
+        assert_eq!(samples.last().map(|x| x.0), Some(tick));
+    }
+}
@@ -0,0 +1,548 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+use crate::misc::{
+    units::{self, Dimension},
+    util::save_dated_dir,
+};
+
+use super::{
+    snapshot::{SnapshotFormat, SnapshotType},
+    solver::Solver,
+    Simulation, SimulationParameters,
+};
+
+/// Simulation parameters a sweep can change directly, with every other name only handed to the script.
+const PARAMETERS: [(&str, Option<Dimension>); 7] = [
+    ("v", Some(Dimension::Speed)),
+    ("dt", Some(Dimension::Time)),
+    ("dx", Some(Dimension::Length)),
+    ("loss", Some(Dimension::Frequency)),
+    ("amplitude", None),
+    ("frequency", Some(Dimension::Frequency)),
+    // Changes nothing, but is recorded in the index for `composite` to color the run's snapshots by.
+    ("wavelength", Some(Dimension::Length)),
+];
+
+/// Runs the simulation once for each set of parameter values, configured with a `[sweep]` table.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SweepConfig {
+    #[serde(rename = "parameter")]
+    pub parameters: Vec<SweepParameter>,
+    /// How the values of each parameter are combined into runs.
+    #[serde(default)]
+    pub mode: SweepMode,
+    /// Ticks each run is simulated for before its snapshots are taken.
+    pub settle: u64,
+    /// Snapshots taken at the end of each run.
+    #[serde(default)]
+    pub snapshots: Vec<SnapshotType>,
+    /// Format of the snapshots, using the config's `snapshot_format` without one.
+    pub format: Option<SnapshotFormat>,
+    /// Name of each run's snapshots, where `{run}` and `{parameter}` are replaced with the run number and values.
+    /// Defaults to the run number followed by every parameter and its value.
+    pub name: Option<String>,
+    /// Directory the snapshots and `index.csv` are written to, a dated one in `states` without one.
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SweepParameter {
+    /// One of `v`, `dt`, `dx`, `loss`, `amplitude`, `frequency` or `wavelength`, or any other name for the script's `sweep` function to use.
+    pub name: String,
+    /// Every value, which can have units like the config value of the same name.
+    pub values: Option<Vec<Value>>,
+    /// Evenly spaced values from `start` to `end`.
+    pub linear: Option<SweepRange>,
+    /// Logarithmically spaced values from `start` to `end`.
+    pub log: Option<SweepRange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SweepRange {
+    pub start: Value,
+    pub end: Value,
+    /// Number of values, including both ends.
+    pub steps: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SweepMode {
+    /// Every combination of values, changing the last parameter the fastest.
+    #[default]
+    Cartesian,
+    /// The first value of every parameter together, then the second and so on.
+    Zip,
+}
+
+/// Progress through a sweep, which records each finished run in `index.csv`.
+pub struct Sweep {
+    config: SweepConfig,
+    /// Values of every parameter in each run.
+    runs: Vec<Vec<f64>>,
+    /// The run being simulated, which is past the end once the sweep is finished.
+    run: usize,
+    format: SnapshotFormat,
+    output: PathBuf,
+    index: BufWriter<File>,
+}
+
+impl SweepParameter {
+    fn dimension(&self) -> Option<Dimension> {
+        (PARAMETERS.iter())
+            .find(|(name, _)| *name == self.name)
+            .and_then(|(_, dimension)| *dimension)
+    }
+
+    /// Whether the value is only handed to the script.
+    fn is_script(&self) -> bool {
+        !PARAMETERS.iter().any(|(name, _)| *name == self.name)
+    }
+
+    fn values(&self) -> Result<Vec<f64>> {
+        let value = |value: &Value| match (value, self.dimension()) {
+            (Value::Integer(x), _) => Ok(*x as f64),
+            (Value::Float(x), _) => Ok(*x),
+            (Value::String(x), Some(dimension)) => units::parse(x, dimension),
+            _ => bail!(
+                "Expected a number for sweep parameter `{}`, found `{value}`.",
+                self.name
+            ),
+        };
+
+        let values = match (&self.values, &self.linear, &self.log) {
+            (Some(values), None, None) => values.iter().map(value).collect::<Result<Vec<_>>>()?,
+            (None, Some(range), None) => {
+                let (start, end) = (value(&range.start)?, value(&range.end)?);
+                (range.fractions()?.map(|t| start + (end - start) * t)).collect()
+            }
+            (None, None, Some(range)) => {
+                let (start, end) = (value(&range.start)?, value(&range.end)?);
+                ensure!(
+                    start > 0.0 && end > 0.0,
+                    "Logarithmic sweeps of `{}` must have a positive start and end.",
+                    self.name
+                );
+                (range.fractions()?.map(|t| start * (end / start).powf(t))).collect()
+            }
+            _ => bail!(
+                "Sweep parameter `{}` needs exactly one of `values`, `linear` or `log`.",
+                self.name
+            ),
+        };
+
+        ensure!(
+            !values.is_empty(),
+            "Sweep parameter `{}` has no values.",
+            self.name
+        );
+        ensure!(
+            self.name != "wavelength" || values.iter().all(|x| *x > 0.0),
+            "Swept wavelengths must be positive."
+        );
+        Ok(values)
+    }
+}
+
+impl SweepRange {
+    /// Positions of each step from the start to the end.
+    fn fractions(&self) -> Result<impl Iterator<Item = f64>> {
+        ensure!(self.steps > 0, "Sweep ranges need at least one step.");
+        let last = (self.steps - 1).max(1) as f64;
+        Ok((0..self.steps).map(move |i| i as f64 / last))
+    }
+}
+
+impl Sweep {
+    /// Works out every run, failing if a parameter needs a `sweep` function that the script doesn't have.
+    pub fn new(
+        config: SweepConfig,
+        base_path: &Path,
+        format: SnapshotFormat,
+        script: bool,
+    ) -> Result<Self> {
+        ensure!(
+            !config.parameters.is_empty(),
+            "Sweeps need at least one parameter."
+        );
+        if let Some(parameter) = config.parameters.iter().find(|x| x.is_script()) {
+            ensure!(
+                script,
+                "`{}` isn't a simulation parameter, so sweeping it needs a script with a `sweep` function.",
+                parameter.name
+            );
+        }
+
+        if let Some(template) = &config.name {
+            let mut rest = template.replace("{run}", "");
+            for parameter in &config.parameters {
+                rest = rest.replace(&format!("{{{}}}", parameter.name), "");
+            }
+            ensure!(
+                !rest.contains(['{', '}']),
+                "Sweep name `{template}` uses a placeholder that isn't `{{run}}` or a parameter."
+            );
+        }
+
+        let values = (config.parameters.iter())
+            .map(SweepParameter::values)
+            .collect::<Result<Vec<_>>>()?;
+        let runs = match config.mode {
+            SweepMode::Cartesian => values.iter().fold(vec![Vec::new()], |runs, values| {
+                (runs.iter())
+                    .flat_map(|run| values.iter().map(move |x| [run.as_slice(), &[*x]].concat()))
+                    .collect()
+            }),
+            SweepMode::Zip => {
+                let count = values[0].len();
+                ensure!(
+                    values.iter().all(|x| x.len() == count),
+                    "Zipped sweep parameters must all have the same number of values."
+                );
+                (0..count)
+                    .map(|i| values.iter().map(|x| x[i]).collect())
+                    .collect()
+            }
+        };
+
+        let output = match &config.output {
+            Some(output) => {
+                let output = base_path.join(output);
+                fs::create_dir_all(&output)
+                    .with_context(|| format!("Failed to create {}", output.display()))?;
+                output
+            }
+            None => save_dated_dir("states", "sweep")?,
+        };
+        // Snapshot names are relative to `states`, so the directory is made absolute.
+        let output = output.canonicalize()?;
+
+        let mut index = BufWriter::new(File::create(output.join("index.csv"))?);
+        let columns = (config.parameters.iter().map(|x| x.name.as_str()))
+            .chain(config.snapshots.iter().map(|x| x.name()));
+        writeln!(index, "run,{}", columns.collect::<Vec<_>>().join(","))?;
+
+        Ok(Self {
+            format: config.format.unwrap_or(format),
+            config,
+            runs,
+            run: 0,
+            output,
+            index,
+        })
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    /// The current run and the number of runs.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.run, self.runs.len())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.run >= self.runs.len()
+    }
+
+    /// Whether the current run has been simulated for long enough.
+    fn is_due(&self, tick: u64) -> bool {
+        !self.is_finished() && tick >= self.config.settle
+    }
+
+    fn values(&self) -> impl Iterator<Item = (&SweepParameter, f64)> {
+        (self.config.parameters.iter()).zip(self.runs[self.run].iter().copied())
+    }
+
+    /// Sets the simulation parameters of the current run, returning every value for the script.
+    fn apply(&self, params: &mut SimulationParameters) -> Vec<(String, f64)> {
+        for (parameter, value) in self.values() {
+            let value32 = value as f32;
+            match parameter.name.as_str() {
+                "v" => params.v = value32,
+                "dt" => params.dt = value32,
+                "dx" => params.dx = value32,
+                "loss" => params.loss = value32,
+                "amplitude" => params.amplitude = value32,
+                "frequency" => params.frequency = value32,
+                _ => {}
+            }
+        }
+
+        (self.values())
+            .map(|(parameter, value)| (parameter.name.clone(), value))
+            .collect()
+    }
+
+    /// Names the current run's snapshot of the given kind, without an extension.
+    fn name(&self, kind: &SnapshotType) -> String {
+        let run = self.run;
+        let mut name = match &self.config.name {
+            Some(template) => {
+                let mut name = template.replace("{run}", &run.to_string());
+                for (parameter, value) in self.values() {
+                    name = name.replace(&format!("{{{}}}", parameter.name), &format_value(value));
+                }
+                name
+            }
+            None => {
+                let values = (self.values()).map(|(parameter, value)| {
+                    format!("_{}={}", parameter.name, format_value(value))
+                });
+                format!("{run:03}{}", values.collect::<String>())
+            }
+        };
+
+        if self.config.snapshots.len() > 1 {
+            name.push('_');
+            name.push_str(kind.name());
+        }
+        name
+    }
+
+    /// Records the current run in the index and moves on to the next one, returning the snapshots to take.
+    fn finish_run(&mut self) -> Result<Vec<(SnapshotType, String)>> {
+        let snapshots = (self.config.snapshots.iter())
+            .map(|kind| {
+                let name = format!("{}.{}", self.name(kind), self.format.extension());
+                (kind.clone(), name)
+            })
+            .collect::<Vec<_>>();
+
+        let values = self.values().map(|(_, value)| (value as f32).to_string());
+        let names = snapshots.iter().map(|(_, name)| name.clone());
+        writeln!(
+            self.index,
+            "{},{}",
+            self.run,
+            values.chain(names).collect::<Vec<_>>().join(",")
+        )?;
+        self.index.flush()?;
+
+        self.run += 1;
+        Ok(snapshots
+            .into_iter()
+            .map(|(kind, name)| (kind, self.output.join(name).to_string_lossy().into_owned()))
+            .collect())
+    }
+}
+
+impl<S: Solver> Simulation<S> {
+    pub fn sweep(&self) -> Option<&Sweep> {
+        self.sweep.as_ref()
+    }
+
+    /// Takes the current run's snapshots and resets for the next run once it has settled.
+    pub(super) fn step_sweep(&mut self) -> Result<()> {
+        let Some(sweep) = &mut self.sweep else {
+            return Ok(());
+        };
+        if !sweep.is_due(self.parameters.tick) {
+            return Ok(());
+        }
+
+        for (kind, name) in sweep.finish_run()? {
+            self.snapshot.push(kind, Some(name));
+        }
+        // The fields have to be read before they're reset.
+        self.request_snapshots();
+        self.reset_states();
+        self.reset_average_energy();
+        self.start_sweep_run();
+        Ok(())
+    }
+
+    /// Applies the values of the current run, or pauses once every run is done.
+    pub(super) fn start_sweep_run(&mut self) {
+        let Some(sweep) = &self.sweep else {
+            return;
+        };
+
+        let (run, runs) = sweep.progress();
+        if sweep.is_finished() {
+            self.parameters.running = false;
+            println!(
+                "Finished sweep of {runs} runs, written to {}.",
+                sweep.output().display()
+            );
+            return;
+        }
+
+        let values = (sweep.values())
+            .map(|(parameter, value)| format!("{} = {}", parameter.name, format_value(value)))
+            .collect::<Vec<_>>();
+        println!("Sweep run {}/{runs}: {}", run + 1, values.join(", "));
+
+        let values = sweep.apply(&mut self.parameters);
+        if let Some(scripting) = &mut self.script {
+            if scripting.has_function("sweep") {
+                scripting.set_sweep(&values);
+                self.script_update("sweep");
+            }
+        }
+    }
+}
+
+/// Formats a value for a file name, switching to scientific notation for very large or small magnitudes.
+fn format_value(value: f64) -> String {
+    let value = value as f32;
+    match value.abs() {
+        x if x == 0.0 || (1e-3..1e6).contains(&x) => value.to_string(),
+        _ => format!("{value:e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use crate::{
+        config::Config,
+        simulation::{snapshot::SnapshotFormat, SimulationParameters},
+    };
+
+    use super::{format_value, Sweep, SweepConfig};
+
+    /// Builds a sweep writing to its own temporary directory, which is returned to be removed.
+    fn sweep(name: &str, table: &str, script: bool) -> (anyhow::Result<Sweep>, PathBuf) {
+        let directory = env::temp_dir().join(format!("wave-sim-{}-sweep-{name}", process::id()));
+        let config = format!("settle = 10\noutput = \"out\"\n{table}");
+        let config = toml::from_str::<SweepConfig>(&config).unwrap();
+        (
+            Sweep::new(config, &directory, SnapshotFormat::Bin, script),
+            directory,
+        )
+    }
+
+    fn generate(name: &str, table: &str) -> Vec<Vec<f64>> {
+        let (sweep, directory) = sweep(name, table, false);
+        let runs = sweep.unwrap().runs;
+        fs::remove_dir_all(directory).unwrap();
+        runs
+    }
+
+    fn close(a: &[Vec<f64>], b: &[&[f64]]) -> bool {
+        a.len() == b.len()
+            && (a.iter().flatten().zip(b.iter().copied().flatten()))
+                .all(|(a, b)| (a - b).abs() <= b.abs() * 1e-12)
+    }
+
+    #[test]
+    fn generates_values() {
+        let runs = generate(
+            "values",
+            "[[parameter]]\nname = \"dx\"\nvalues = [1, 0.5, \"2 mm\"]",
+        );
+        assert!(close(&runs, &[&[1.0], &[0.5], &[2e-3]]));
+
+        let runs = generate(
+            "linear",
+            "[[parameter]]\nname = \"v\"\nlinear = { start = 1, end = \"3 m/s\", steps = 5 }",
+        );
+        assert!(close(&runs, &[&[1.0], &[1.5], &[2.0], &[2.5], &[3.0]]));
+
+        let runs = generate(
+            "log",
+            "[[parameter]]\nname = \"frequency\"\nlog = { start = \"1 Hz\", end = \"1 kHz\", steps = 4 }",
+        );
+        assert!(close(&runs, &[&[1.0], &[10.0], &[100.0], &[1000.0]]));
+
+        let runs = generate(
+            "single",
+            "[[parameter]]\nname = \"loss\"\nlinear = { start = 2, end = 3, steps = 1 }",
+        );
+        assert!(close(&runs, &[&[2.0]]));
+    }
+
+    #[test]
+    fn combines_parameters() {
+        let parameters = "[[parameter]]\nname = \"v\"\nvalues = [1, 2]\n\
+            [[parameter]]\nname = \"dx\"\nvalues = [3, 4]";
+        let cartesian = generate("cartesian", parameters);
+        assert!(close(
+            &cartesian,
+            &[&[1.0, 3.0], &[1.0, 4.0], &[2.0, 3.0], &[2.0, 4.0]]
+        ));
+
+        let zip = generate("zip", &format!("mode = \"zip\"\n{parameters}"));
+        assert!(close(&zip, &[&[1.0, 3.0], &[2.0, 4.0]]));
+    }
+
+    #[test]
+    fn rejects_bad_sweeps() {
+        let error = |name, table, script| {
+            let (sweep, directory) = sweep(name, table, script);
+            let _ = fs::remove_dir_all(directory);
+            sweep.err().map(|x| x.to_string()).unwrap_or_default()
+        };
+
+        let zip = "mode = \"zip\"\n[[parameter]]\nname = \"v\"\nvalues = [1, 2]\n\
+            [[parameter]]\nname = \"dx\"\nvalues = [3]";
+        assert!(error("bad-zip", zip, false).contains("same number"));
+        let both =
+            "[[parameter]]\nname = \"v\"\nvalues = [1]\nlinear = { start = 1, end = 2, steps = 2 }";
+        assert!(error("both", both, false).contains("exactly one"));
+        let log = "[[parameter]]\nname = \"v\"\nlog = { start = 0, end = 2, steps = 2 }";
+        assert!(error("bad-log", log, false).contains("positive"));
+        let unit = "[[parameter]]\nname = \"dx\"\nvalues = [\"2 ms\"]";
+        assert!(error("bad-unit", unit, false).contains("length"));
+
+        let custom = "[[parameter]]\nname = \"angle\"\nvalues = [1, 2]";
+        assert!(error("no-script", custom, false).contains("script"));
+        assert!(error("script", custom, true).is_empty());
+        let name = "name = \"{run}_{angel}\"\n[[parameter]]\nname = \"angle\"\nvalues = [1]";
+        assert!(error("bad-name", name, true).contains("placeholder"));
+    }
+
+    #[test]
+    fn records_wavelength_without_changing_frequency() {
+        let table = "mode = \"zip\"\nsnapshots = [\"energy\"]\n\
+            [[parameter]]\nname = \"wavelength\"\nvalues = [\"500 nm\", \"1 um\"]\n\
+            [[parameter]]\nname = \"v\"\nvalues = [3e8, 1.5e8]";
+        let (sweep, directory) = sweep("wavelength", table, false);
+        let mut sweep = sweep.unwrap();
+
+        let mut params = SimulationParameters::new(&Config::default());
+        let frequency = params.frequency;
+        let values = sweep.apply(&mut params);
+        assert_eq!(values[0].0, "wavelength");
+        assert!((values[0].1 - 5e-7).abs() < 1e-15);
+        assert_eq!(params.v, 3e8);
+        assert_eq!(params.frequency, frequency);
+
+        let snapshots = sweep.finish_run().unwrap();
+        assert!(snapshots[0].1.ends_with("000_wavelength=5e-7_v=3e8.bin"));
+        sweep.apply(&mut params);
+        assert_eq!(params.frequency, frequency);
+
+        let index = fs::read_to_string(directory.join("out").join("index.csv")).unwrap();
+        let mut rows = index.lines();
+        assert_eq!(rows.next(), Some("run,wavelength,v,energy"));
+        assert_eq!(
+            rows.next(),
+            Some("0,0.0000005,300000000,000_wavelength=5e-7_v=3e8.bin")
+        );
+        drop(sweep);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn formats_values() {
+        assert_eq!(format_value(0.0), "0");
+        assert_eq!(format_value(2.5), "2.5");
+        assert_eq!(format_value(-400.0), "-400");
+        assert_eq!(format_value(5e-7), "5e-7");
+        assert_eq!(format_value(4.3e14), "4.3e14");
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    f64::consts::{PI, TAU},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{bail, ensure, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde::{Deserialize, Serialize};
+
+use crate::misc::{audio::SAMPLE_RATE, units};
+
+/// Time dependence of a source, given its frequency in Hz and phase in radians.
+/// Times are in seconds since the source's start tick.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Square,
+    /// Sinusoid under a Gaussian envelope.
+    GaussianPulse {
+        /// Standard deviation of the envelope.
+        #[serde(deserialize_with = "units::time")]
+        duration: f32,
+        /// Time of the envelope's peak, defaults to four standard deviations.
+        #[serde(default, deserialize_with = "units::time")]
+        delay: Option<f32>,
+    },
+    /// Second derivative of a Gaussian, with its peak spectrum at the source frequency.
+    Ricker {
+        /// Time of the wavelet's peak, defaults to 1.5 periods.
+        #[serde(default, deserialize_with = "units::time")]
+        delay: Option<f32>,
+    },
+    /// Sweep from the source frequency to `end_frequency`, then silence.
+    Chirp {
+        #[serde(deserialize_with = "units::frequency")]
+        end_frequency: f32,
+        #[serde(deserialize_with = "units::time")]
+        duration: f32,
+        /// Sweep exponentially instead of linearly.
+        #[serde(default)]
+        logarithmic: bool,
+    },
+    /// A number of cycles under a Hann window, then silence.
+    ToneBurst {
+        cycles: f32,
+    },
+}
+
+/// Writes a waveform sampled once per tick, like the audio pickup.
+pub enum WaveformWriter {
+    Wav(WavWriter<BufWriter<File>>),
+    Csv(BufWriter<File>),
+}
+
+impl Waveform {
+    /// Every kind of waveform, with parameters chosen for the given frequency.
+    pub fn presets(frequency: f32) -> [Waveform; 6] {
+        let period = frequency.recip();
+        [
+            Waveform::Sine,
+            Waveform::Square,
+            Waveform::GaussianPulse {
+                duration: 2.0 * period,
+                delay: None,
+            },
+            Waveform::Ricker { delay: None },
+            Waveform::Chirp {
+                end_frequency: 2.0 * frequency,
+                duration: 20.0 * period,
+                logarithmic: false,
+            },
+            Waveform::ToneBurst { cycles: 5.0 },
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Waveform::Sine => "Sine",
+            Waveform::Square => "Square",
+            Waveform::GaussianPulse { .. } => "Gaussian Pulse",
+            Waveform::Ricker { .. } => "Ricker Wavelet",
+            Waveform::Chirp { .. } => "Chirp",
+            Waveform::ToneBurst { .. } => "Tone Burst",
+        }
+    }
+
+    /// Checks the parameters give a waveform that can be evaluated, given the source frequency in Hz.
+    pub fn validate(&self, frequency: f32) -> Result<()> {
+        let positive = |name, value: f32| {
+            ensure!(
+                value.is_finite() && value > 0.0,
+                "`{name}` must be positive, found {value}."
+            );
+            Ok(())
+        };
+
+        match *self {
+            Waveform::Sine | Waveform::Square | Waveform::Ricker { .. } => Ok(()),
+            Waveform::GaussianPulse { duration, .. } => positive("duration", duration),
+            Waveform::Chirp {
+                end_frequency,
+                duration,
+                logarithmic,
+            } => {
+                positive("duration", duration)?;
+                ensure!(
+                    !logarithmic || (frequency > 0.0 && end_frequency > 0.0),
+                    "Logarithmic chirps need positive start and end frequencies, found {frequency} and {end_frequency}."
+                );
+                Ok(())
+            }
+            Waveform::ToneBurst { cycles } => positive("cycles", cycles),
+        }
+    }
+
+    pub fn evaluate(&self, t: f64, frequency: f64, phase: f64) -> f64 {
+        match *self {
+            Waveform::Sine => (TAU * frequency * t + phase).cos(),
+            Waveform::Square => (TAU * frequency * t + phase).cos().signum(),
+            Waveform::GaussianPulse { duration, delay } => {
+                let duration = duration as f64;
+                let t = t - delay.map_or(4.0 * duration, |x| x as f64);
+                (-(t / duration).powi(2) / 2.0).exp() * (TAU * frequency * t + phase).cos()
+            }
+            Waveform::Ricker { delay } => {
+                let t = t - delay.map_or(1.5 / frequency, |x| x as f64);
+                let a = (PI * frequency * t).powi(2);
+                (1.0 - 2.0 * a) * (-a).exp()
+            }
+            Waveform::Chirp {
+                end_frequency,
+                duration,
+                logarithmic,
+            } => {
+                let (end, duration) = (end_frequency as f64, duration as f64);
+                if t > duration {
+                    return 0.0;
+                }
+
+                let cycles = if logarithmic && end != frequency {
+                    let k = end / frequency;
+                    frequency * duration * (k.powf(t / duration) - 1.0) / k.ln()
+                } else {
+                    frequency * t + (end - frequency) * t * t / (2.0 * duration)
+                };
+                (TAU * cycles + phase).cos()
+            }
+            Waveform::ToneBurst { cycles } => {
+                let duration = cycles as f64 / frequency;
+                if t > duration {
+                    return 0.0;
+                }
+
+                (PI * t / duration).sin().powi(2) * (TAU * frequency * t + phase).cos()
+            }
+        }
+    }
+}
+
+impl WaveformWriter {
+    /// Picks the format from the extension of `path`, either `wav` or `csv`.
+    pub fn new(path: &Path) -> Result<Self> {
+        let extension = path.extension().and_then(|x| x.to_str());
+        if !matches!(extension, Some("wav" | "csv")) {
+            bail!("Waveform references must be written to a .wav or .csv file.");
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        Ok(match extension {
+            Some("wav") => Self::Wav(WavWriter::new(
+                file,
+                WavSpec {
+                    channels: 1,
+                    sample_rate: SAMPLE_RATE,
+                    bits_per_sample: 32,
+                    sample_format: SampleFormat::Float,
+                },
+            )?),
+            _ => {
+                writeln!(file, "tick,time,value")?;
+                Self::Csv(file)
+            }
+        })
+    }
+
+    pub fn write(&mut self, tick: u64, time: f64, value: f32) -> Result<()> {
+        match self {
+            Self::Wav(writer) => writer.write_sample(value)?,
+            Self::Csv(writer) => writeln!(writer, "{tick},{time:e},{value:e}")?,
+        }
+        Ok(())
+    }
+}
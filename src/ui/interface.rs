@@ -1,20 +1,28 @@
-use std::time::Instant;
+use std::{fs, mem, time::Instant};
 
 use bitflags::Flags;
-use egui::{emath::Numeric, Color32, Context, RichText, Slider, Ui, Window};
+use egui::{emath::Numeric, Color32, ComboBox, Context, DragValue, RichText, Slider, Ui, Window};
 use nalgebra::Vector2;
 
 use crate::{
-    misc::RingBuffer,
+    config::RecordInterval,
+    misc::{mesh::MeshOptions, units::Dimension, RingBuffer},
+    recording::Recording,
     renderer::Renderer,
-    simulation::{snapshot::SnapshotType, Simulation, SimulationFlags},
+    simulation::{
+        boundary::Boundary,
+        snapshot::{SnapshotFormat, SnapshotType},
+        solver::{courant_limit, stencil_weights},
+        source::{Position, Source},
+        waveform::Waveform,
+        Simulation, SimulationFlags,
+    },
     GraphicsContext,
 };
 
 use super::sci_dragger::SciDragValue;
 
 pub struct Gui {
-    pub queue_screenshot: bool,
     pub show_about: bool,
     fps: FpsTracker,
 }
@@ -25,7 +33,7 @@ struct FpsTracker {
 }
 
 const COURANT_TIP: &str =
-    "When the Courant number is not in (0, 0.7], the simulation may become unstable.";
+    "When the Courant number is zero or above the limit for the stencil order, the simulation may become unstable.";
 const DESCRIPTION: &str = "Wave Simulator is a GPU accelerated simulator for the discretized wave equation. Created by Connor Slade.";
 
 impl Gui {
@@ -35,6 +43,7 @@ impl Gui {
         gc: &GraphicsContext,
         simulation: &mut Simulation,
         render: &mut Renderer,
+        recording: &Recording,
     ) {
         let now = Instant::now();
         let frame_time = now - self.fps.last_frame;
@@ -57,16 +66,27 @@ impl Gui {
             }
         });
 
+        let mut remove_source = None;
         Window::new("Wave Simulator")
             .default_width(0.0)
             .show(ctx, |ui| {
                 let size = simulation.get_size();
+                let stencil_order = simulation.get_stencil_order();
                 let current_fps = frame_time.as_secs_f64().recip();
                 self.fps.fps_history.push(current_fps);
                 let avg_fps = self.fps.fps_history.avg();
 
                 let (shift, ctrl) = ui.input(|i| (i.modifiers.shift, i.modifiers.ctrl));
 
+                let lock_in = (simulation.lock_in()).map(|x| {
+                    (
+                        x.frequencies(&simulation.parameters),
+                        x.samples(simulation.parameters.tick),
+                    )
+                });
+
+                let sweep = simulation.sweep().map(|x| x.progress());
+
                 let params = &mut simulation.parameters;
                 ui.label(format!("Domain: {}×{}", size.x, size.y));
                 ui.horizontal(|ui| {
@@ -77,6 +97,13 @@ impl Gui {
                     ));
                 });
                 ui.label(format!("Tick: {}", params.tick));
+                if let Some((run, runs)) = sweep {
+                    ui.label(format!("Sweep Run: {}/{runs}", (run + 1).min(runs)));
+                }
+                if let Some(instability) = &simulation.instability {
+                    ui.label(RichText::new(instability.to_string()).color(Color32::RED))
+                        .on_hover_text(COURANT_TIP);
+                }
 
                 ui.separator();
 
@@ -104,28 +131,54 @@ impl Gui {
                         &mut params.flags,
                         SimulationFlags::BILINIER_SAMPLING,
                     );
+
+                    if lock_in.is_some() {
+                        bit_checkbox(
+                            ui,
+                            "Amplitude View",
+                            &mut params.flags,
+                            SimulationFlags::AMPLITUDE_VIEW,
+                        );
+                        bit_checkbox(
+                            ui,
+                            "Phase View",
+                            &mut params.flags,
+                            SimulationFlags::PHASE_VIEW,
+                        );
+                    }
                 });
 
                 ui.collapsing("Simulation", |ui| {
-                    bit_checkbox(
-                        ui,
-                        "Reflective Boundaries",
-                        &mut params.flags,
-                        SimulationFlags::REFLECTIVE_BOUNDARY,
-                    );
+                    let edges = &mut params.boundaries;
+                    boundary_combo(ui, "Left", &mut edges.left, &mut edges.right);
+                    boundary_combo(ui, "Right", &mut edges.right, &mut edges.left);
+                    boundary_combo(ui, "Top", &mut edges.top, &mut edges.bottom);
+                    boundary_combo(ui, "Bottom", &mut edges.bottom, &mut edges.top);
 
                     ui.separator();
 
-                    sci_dragger(ui, "dx (m)", &mut params.dx);
-                    sci_dragger(ui, "dt (s)", &mut params.dt);
-                    sci_dragger(ui, "Wave Speed (m/s)", &mut params.v);
+                    quantity_dragger(ui, "dx", Dimension::Length, &mut params.dx);
+                    quantity_dragger(ui, "dt", Dimension::Time, &mut params.dt);
+                    quantity_dragger(ui, "Wave Speed", Dimension::Speed, &mut params.v);
+                    quantity_dragger(ui, "Loss", Dimension::Frequency, &mut params.loss);
+
+                    ui.checkbox(
+                        &mut simulation.stability.rollback,
+                        "Rollback on Instability",
+                    )
+                    .on_hover_text("Return to the last stable state when the field blows up.");
 
                     let c = params.dt * params.v / params.dx;
+                    let limit = courant_limit(stencil_weights(stencil_order).unwrap());
+                    ui.label(format!("Stencil Order: {stencil_order}"));
                     ui.horizontal(|ui| {
                         ui.label(format!("Courant: {c:.2}"));
-                        if c > 0.7 {
-                            ui.label(RichText::new("CFL not met. (c < 0.7)").color(Color32::RED))
-                                .on_hover_text(COURANT_TIP);
+                        if c > limit {
+                            ui.label(
+                                RichText::new(format!("CFL not met. (c < {limit:.2})"))
+                                    .color(Color32::RED),
+                            )
+                            .on_hover_text(COURANT_TIP);
                         } else if c == 0.0 {
                             ui.label(RichText::new("C is zero.").color(Color32::RED))
                                 .on_hover_text(COURANT_TIP);
@@ -135,7 +188,134 @@ impl Gui {
 
                 ui.collapsing("Oscillator", |ui| {
                     sci_dragger(ui, "Amplitude", &mut params.amplitude);
-                    sci_dragger(ui, "Frequency (Hz)", &mut params.frequency);
+                    quantity_dragger(ui, "Frequency", Dimension::Frequency, &mut params.frequency);
+                });
+
+                ui.collapsing("Sources", |ui| {
+                    for (i, source) in params.sources.iter_mut().enumerate() {
+                        ui.push_id(i, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("Source {}", i + 1));
+                                if ui.button("🗑").on_hover_text("Remove source").clicked() {
+                                    remove_source = Some(i);
+                                }
+                            });
+                            source_ui(ui, source);
+                        });
+                        ui.separator();
+                    }
+
+                    if ui.button("Add Source").clicked() {
+                        params.sources.push(Source {
+                            position: Position::Cells(size.x as f32 / 2.0, size.y as f32 / 2.0),
+                            amplitude: params.amplitude,
+                            frequency: params.frequency,
+                            ..Default::default()
+                        });
+                    }
+                });
+
+                ComboBox::from_label("Snapshot Format")
+                    .selected_text(simulation.snapshot.format.extension())
+                    .show_ui(ui, |ui| {
+                        for format in SnapshotFormat::ALL {
+                            let format_ref = &mut simulation.snapshot.format;
+                            ui.selectable_value(format_ref, format, format.extension());
+                        }
+                    });
+
+                ui.collapsing("Mesh Export", |ui| {
+                    mesh_ui(ui, &mut simulation.snapshot.mesh);
+                    ui.horizontal(|ui| {
+                        for format in [SnapshotFormat::Stl, SnapshotFormat::Ply] {
+                            let label = format!("Save {}", format.extension().to_uppercase());
+                            if ui.button(label).clicked() {
+                                simulation
+                                    .snapshot
+                                    .push_format(SnapshotType::Energy, format);
+                            }
+                        }
+                    });
+                });
+
+                if !simulation.detectors().is_empty() {
+                    ui.collapsing("Detectors", |ui| {
+                        for recorder in simulation.detectors() {
+                            let path = recorder.detector.output.display();
+                            ui.label(format!("{path}: {} samples", recorder.samples.len()));
+                        }
+
+                        if ui.button("Save Detectors").clicked() {
+                            if let Err(e) = simulation.save_detectors() {
+                                eprintln!("Failed to save detectors: {:?}", e);
+                            }
+                        }
+                    });
+                }
+
+                if let Some((frequencies, samples)) = lock_in {
+                    ui.collapsing("Lock-in", |ui| {
+                        ui.label(format!("Samples: {samples}"));
+                        ComboBox::from_label("Frequency")
+                            .selected_text(format!(
+                                "{:.3e} Hz",
+                                frequencies[render.lock_in_frequency.min(frequencies.len() - 1)]
+                            ))
+                            .show_ui(ui, |ui| {
+                                for (i, frequency) in frequencies.iter().enumerate() {
+                                    let text = format!("{frequency:.3e} Hz");
+                                    ui.selectable_value(&mut render.lock_in_frequency, i, text);
+                                }
+                            });
+
+                        if ui.button("Save Lock-in").clicked() {
+                            simulation.save_lock_in();
+                        }
+                    });
+                }
+
+                if let Some(flux) = simulation.flux() {
+                    ui.collapsing("Flux", |ui| {
+                        for (name, energy) in flux.monitors().zip(&flux.energy) {
+                            ui.label(format!("{name}: {energy:.3e}"));
+                        }
+
+                        if let Some(report) = flux.report() {
+                            ui.label(report.to_string());
+                        }
+                    });
+                }
+
+                ui.collapsing("Recording", |ui| {
+                    let record = &mut simulation.record;
+                    ui.checkbox(&mut record.enabled, "Record");
+                    interval_ui(ui, &mut record.interval);
+                    ui.label(format!("Frames: {}", recording.frames()));
+                });
+
+                ui.collapsing("Checkpoints", |ui| {
+                    if ui.button("Save Checkpoint").clicked() {
+                        simulation.checkpoints.push(None);
+                    }
+
+                    let mut paths = fs::read_dir("checkpoints")
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|entry| Some(entry.ok()?.path()))
+                        .filter(|path| path.extension().is_some_and(|ext| ext == "ckpt"))
+                        .collect::<Vec<_>>();
+                    paths.sort();
+
+                    for path in paths {
+                        ui.horizontal(|ui| {
+                            if ui.button("Load").clicked() {
+                                if let Err(e) = simulation.load_checkpoint(&path) {
+                                    eprintln!("Failed to load checkpoint: {:?}", e);
+                                }
+                            }
+                            ui.label(path.file_name().unwrap().to_string_lossy());
+                        });
+                    }
                 });
 
                 ui.separator();
@@ -158,9 +338,9 @@ impl Gui {
                         )
                         .clicked()
                     {
-                        simulation.reset_average_energy(&gc.queue);
+                        simulation.reset_average_energy();
                         if !shift {
-                            simulation.reset_states(&gc.queue);
+                            simulation.reset_states();
                         }
                     }
 
@@ -174,14 +354,28 @@ impl Gui {
                         } else if ctrl {
                             simulation.snapshot.push(SnapshotType::Energy, None);
                         } else {
-                            self.queue_screenshot = true;
+                            simulation.queue_screenshot = true;
                         }
                     }
 
+                    let recording = &mut simulation.record.enabled;
+                    *recording ^= ui
+                        .button(if *recording { "⏹" } else { "⏺" })
+                        .on_hover_text(if *recording {
+                            "Stop recording"
+                        } else {
+                            "Start recording"
+                        })
+                        .clicked();
+
                     self.show_about ^= ui.button("ℹ").on_hover_text("About").clicked();
                 });
             });
 
+        if let Some(i) = remove_source {
+            simulation.remove_source(i);
+        }
+
         if self.show_about {
             Window::new("About").show(ctx, |ui| {
                 ui.label(DESCRIPTION);
@@ -205,16 +399,186 @@ fn sci_dragger<Num: Numeric>(ui: &mut Ui, label: &str, value: &mut Num) {
     });
 }
 
+/// A value in SI units, shown with its unit.
+fn quantity_dragger(ui: &mut Ui, label: &str, dimension: Dimension, value: &mut f32) {
+    ui.horizontal(|ui| {
+        SciDragValue::new(value).dimension(dimension).show(ui);
+        ui.label(label);
+    });
+}
+
 fn bit_checkbox<Value: Flags + Copy>(ui: &mut Ui, label: &str, value: &mut Value, flag: Value) {
     let mut bool_value = value.contains(flag);
     ui.checkbox(&mut bool_value, label);
     value.set(flag, bool_value);
 }
 
+fn source_ui(ui: &mut Ui, source: &mut Source) {
+    ui.horizontal(|ui| match &mut source.position {
+        Position::Cells(x, y) => {
+            ui.add(DragValue::new(x));
+            ui.add(DragValue::new(y));
+            ui.label("Position (cells)");
+        }
+        Position::Meters(x, y) => {
+            SciDragValue::new(x).dimension(Dimension::Length).show(ui);
+            SciDragValue::new(y).dimension(Dimension::Length).show(ui);
+            ui.label("Position");
+        }
+    });
+
+    sci_dragger(ui, "Amplitude", &mut source.amplitude);
+    quantity_dragger(ui, "Frequency", Dimension::Frequency, &mut source.frequency);
+    ui.horizontal(|ui| {
+        ui.add(DragValue::new(&mut source.phase).speed(0.01));
+        ui.label("Phase (rad)");
+    });
+    waveform_ui(ui, &mut source.waveform, source.frequency);
+    ui.horizontal(|ui| {
+        ui.add(
+            DragValue::new(&mut source.width)
+                .speed(0.1)
+                .clamp_range(0.1..=f32::MAX),
+        );
+        ui.label("Width (cells)");
+    });
+
+    ui.horizontal(|ui| {
+        ui.add(DragValue::new(&mut source.start));
+        ui.label("Start Tick");
+    });
+    ui.horizontal(|ui| {
+        let mut stops = source.stop.is_some();
+        ui.checkbox(&mut stops, "Stop Tick");
+        source.stop = stops.then(|| source.stop.unwrap_or(source.start));
+        if let Some(stop) = &mut source.stop {
+            ui.add(DragValue::new(stop));
+        }
+    });
+}
+
+fn mesh_ui(ui: &mut Ui, mesh: &mut MeshOptions) {
+    sci_dragger(ui, "Height Scale", &mut mesh.scale);
+    ui.horizontal(|ui| {
+        ui.add(
+            DragValue::new(&mut mesh.base)
+                .speed(0.1)
+                .clamp_range(0.0..=f32::MAX),
+        );
+        ui.label("Base (cells)");
+    });
+    ui.horizontal(|ui| {
+        ui.add(DragValue::new(&mut mesh.decimation).clamp_range(1..=u32::MAX));
+        ui.label("Decimation");
+    });
+    ui.horizontal(|ui| {
+        let mut log = mesh.log.is_some();
+        ui.checkbox(&mut log, "Log Scale");
+        mesh.log = log.then(|| mesh.log.unwrap_or(3.0));
+        if let Some(decades) = &mut mesh.log {
+            ui.add(
+                DragValue::new(decades)
+                    .speed(0.1)
+                    .clamp_range(0.1..=f32::MAX)
+                    .suffix(" decades"),
+            );
+        }
+    });
+}
+
+fn interval_ui(ui: &mut Ui, interval: &mut RecordInterval) {
+    ui.horizontal(|ui| {
+        let (RecordInterval::Ticks(count) | RecordInterval::Frames(count)) = interval;
+        let mut count = *count;
+        ui.add(DragValue::new(&mut count).clamp_range(1..=u64::MAX));
+        ComboBox::from_id_source("Interval")
+            .selected_text(match interval {
+                RecordInterval::Ticks(_) => "Ticks",
+                RecordInterval::Frames(_) => "Frames",
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(interval, RecordInterval::Ticks(count), "Ticks");
+                ui.selectable_value(interval, RecordInterval::Frames(count), "Frames");
+            });
+        ui.label("Interval");
+
+        *interval = match interval {
+            RecordInterval::Ticks(_) => RecordInterval::Ticks(count),
+            RecordInterval::Frames(_) => RecordInterval::Frames(count),
+        };
+    });
+}
+
+fn waveform_ui(ui: &mut Ui, waveform: &mut Waveform, frequency: f32) {
+    ComboBox::from_label("Waveform")
+        .selected_text(waveform.name())
+        .show_ui(ui, |ui| {
+            for preset in Waveform::presets(frequency) {
+                let selected = mem::discriminant(&preset) == mem::discriminant(waveform);
+                if ui.selectable_label(selected, preset.name()).clicked() && !selected {
+                    *waveform = preset;
+                }
+            }
+        });
+
+    match waveform {
+        Waveform::Sine | Waveform::Square => {}
+        Waveform::GaussianPulse { duration, delay } => {
+            quantity_dragger(ui, "Duration", Dimension::Time, duration);
+            delay_ui(ui, delay, 4.0 * *duration);
+        }
+        Waveform::Ricker { delay } => delay_ui(ui, delay, 1.5 / frequency),
+        Waveform::Chirp {
+            end_frequency,
+            duration,
+            logarithmic,
+        } => {
+            quantity_dragger(ui, "End Frequency", Dimension::Frequency, end_frequency);
+            quantity_dragger(ui, "Duration", Dimension::Time, duration);
+            ui.checkbox(logarithmic, "Logarithmic");
+        }
+        Waveform::ToneBurst { cycles } => {
+            ui.horizontal(|ui| {
+                ui.add(
+                    DragValue::new(cycles)
+                        .speed(0.1)
+                        .clamp_range(0.5..=f32::MAX),
+                );
+                ui.label("Cycles");
+            });
+        }
+    }
+}
+
+/// Unchecking the delay goes back to the waveform's default, which is also the starting value when checked.
+fn delay_ui(ui: &mut Ui, delay: &mut Option<f32>, default: f32) {
+    let mut custom = delay.is_some();
+    ui.checkbox(&mut custom, "Custom Delay");
+    *delay = custom.then(|| delay.unwrap_or(default));
+    if let Some(delay) = delay {
+        quantity_dragger(ui, "Delay", Dimension::Time, delay);
+    }
+}
+
+/// Periodic edges are kept in pairs by changing the opposite edge along with this one.
+fn boundary_combo(ui: &mut Ui, label: &str, value: &mut Boundary, opposite: &mut Boundary) {
+    let previous = *value;
+    ComboBox::from_label(label)
+        .selected_text(value.name())
+        .show_ui(ui, |ui| {
+            for boundary in Boundary::ALL {
+                ui.selectable_value(value, boundary, boundary.name());
+            }
+        });
+
+    if *value != previous && (*value == Boundary::Periodic || previous == Boundary::Periodic) {
+        *opposite = *value;
+    }
+}
+
 impl Default for Gui {
     fn default() -> Self {
         Self {
-            queue_screenshot: false,
             show_about: false,
             fps: FpsTracker {
                 fps_history: RingBuffer::new(),
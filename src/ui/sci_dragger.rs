@@ -1,8 +1,14 @@
 use egui::{emath::Numeric, DragValue, Ui};
 
+use crate::misc::units::{self, Dimension};
+
+const EXPONENTS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+
 pub struct SciDragValue<'a, Num: Numeric> {
     value: &'a mut Num,
     signifacant_figures: u8,
+    /// Shows the value with its SI unit, and lets other units of the same dimension be typed in.
+    dimension: Option<Dimension>,
 }
 
 impl<'a, Num: Numeric> SciDragValue<'a, Num> {
@@ -10,6 +16,14 @@ impl<'a, Num: Numeric> SciDragValue<'a, Num> {
         Self {
             value,
             signifacant_figures: 3,
+            dimension: None,
+        }
+    }
+
+    pub fn dimension(self, dimension: Dimension) -> Self {
+        Self {
+            dimension: Some(dimension),
+            ..self
         }
     }
 
@@ -21,20 +35,19 @@ impl<'a, Num: Numeric> SciDragValue<'a, Num> {
         ui.add(
             DragValue::new(self.value)
                 .speed(speed)
-                .custom_parser(|_str| {
-                    // todo
-                    None
-                })
+                .custom_parser(|text| parse(text, self.dimension))
                 .custom_formatter(|val, _| {
                     let exponent = (val.abs().log10().floor()) as i32;
                     let significand = val / f64::powi(10.0, exponent);
                     let decimal_places = self.signifacant_figures as usize - 1;
 
+                    let unit = self.dimension.map(|x| format!(" {}", x.symbol()));
+                    let unit = unit.unwrap_or_default();
                     if exponent == 0 {
-                        format!("{significand:.00$}", decimal_places)
+                        format!("{significand:.00$}{unit}", decimal_places)
                     } else {
                         format!(
-                            "{significand:.01$}×10{}",
+                            "{significand:.01$}×10{}{unit}",
                             superscript_number(exponent),
                             decimal_places
                         )
@@ -44,9 +57,34 @@ impl<'a, Num: Numeric> SciDragValue<'a, Num> {
     }
 }
 
-fn superscript_number(mut num: i32) -> String {
-    const EXPONENTS: [char; 10] = ['⁰', '¹', '²', '³', '⁴', '⁵', '⁶', '⁷', '⁸', '⁹'];
+/// Parses a plain number or one shown by the formatter, or a value with any unit of the dimension like `5 um`.
+fn parse(text: &str, dimension: Option<Dimension>) -> Option<f64> {
+    let mut text = text.trim();
+    if let Some(dimension) = dimension {
+        if let Ok(value) = units::parse(text, dimension) {
+            return Some(value);
+        }
+        text = text.strip_suffix(dimension.symbol()).unwrap_or(text).trim();
+    }
+
+    match text.split_once("×10") {
+        Some((significand, exponent)) => {
+            let exponent = (exponent.chars())
+                .map(|x| match x {
+                    '¯' => Some('-'),
+                    _ => Some(char::from_digit(
+                        EXPONENTS.iter().position(|&y| y == x)? as u32,
+                        10,
+                    )?),
+                })
+                .collect::<Option<String>>()?;
+            Some(significand.trim().parse::<f64>().ok()? * 10_f64.powi(exponent.parse().ok()?))
+        }
+        None => text.parse().ok(),
+    }
+}
 
+fn superscript_number(mut num: i32) -> String {
     let neg = num < 0;
     num = num.abs();
 